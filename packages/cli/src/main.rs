@@ -1,25 +1,44 @@
 use anyhow::Result;
 use clap::Parser;
-use libcheese::common::{parse_other_token_name, CHEESE_MINT};
-use libcheese::jupiter::fetch_jupiter_prices;
-use libcheese::meteora::{fetch_meteora_cheese_pools, MeteoraPool};
-use libcheese::raydium::{fetch_raydium_cheese_pools, fetch_raydium_mint_ids};
-use libcheese::solana::TradeExecutor;
-use reqwest::Client;
-use solana_sdk::signature::Keypair;
+use libcheese::arbitrage::{find_arbitrage_cycles, ArbitrageCycle, CycleSearch};
+use libcheese::common::{BaseToken, WSOL_MINT};
+use libcheese::http::{HttpClient, HttpSettings};
+use libcheese::jupiter::{
+    fetch_jupiter_prices, get_jupiter_quote, Confidence, JupiterQuoteParams, TokenPrice,
+};
+use libcheese::meteora::dlmm::DlmmVenue;
+use libcheese::meteora::MeteoraVenue;
+use libcheese::orca::OrcaVenue;
+use libcheese::pricing::{reference_price, DEFAULT_MAX_DEVIATION, USDC_MINT};
+use libcheese::pumpfun::{fetch_bonding_curves, is_pump_mint, PumpFunVenue};
+use libcheese::raydium::{fetch_raydium_mint_ids, RaydiumVenue};
+use libcheese::solana::{
+    JupiterRouting, PriorityFeeSettings, RpcSettings, TradeExecutor, LAMPORTS_PER_SIGNATURE,
+    MAX_COMPUTE_UNITS,
+};
+use libcheese::stream::PoolStream;
+use libcheese::venue::{Pool, PoolSide, Venue};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signer::keypair::read_keypair_file;
 use std::collections::{HashMap, HashSet};
-use std::env;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::time;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-const WALLET_CHEESE_BALANCE: f64 = 5_000_000.0;
-const WALLET_SOL_BALANCE: f64 = 1.0;
-const SOL_PER_TX: f64 = 0.000005; // Approximate SOL cost per transaction
-const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+const LEG_COMPUTE_UNITS: u32 = 150_000; // One swap, tick or bin crossings included
+const PROFIT_CHECK_COMPUTE_UNITS: u32 = 10_000; // The closing balance check
 const LOOP_INTERVAL: Duration = Duration::from_secs(30);
+/// How long --stream follows one set of pools before listing them again
+const STREAM_WINDOW: Duration = Duration::from_secs(300);
 const MIN_PROFIT_USD: f64 = 1.0; // Minimum profit in USD to execute trade
+const MAX_PRICE_AGE_SECS: u64 = 300; // Older Jupiter quotes count as stale
+/// Venues searched for pools between the base token's other tokens
+const LINK_VENUES: [&str; 2] = ["Meteora", "Raydium"];
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -27,6 +46,18 @@ struct Args {
     /// Run mode (hot/cold)
     mode: String,
 
+    /// Log format on stderr (human/json)
+    #[arg(long, default_value = "human")]
+    log_format: String,
+
+    /// Log more, -v for debug and -vv for trace. RUST_LOG overrides it.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log less, -q for warnings only and -qq for errors only
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
     /// RPC URL (optional, defaults to mainnet)
     #[arg(long)]
     rpc_url: Option<String>,
@@ -34,6 +65,96 @@ struct Args {
     /// Keypair file path (required for hot mode)
     #[arg(long)]
     keypair: Option<String>,
+
+    /// RPC commitment level (processed/confirmed/finalized)
+    #[arg(long, default_value = "confirmed")]
+    commitment: String,
+
+    /// Timeout for each RPC request, in seconds
+    #[arg(long, default_value_t = 30)]
+    rpc_timeout: u64,
+
+    /// How long to wait for a transaction to confirm, in seconds
+    #[arg(long, default_value_t = 60)]
+    confirm_timeout: u64,
+
+    /// Percentile of recent prioritization fees to pay
+    #[arg(long, default_value_t = 75)]
+    priority_fee_percentile: u8,
+
+    /// Maximum priority fee, in micro-lamports per compute unit
+    #[arg(long, default_value_t = 1_000_000)]
+    max_priority_fee: u64,
+
+    /// Route swaps through Jupiter (off/fallback/all). "fallback" only
+    /// routes legs on pools we can't trade directly.
+    #[arg(long, default_value = "off")]
+    jupiter_routing: String,
+
+    /// Only accept single-pool Jupiter routes
+    #[arg(long)]
+    jupiter_direct_only: bool,
+
+    /// Comma-separated Jupiter DEX labels to restrict routes to
+    #[arg(long)]
+    jupiter_dexes: Option<String>,
+
+    /// Most swaps in one arbitrage cycle
+    #[arg(long, default_value_t = 3)]
+    max_hops: usize,
+
+    /// Most USD to put into one arbitrage cycle
+    #[arg(long)]
+    max_trade_usd: Option<f64>,
+
+    /// Follow pools over a websocket and search again whenever one changes
+    #[arg(long)]
+    stream: bool,
+
+    /// Websocket URL for --stream (optional, defaults to the RPC URL's)
+    #[arg(long)]
+    ws_url: Option<String>,
+
+    /// Mint of the token to scan pools of (optional, defaults to CHEESE)
+    #[arg(long)]
+    base_mint: Option<String>,
+
+    /// Symbol to show for the base token
+    #[arg(long)]
+    base_symbol: Option<String>,
+
+    /// Emoji to show for the base token
+    #[arg(long)]
+    base_emoji: Option<String>,
+
+    /// Pool to price the base token from instead of its deepest stablecoin
+    /// and SOL pools (optional)
+    #[arg(long)]
+    quote_pool: Option<String>,
+
+    /// Timeout for each pool, price and swap API request, in seconds
+    #[arg(long, default_value_t = 10)]
+    http_timeout: u64,
+
+    /// Retries for an API request that fails with a 429, 5xx or timeout
+    #[arg(long, default_value_t = 3)]
+    http_retries: u32,
+
+    /// API requests per second allowed to each host
+    #[arg(long, default_value_t = 5.0)]
+    http_rate_limit: f64,
+
+    /// Per-host rate limit as HOST=REQUESTS_PER_SECOND (repeatable)
+    #[arg(long)]
+    http_host_rate: Vec<String>,
+
+    /// Failed API requests in a row before a host is left alone
+    #[arg(long, default_value_t = 5)]
+    http_breaker_threshold: u32,
+
+    /// How long to leave a failing host alone, in seconds
+    #[arg(long, default_value_t = 30)]
+    http_breaker_cooldown: u64,
 }
 
 /// A row describing one pool
@@ -42,75 +163,60 @@ struct DisplayPool {
     source: String,
     other_mint: String,
     other_symbol: String,
-    cheese_qty: String,
+    base_qty: String,
     other_qty: String,
     pool_type: String,
     tvl: String,
     volume_usd: String,
     fee: String,
     pool_address: String,
-    cheese_price: String, // e.g. "$0.000057"
 }
 
 #[derive(Debug, Default)]
 struct CheeseAggregates {
     total_liquidity_usd: f64,
     number_of_pools: u64,
-    total_cheese_qty: f64,
+    total_base_qty: f64,
     total_volume_24h: f64,
 }
 
-#[derive(Debug, Clone)]
-struct PoolEdge {
-    pool_address: String,
-    source: String, // "Meteora" or "Raydium"
-    token_a: String,
-    token_b: String,
-    fee: f64,
-    tvl: f64,
-    reserves_a: f64,
-    reserves_b: f64,
-}
-
-#[derive(Debug)]
-struct ArbitrageCycle {
-    steps: Vec<TradeStep>,
-    initial_cheese: f64,
-    final_cheese: f64,
-    total_fees_sol: f64,
-    pool_fees_paid: Vec<f64>,
-    initial_usdc_value: f64, // Value of initial CHEESE in USDC
-    final_usdc_value: f64,   // Value of final CHEESE in USDC
-    fees_usdc_value: f64,    // Value of all fees in USDC
-}
-
-#[derive(Debug, Clone)]
-struct TradeStep {
-    pool_address: String,
-    source: String,
-    sell_token: String,
-    buy_token: String,
-    amount_in: f64,
-    expected_out: f64,
-    fee_percent: f64,
-}
-
-#[derive(Debug)]
-struct ArbitrageOpportunity {
-    pool_address: String,
-    symbol: String,
-    cheese_qty: f64,
-    other_qty: f64,
-    implied_price: f64,
-    usdc_price: f64,
-    max_trade_size: f64,
-    net_profit_usd: f64,
-    is_sell: bool,
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    init_logging(&args)?;
+
+    let rpc_url = args
+        .rpc_url
+        .clone()
+        .unwrap_or_else(|| "https://api.mainnet-beta.solana.com".to_string());
+    let commitment = CommitmentConfig::from_str(&args.commitment)
+        .map_err(|e| anyhow::anyhow!("Invalid --commitment: {}", e))?;
+
+    // Read-only client for pools that are discovered on chain
+    let rpc_client = RpcClient::new_with_timeout_and_commitment(
+        rpc_url.clone(),
+        Duration::from_secs(args.rpc_timeout),
+        commitment,
+    );
+
+    let mut host_rates = HashMap::new();
+    for entry in &args.http_host_rate {
+        let (host, rate) = entry
+            .split_once('=')
+            .and_then(|(host, rate)| Some((host.to_string(), rate.parse::<f64>().ok()?)))
+            .ok_or_else(|| anyhow::anyhow!("Invalid --http-host-rate: {}", entry))?;
+        host_rates.insert(host, rate);
+    }
+    // Shared by every API call so rate limits and breakers hold across iterations
+    let client = HttpClient::new(HttpSettings {
+        timeout: Duration::from_secs(args.http_timeout),
+        max_retries: args.http_retries,
+        requests_per_second: args.http_rate_limit,
+        host_rates,
+        breaker_threshold: args.http_breaker_threshold,
+        breaker_cooldown: Duration::from_secs(args.http_breaker_cooldown),
+        ..HttpSettings::default()
+    })?;
 
     // If hot mode, validate keypair
     let executor = if args.mode == "hot" {
@@ -119,24 +225,80 @@ async fn main() -> Result<()> {
             std::process::exit(1);
         }
 
-        let keypair_path = args.keypair.unwrap();
-        let keypair = read_keypair_file(&keypair_path)
+        let keypair_path = args.keypair.as_deref().unwrap();
+        let keypair = read_keypair_file(keypair_path)
             .map_err(|e| anyhow::anyhow!("Failed to read keypair file: {}", e))?;
 
-        let rpc_url = args
-            .rpc_url
-            .unwrap_or_else(|| "https://api.mainnet-beta.solana.com".to_string());
+        let settings = RpcSettings {
+            commitment,
+            request_timeout: Duration::from_secs(args.rpc_timeout),
+            confirm_timeout: Duration::from_secs(args.confirm_timeout),
+        };
+
+        let priority_fees = PriorityFeeSettings {
+            percentile: args.priority_fee_percentile,
+            max_micro_lamports: args.max_priority_fee,
+            ..PriorityFeeSettings::default()
+        };
 
-        Some(TradeExecutor::new(&rpc_url, keypair))
+        let mut executor = TradeExecutor::with_settings(&rpc_url, keypair, settings)?
+            .with_priority_fees(priority_fees)
+            .with_http_client(client.clone());
+        let all_legs = match args.jupiter_routing.as_str() {
+            "off" => None,
+            "fallback" => Some(false),
+            "all" => Some(true),
+            other => return Err(anyhow::anyhow!("Invalid --jupiter-routing: {}", other)),
+        };
+        if let Some(all_legs) = all_legs {
+            executor = executor.with_jupiter_routing(JupiterRouting {
+                all_legs,
+                only_direct_routes: args.jupiter_direct_only,
+                dexes: args
+                    .jupiter_dexes
+                    .as_ref()
+                    .map(|dexes| dexes.split(',').map(|dex| dex.trim().to_string()).collect()),
+            });
+        }
+        Some(executor)
     } else {
         None
     };
 
+    let search = CycleSearch {
+        max_hops: args.max_hops,
+        max_trade_usd: args.max_trade_usd,
+        ..CycleSearch::default()
+    };
+
+    let base = base_token(&args)?;
+
+    let ws_url = args.stream.then(|| {
+        args.ws_url.clone().unwrap_or_else(|| {
+            rpc_url
+                .replacen("https://", "wss://", 1)
+                .replacen("http://", "ws://", 1)
+        })
+    });
+
     loop {
-        if let Err(e) = run_iteration(&executor).await {
-            eprintln!("Error in iteration: {}", e);
+        if let Err(e) = run_iteration(
+            &client,
+            &rpc_client,
+            &executor,
+            &base,
+            &search,
+            ws_url.as_deref(),
+        )
+        .await
+        {
+            error!(error = %e, "Iteration failed");
         }
 
+        // Streaming only returns to list pools again, so there's nothing to wait for
+        if args.stream {
+            continue;
+        }
         if args.mode != "hot" {
             break;
         }
@@ -147,141 +309,206 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
-    let client = Client::new();
-
-    // 1) fetch from Meteora
-    let meteora_pools = fetch_meteora_cheese_pools(&client).await?;
+async fn run_iteration(
+    client: &HttpClient,
+    rpc_client: &RpcClient,
+    executor: &Option<TradeExecutor>,
+    base: &BaseToken,
+    search: &CycleSearch,
+    ws_url: Option<&str>,
+) -> Result<()> {
+    // 1) find the base token's pools on every venue. Not every RPC node serves the
+    //    program account scan DLMM discovery needs, so a venue that fails
+    //    is skipped rather than failing the run.
+    let venues: Vec<Box<dyn Venue + '_>> = vec![
+        Box::new(MeteoraVenue::new(client, rpc_client)),
+        Box::new(DlmmVenue::new(rpc_client)),
+        Box::new(RaydiumVenue::new(client, rpc_client)),
+        Box::new(OrcaVenue::new(rpc_client)),
+        Box::new(PumpFunVenue::new(rpc_client)),
+    ];
+    let mut pools = Vec::new();
+    for venue in &venues {
+        match venue.discover_pools(&base.mint).await {
+            Ok(found) => {
+                info!(
+                    venue = venue.name(),
+                    pools = found.len(),
+                    "Found {} pools",
+                    base.symbol
+                );
+                pools.extend(found);
+            }
+            Err(e) => warn!(venue = venue.name(), error = %e, "Skipping venue"),
+        }
+    }
 
-    // 2) fetch from Raydium
-    let raydium_pools = fetch_raydium_cheese_pools(&client).await?;
+    // 2) pools joining the base token's other tokens to each other, so
+    //    cycles can go more than one hop from it. Only venues with a listing API are
+    //    asked, one token at a time; SOL and USDC are reached from the
+    //    other side since they have far too many pools to list.
+    if search.max_hops > 2 {
+        let tokens: HashSet<Pubkey> = pools.iter().flat_map(|pool| pool.mints).collect();
+        let hubs = [base.mint, WSOL_MINT, USDC_MINT];
+        let mut links = Vec::new();
+        for token in tokens.iter().filter(|token| !hubs.contains(token)) {
+            for venue in venues.iter().filter(|v| LINK_VENUES.contains(&v.name())) {
+                match venue.discover_pools(token).await {
+                    Ok(found) => links.extend(found.into_iter().filter(|pool| {
+                        !pool.mints.contains(&base.mint)
+                            && pool.mints.iter().all(|mint| tokens.contains(mint))
+                    })),
+                    Err(e) => warn!(
+                        venue = venue.name(),
+                        mint = %token,
+                        error = %e,
+                        "Skipping venue for linking pools"
+                    ),
+                }
+            }
+        }
+        // A pool between two of the tokens is found from both sides
+        let mut seen = HashSet::new();
+        links.retain(|pool| seen.insert(pool.address));
+        info!(
+            pools = links.len(),
+            "Found pools linking {}'s other tokens", base.symbol
+        );
+        pools.extend(links);
+    }
 
     // gather unique mints
     let mut set = HashSet::new();
-    set.insert(CHEESE_MINT.to_string());
-    for pool in &meteora_pools {
-        for m in &pool.pool_token_mints {
-            set.insert(m.clone());
+    set.insert(base.mint.to_string());
+    set.insert(WSOL_MINT.to_string()); // to price transaction fees
+    for pool in &pools {
+        for mint in &pool.mints {
+            set.insert(mint.to_string());
         }
     }
     let mut all_mints_vec: Vec<String> = set.into_iter().collect();
     all_mints_vec.sort();
 
     // fetch minted data from Raydium
-    let minted_data = fetch_raydium_mint_ids(&client, &all_mints_vec).await?;
+    let minted_data = fetch_raydium_mint_ids(client, &all_mints_vec).await?;
     let mut mint_to_symbol = HashMap::new();
-    for maybe_item in &minted_data {
-        if let Some(item) = maybe_item {
-            mint_to_symbol.insert(item.address.clone(), item.symbol.clone());
-        }
+    for item in minted_data.iter().flatten() {
+        mint_to_symbol.insert(item.address.clone(), item.symbol.clone());
     }
 
     // fetch Jupiter prices
-    let jup_prices = fetch_jupiter_prices(&client, &all_mints_vec).await?;
+    let jup_prices = fetch_jupiter_prices(client, &all_mints_vec).await?;
+    let mut usd_prices: HashMap<String, f64> = jup_prices
+        .iter()
+        .map(|(mint, price)| (mint.clone(), price.price))
+        .collect();
 
-    // Find the USDC/CHEESE price from the specific pool
-    let usdc_pool = meteora_pools
+    // Tokens still on their pump.fun bonding curve may have no Jupiter price
+    // yet, so value them at the curve's SOL price
+    let unpriced: Vec<Pubkey> = all_mints_vec
         .iter()
-        .find(|p| p.pool_address == "2rkTh46zo8wUvPJvACPTJ16RNUHEM9EZ1nLYkUxZEHkw")
-        .unwrap();
-    let (cheese_ix, usdc_ix) = if usdc_pool.pool_token_mints[0] == CHEESE_MINT {
-        (0, 1)
-    } else {
-        (1, 0)
-    };
-    let cheese_amt: f64 = usdc_pool.pool_token_amounts[cheese_ix]
-        .parse()
-        .unwrap_or(0.0);
-    let usdc_amt: f64 = usdc_pool.pool_token_amounts[usdc_ix].parse().unwrap_or(0.0);
-    let cheese_usdc_price = if cheese_amt > 0.0 {
-        usdc_amt / cheese_amt
-    } else {
-        0.0
-    };
+        .filter(|mint| is_pump_mint(mint) && !usd_prices.contains_key(*mint))
+        .filter_map(|mint| Pubkey::from_str(mint).ok())
+        .collect();
+    if !unpriced.is_empty() {
+        match fetch_bonding_curves(rpc_client, &unpriced).await {
+            Ok(curves) => {
+                let sol_price = usd_prices
+                    .get(&WSOL_MINT.to_string())
+                    .copied()
+                    .unwrap_or(0.0);
+                for (mint, curve) in curves.iter().filter(|(_, curve)| !curve.complete) {
+                    info!(%mint, "Still on its pump.fun bonding curve");
+                    usd_prices.insert(mint.to_string(), curve.ui_price() * sol_price);
+                }
+            }
+            Err(e) => warn!(error = %e, "Skipping pump.fun bonding curves"),
+        }
+    }
+
+    // The base token's USD price: the pinned pool when it's still listed,
+    // otherwise every stablecoin and SOL pool that agrees with the rest
+    let pinned = base.quote_pool.and_then(|address| {
+        let pool = pools.iter().find(|pool| pool.address == address);
+        if pool.is_none() {
+            warn!(pool = %address, "Quote pool not found, pricing from every pool");
+        }
+        pool
+    });
+    let sol_usd = usd_prices.get(&WSOL_MINT.to_string()).copied();
+    let reference = reference_price(
+        pinned.map_or(&pools[..], std::slice::from_ref),
+        &base.mint,
+        sol_usd,
+        DEFAULT_MAX_DEVIATION,
+    )
+    .ok_or_else(|| anyhow::anyhow!("No USDC, USDT or SOL pool to price {} with", base.symbol))?;
+    println!(
+        "\n{} reference price ${:.6} from {} pool(s):",
+        base.symbol,
+        reference.price_usd,
+        reference.sources.len()
+    );
+    for source in &reference.sources {
+        println!(
+            "  {:12} {} ${:.6} ({:.1}%)",
+            source.source,
+            source.pool,
+            source.price_usd,
+            reference.weight(source) * 100.0
+        );
+    }
+    for source in &reference.rejected {
+        println!(
+            "  {:12} {} ${:.6} left out as an outlier",
+            source.source, source.pool, source.price_usd
+        );
+    }
+    let base_usd_price = reference.price_usd;
 
     // Print table header
-    println!("\n| Source   | Other Mint                                   | Other Name | Pool Type  | CHEESE Qty | Other Qty | Liquidity($) | Volume($) |   Fee | CHEESE Price | Pool Address                                 |");
-    println!("|----------|----------------------------------------------|------------|------------|------------|-----------|--------------|-----------|-------|--------------|----------------------------------------------|");
+    println!(
+        "\n| Source       | Other Mint                                   | Other Name | Pool Type  | {:10} | Other Qty | Liquidity($) | Volume($) |   Fee | {:12} | Pool Address                                 |",
+        format!("{} Qty", base.symbol),
+        format!("{} Price", base.symbol)
+    );
+    println!("|--------------|----------------------------------------------|------------|------------|------------|-----------|--------------|-----------|-------|--------------|----------------------------------------------|");
 
     // Prepare display pools
     let mut display_pools = Vec::new();
     let mut aggregates = CheeseAggregates::default();
 
-    // Add Meteora pools
-    for pool in &meteora_pools {
-        let (cheese_ix, other_ix) = if pool.pool_token_mints[0] == CHEESE_MINT {
-            (0, 1)
-        } else {
-            (1, 0)
+    for pool in &pools {
+        let Some(side) = pool.side(&base.mint) else {
+            continue;
         };
+        let other_mint = side.other_mint.to_string();
 
-        let other_mint = pool.pool_token_mints[other_ix].clone();
-        let other_symbol = mint_to_symbol
-            .get(&other_mint)
-            .cloned()
-            .unwrap_or_else(|| parse_other_token_name(&pool.pool_name));
-
-        let cheese_qty: f64 = pool.pool_token_amounts[cheese_ix].parse().unwrap_or(0.0);
-        let other_qty: f64 = pool.pool_token_amounts[other_ix].parse().unwrap_or(0.0);
-
-        // Update aggregates
-        aggregates.number_of_pools += 1;
-        aggregates.total_cheese_qty += cheese_qty;
-        aggregates.total_liquidity_usd += pool.pool_tvl;
-        aggregates.total_volume_24h += pool.daily_volume;
-
-        display_pools.push(DisplayPool {
-            source: "Meteora".to_string(),
-            other_mint,
-            other_symbol,
-            cheese_qty: format!("{:.2}", cheese_qty),
-            other_qty: format!("{:.2}", other_qty),
-            pool_type: pool.pool_type.clone(),
-            tvl: format!("{:.2}", pool.pool_tvl),
-            volume_usd: format!("{:.2}", pool.daily_volume),
-            fee: format!("{}%", pool.total_fee_pct.trim_end_matches('%')),
-            pool_address: pool.pool_address.clone(),
-            cheese_price: format!("${:.6}", cheese_usdc_price),
+        // Venues without USD figures get their reserves valued at Jupiter prices
+        let price = |mint: &str| usd_prices.get(mint).copied().unwrap_or(0.0);
+        let tvl = pool.tvl_usd.unwrap_or_else(|| {
+            side.qty * price(&base.mint.to_string()) + side.other_qty * price(&other_mint)
         });
-    }
-
-    // Add Raydium pools
-    for pool in &raydium_pools {
-        let (cheese_qty, other_qty, other_mint, other_symbol) = if pool.mintA.address == CHEESE_MINT
-        {
-            (
-                pool.mint_amount_a,
-                pool.mint_amount_b,
-                pool.mintB.address.clone(),
-                pool.mintB.symbol.clone(),
-            )
-        } else {
-            (
-                pool.mint_amount_b,
-                pool.mint_amount_a,
-                pool.mintA.address.clone(),
-                pool.mintA.symbol.clone(),
-            )
-        };
 
         // Update aggregates
         aggregates.number_of_pools += 1;
-        aggregates.total_cheese_qty += cheese_qty;
-        aggregates.total_liquidity_usd += pool.tvl;
-        aggregates.total_volume_24h += pool.day.volume;
+        aggregates.total_base_qty += side.qty;
+        aggregates.total_liquidity_usd += tvl;
+        aggregates.total_volume_24h += pool.volume_24h_usd.unwrap_or(0.0);
 
         display_pools.push(DisplayPool {
-            source: "Raydium".to_string(),
+            source: pool.source.to_string(),
+            other_symbol: other_symbol(&mint_to_symbol, &side),
             other_mint,
-            other_symbol,
-            cheese_qty: format!("{:.2}", cheese_qty),
-            other_qty: format!("{:.2}", other_qty),
-            pool_type: pool.r#type.clone(),
-            tvl: format!("{:.2}", pool.tvl),
-            volume_usd: format!("{:.2}", pool.day.volume),
-            fee: format!("{:.2}%", pool.feeRate * 100.0),
-            pool_address: pool.pool_id.clone(),
-            cheese_price: format!("${:.6}", cheese_usdc_price),
+            base_qty: format!("{:.2}", side.qty),
+            other_qty: format!("{:.2}", side.other_qty),
+            pool_type: pool.pool_type.clone(),
+            tvl: format!("{:.2}", tvl),
+            volume_usd: pool
+                .volume_24h_usd
+                .map_or_else(|| "N/A".to_string(), |volume| format!("{:.2}", volume)),
+            fee: format!("{:.2}%", pool.fees.trade_fee * 100.0),
+            pool_address: pool.address.to_string(),
         });
     }
 
@@ -295,13 +522,15 @@ async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
     });
 
     // Print pools
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut flagged_prices = 0;
     for pool in &display_pools {
         // Calculate derived price if available
-        let derived_price = if let Some(price) = jup_prices.get(&pool.other_mint) {
+        let derived_price = if let Some(price) = usd_prices.get(&pool.other_mint) {
             let other_qty = pool.other_qty.parse::<f64>().unwrap_or(0.0);
-            let cheese_qty = pool.cheese_qty.parse::<f64>().unwrap_or(0.0);
-            if cheese_qty > 0.0 {
-                (other_qty * price) / cheese_qty
+            let base_qty = pool.base_qty.parse::<f64>().unwrap_or(0.0);
+            if base_qty > 0.0 {
+                (other_qty * price) / base_qty
             } else {
                 0.0
             }
@@ -309,27 +538,35 @@ async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
             0.0
         };
 
+        // A low-confidence or stale Jupiter price only gets a flagged mention
+        let untrusted = jup_prices
+            .get(&pool.other_mint)
+            .is_some_and(|price| is_untrusted(price, now));
+        if untrusted && derived_price > 0.0 {
+            flagged_prices += 1;
+        }
+
         // Use derived price for TVL if available
-        let tvl = if derived_price > 0.0 {
-            let cheese_qty = pool.cheese_qty.parse::<f64>().unwrap_or(0.0);
-            cheese_qty * derived_price * 2.0 // multiply by 2 since it's both sides of the pool
+        let tvl = if derived_price > 0.0 && !untrusted {
+            let base_qty = pool.base_qty.parse::<f64>().unwrap_or(0.0);
+            base_qty * derived_price * 2.0 // multiply by 2 since it's both sides of the pool
         } else {
             pool.tvl.parse::<f64>().unwrap_or(0.0)
         };
 
         println!(
-            "| {:8} | {:44} | {:10} | {:10} | {:10} | {:9} | {:12} | {:9} | {:5} | {:12} | {:44} |",
+            "| {:12} | {:44} | {:10} | {:10} | {:10} | {:9} | {:12} | {:9} | {:5} | {:12} | {:44} |",
             pool.source,
             pool.other_mint,
             pool.other_symbol,
             pool.pool_type,
-            pool.cheese_qty,
+            pool.base_qty,
             pool.other_qty,
             format!("{:.2}", tvl),
             pool.volume_usd,
             pool.fee,
             if derived_price > 0.0 {
-                format!("${:.6}", derived_price)
+                format!("${:.6}{}", derived_price, if untrusted { "*" } else { "" })
             } else {
                 "N/A".to_string()
             },
@@ -337,8 +574,15 @@ async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
         );
     }
 
+    if flagged_prices > 0 {
+        println!(
+            "\n* Derived from a low-confidence or stale Jupiter price ({} pools)",
+            flagged_prices
+        );
+    }
+
     // Print summary
-    println!("\n===== 🧀 Aggregates =====");
+    println!("\n===== {} Aggregates =====", base.mark());
     println!(
         "Total Liquidity (USD):   ${:.2}",
         aggregates.total_liquidity_usd
@@ -348,169 +592,222 @@ async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
         aggregates.total_volume_24h
     );
     println!("Number of pools:        {}", aggregates.number_of_pools);
-    println!("Total 🧀 in pools:      {:.2}", aggregates.total_cheese_qty);
+    println!(
+        "Total {} in pools:      {:.2}",
+        base.mark(),
+        aggregates.total_base_qty
+    );
     println!("===========================\n");
 
-    // Process pools and find opportunities
-    let opportunities = find_arbitrage_opportunities(&meteora_pools, cheese_usdc_price)?;
+    // 3) search every cycle from the base token or USDC back to itself, quoting each
+    //    promising one at its best size, leg by leg against the chain
+    usd_prices
+        .entry(base.mint.to_string())
+        .or_insert(base_usd_price);
+    let context = CycleContext {
+        client,
+        base,
+        executor,
+        search,
+        usd_prices: &usd_prices,
+        mint_to_symbol: &mint_to_symbol,
+    };
+    let Some(ws_url) = ws_url else {
+        return evaluate_cycles(&context, &venues, &pools).await;
+    };
+    evaluate_cycles(&context, &venues, &pools).await?;
 
-    // Print opportunities
-    for opp in opportunities
-        .iter()
-        .filter(|o| o.net_profit_usd >= MIN_PROFIT_USD)
-    {
-        // Get the pool for this opportunity
-        let pool = meteora_pools
-            .iter()
-            .find(|p| p.pool_address == opp.pool_address)
-            .unwrap();
-        let fee_percent = pool
-            .total_fee_pct
-            .trim_end_matches('%')
-            .parse::<f64>()
-            .unwrap()
-            / 100.0;
+    // 4) keep the pools current from the websocket and search again on every
+    //    change, until it's time to list pools and prices afresh
+    let mut stream = PoolStream::subscribe(ws_url, rpc_client.commitment(), &venues, pools).await?;
+    info!(pools = stream.pools().len(), ws_url, "Streaming pools");
+    let deadline = time::Instant::now() + STREAM_WINDOW;
+    loop {
+        let updates = match time::timeout_at(deadline, stream.next()).await {
+            Ok(Some(updates)) => updates,
+            Ok(None) => return Err(anyhow::anyhow!("Websocket at {} closed", ws_url)),
+            Err(_) => return Ok(()),
+        };
+        if updates.is_empty() {
+            continue;
+        }
+        for update in &updates {
+            debug!(
+                venue = update.pool.source,
+                pool = %update.pool.address,
+                slot = update.slot,
+                "Pool changed"
+            );
+        }
+        if let Err(e) = evaluate_cycles(&context, &venues, stream.pools()).await {
+            error!(error = %e, "Evaluating cycles failed");
+        }
+    }
+}
 
-        println!("\nPool: {} ({})", opp.pool_address, opp.symbol);
-        println!("├─ Implied CHEESE price: ${:.10}", opp.implied_price);
-        println!(
-            "├─ Price difference: {:.2}%",
-            ((opp.implied_price - opp.usdc_price) / opp.usdc_price) * 100.0
-        );
-        println!("├─ Pool liquidity:");
-        println!("│  ├─ 🧀: {:.2}", opp.cheese_qty);
-        println!("│  └─ {}: {:.2}", opp.symbol, opp.other_qty);
-        println!("├─ Fees:");
-        println!("│  ├─ USDC->CHEESE fee: 0.25%");
-        println!("│  ├─ CHEESE->Target fee: {:.2}%", fee_percent * 100.0);
-        println!("│  ├─ Target->CHEESE fee: {:.2}%", fee_percent * 100.0);
-        println!("│  ├─ CHEESE->USDC fee: 0.25%");
+/// Everything cycle evaluation needs besides the venues and pools
+struct CycleContext<'a> {
+    client: &'a HttpClient,
+    base: &'a BaseToken,
+    executor: &'a Option<TradeExecutor>,
+    search: &'a CycleSearch,
+    usd_prices: &'a HashMap<String, f64>,
+    mint_to_symbol: &'a HashMap<String, String>,
+}
+
+/// Search, print and, in hot mode, execute the cycles through `pools`
+async fn evaluate_cycles(
+    context: &CycleContext<'_>,
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+) -> Result<()> {
+    let CycleContext {
+        client,
+        base,
+        executor,
+        search,
+        usd_prices,
+        mint_to_symbol,
+    } = *context;
+    // Without a SOL price transaction costs would count as free
+    let sol_usd = usd_prices
+        .get(&WSOL_MINT.to_string())
+        .copied()
+        .filter(|price| *price > 0.0)
+        .ok_or_else(|| anyhow::anyhow!("No SOL price to cost transactions with"))?;
+    let start_mints = [base.mint, USDC_MINT];
+    // Only size trades to the wallet when there is one to trade from
+    let mut balances = HashMap::new();
+    if let Some(executor) = executor {
+        for mint in start_mints {
+            balances.insert(mint, executor.token_balance(&mint).await?);
+        }
+    }
+    let mut cycles = find_arbitrage_cycles(
+        venues,
+        pools,
+        &start_mints,
+        search,
+        &balances,
+        usd_prices,
+        LAMPORTS_PER_SIGNATURE as f64 / LAMPORTS_PER_SOL as f64,
+    )
+    .await;
+    // In hot mode, cost each cycle that still clears the bar from the
+    // priority fees paid lately on the pools it write-locks
+    if let Some(executor) = executor {
+        let mut costed = Vec::with_capacity(cycles.len());
+        for mut cycle in cycles {
+            if cycle.net_profit_usd >= MIN_PROFIT_USD {
+                let cost_sol = match cycle_cost_sol(executor, &cycle).await {
+                    Ok(cost_sol) => cost_sol,
+                    Err(e) => {
+                        warn!(error = %e, "Skipping cycle that can't be costed");
+                        continue;
+                    }
+                };
+                cycle.net_profit_usd -= (cost_sol - cycle.total_fees_sol) * sol_usd;
+                cycle.total_fees_sol = cost_sol;
+            }
+            costed.push(cycle);
+        }
+        cycles = costed;
+        cycles.sort_by(|a, b| b.net_profit_usd.total_cmp(&a.net_profit_usd));
+    }
+    let symbol = |mint: &str| token_symbol(mint_to_symbol, pools, mint);
+
+    // Print opportunities
+    for cycle in cycles.iter().filter(|c| c.net_profit_usd >= MIN_PROFIT_USD) {
+        let Some(first) = cycle.steps.first() else {
+            continue;
+        };
+        let path: Vec<String> = std::iter::once(symbol(&first.sell_token))
+            .chain(cycle.steps.iter().map(|step| symbol(&step.buy_token)))
+            .collect();
+        println!("\nCycle: {}", path.join(" -> "));
+        println!("├─ Legs:");
+        for (i, step) in cycle.steps.iter().enumerate() {
+            println!(
+                "│  {}. {:.6} {} -> {:.6} {} on {} {} (fee {:.2}%)",
+                i + 1,
+                step.amount_in,
+                symbol(&step.sell_token),
+                step.expected_out,
+                symbol(&step.buy_token),
+                step.source,
+                step.pool_address,
+                step.fee_percent * 100.0
+            );
+        }
+        println!("└─ Profitability:");
         println!(
-            "│  └─ Transaction cost: ${:.4} (4 transactions)",
-            SOL_PER_TX * 4.0
+            "   ├─ {} in: {:.6} (${:.4})",
+            path[0], cycle.initial_amount, cycle.initial_usdc_value
         );
-        println!("├─ Trade path:");
         println!(
-            "│  1. Buy {:.2} USDC worth of CHEESE at ${:.10}",
-            opp.max_trade_size * opp.usdc_price,
-            opp.usdc_price
+            "   ├─ {} out: {:.6} (${:.4})",
+            path[0], cycle.final_amount, cycle.final_usdc_value
         );
+        println!("   ├─ Pool fees: ${:.4}", cycle.fees_usdc_value);
         println!(
-            "│  2. Trade CHEESE for {} at ${:.10}",
-            opp.symbol, opp.implied_price
+            "   ├─ Transaction cost: ${:.4} ({} lamports)",
+            cycle.total_fees_sol * sol_usd,
+            (cycle.total_fees_sol * LAMPORTS_PER_SOL as f64).round() as u64
         );
-        println!("│  3. Trade {} back to CHEESE", opp.symbol);
-        println!("│  4. Sell CHEESE for USDC at ${:.10}", opp.usdc_price);
-        println!("└─ Profitability:");
-        let gross_profit = opp.max_trade_size * (opp.implied_price - opp.usdc_price).abs();
-        let total_fees = (opp.max_trade_size * opp.usdc_price * 0.0025) + // First USDC->CHEESE 0.25%
-                        (opp.max_trade_size * opp.implied_price * fee_percent) +   // CHEESE->Target fee
-                        (opp.max_trade_size * opp.implied_price * fee_percent) +   // Target->CHEESE fee
-                        (opp.max_trade_size * opp.usdc_price * 0.0025) +   // Final CHEESE->USDC 0.25%
-                        (SOL_PER_TX * 4.0); // 4 transactions total
-        println!("   ├─ Gross profit: ${:.4}", gross_profit);
-        println!("   ├─ Total fees: ${:.4}", total_fees);
-        println!("   └─ Net profit: ${:.4}", opp.net_profit_usd);
+        println!("   └─ Net profit: ${:.4}", cycle.net_profit_usd);
+
+        // Compare the first leg against the aggregator's best route
+        let scale = |mint: &str| 10f64.powi(token_decimals(pools, mint) as i32);
+        let amount_in = (first.amount_in * scale(&first.sell_token)) as u64;
+        let params = JupiterQuoteParams::new(&first.sell_token, &first.buy_token, amount_in, 50);
+        match get_jupiter_quote(client, &params).await {
+            Ok(quote) => {
+                let ours = first.expected_out;
+                let jupiter_out = quote.out_amount().unwrap_or(0) as f64 / scale(&first.buy_token);
+                let versus = if ours > 0.0 {
+                    format!("{:+.4}% vs ours", (jupiter_out - ours) / ours * 100.0)
+                } else {
+                    "ours quotes nothing".to_string()
+                };
+                println!(
+                    "   Jupiter quote for leg 1: {:.6} {} via {} ({})",
+                    jupiter_out,
+                    symbol(&first.buy_token),
+                    quote.route_labels().join(" -> "),
+                    versus
+                );
+            }
+            Err(e) => println!("   Jupiter quote unavailable: {}", e),
+        }
 
         // Execute trade if in hot mode
         if let Some(executor) = executor {
-            println!("\nExecuting trade...");
-
-            // Get the other token's index
-            let (_, other_ix) = if pool.pool_token_mints[0] == CHEESE_MINT {
-                (0, 1)
-            } else {
-                (1, 0)
+            // The closing balance check wants the profit in start token base units
+            let Some(start_price) = usd_prices
+                .get(&first.sell_token)
+                .copied()
+                .filter(|price| *price > 0.0)
+            else {
+                println!("\nNo price for {}, not executing", path[0]);
+                continue;
             };
-
-            if opp.is_sell {
-                // Path: USDC -> CHEESE -> Target -> CHEESE -> USDC
-
-                // 1. USDC -> CHEESE on Meteora
-                let amount_in_usdc = (opp.max_trade_size * opp.usdc_price * 1_000_000.0) as u64;
-                let sig1 = executor
-                    .execute_trade(
-                        usdc_pool,
-                        USDC_MINT,
-                        CHEESE_MINT,
-                        amount_in_usdc,
-                        50, // 0.5% slippage
-                    )
-                    .await?;
-                println!("1. USDC -> CHEESE: {}", sig1);
-
-                // 2. CHEESE -> Target token
-                let amount_in_cheese = (opp.max_trade_size * 1_000_000_000.0) as u64;
-                let sig2 = executor
-                    .execute_trade(
-                        pool,
-                        CHEESE_MINT,
-                        &pool.pool_token_mints[other_ix],
-                        amount_in_cheese,
-                        50,
-                    )
-                    .await?;
-                println!("2. CHEESE -> {}: {}", opp.symbol, sig2);
-
-                // 3. Target -> CHEESE
-                let amount_in_target = (opp.other_qty * 0.1 * 1_000_000_000.0) as u64; // 10% of target token liquidity
-                let sig3 = executor
-                    .execute_trade(
-                        pool,
-                        &pool.pool_token_mints[other_ix],
-                        CHEESE_MINT,
-                        amount_in_target,
-                        50,
-                    )
-                    .await?;
-                println!("3. {} -> CHEESE: {}", opp.symbol, sig3);
-
-                // 4. CHEESE -> USDC
-                let sig4 = executor
-                    .execute_trade(usdc_pool, CHEESE_MINT, USDC_MINT, amount_in_cheese, 50)
-                    .await?;
-                println!("4. CHEESE -> USDC: {}", sig4);
-            } else {
-                // Path: USDC -> CHEESE -> Target -> CHEESE -> USDC
-
-                // 1. USDC -> CHEESE on Meteora
-                let amount_in_usdc = (opp.max_trade_size * opp.usdc_price * 1_000_000.0) as u64;
-                let sig1 = executor
-                    .execute_trade(usdc_pool, USDC_MINT, CHEESE_MINT, amount_in_usdc, 50)
-                    .await?;
-                println!("1. USDC -> CHEESE: {}", sig1);
-
-                // 2. CHEESE -> Target token
-                let amount_in_cheese = (opp.max_trade_size * 1_000_000_000.0) as u64;
-                let sig2 = executor
-                    .execute_trade(
-                        pool,
-                        CHEESE_MINT,
-                        &pool.pool_token_mints[other_ix],
-                        amount_in_cheese,
-                        50,
-                    )
-                    .await?;
-                println!("2. CHEESE -> {}: {}", opp.symbol, sig2);
-
-                // 3. Target -> CHEESE
-                let amount_in_target = (opp.other_qty * 0.1 * 1_000_000_000.0) as u64; // 10% of target token liquidity
-                let sig3 = executor
-                    .execute_trade(
-                        pool,
-                        &pool.pool_token_mints[other_ix],
-                        CHEESE_MINT,
-                        amount_in_target,
-                        50,
-                    )
-                    .await?;
-                println!("3. {} -> CHEESE: {}", opp.symbol, sig3);
-
-                // 4. CHEESE -> USDC
-                let sig4 = executor
-                    .execute_trade(usdc_pool, CHEESE_MINT, USDC_MINT, amount_in_cheese, 50)
-                    .await?;
-                println!("4. CHEESE -> USDC: {}", sig4);
+            let min_profit = (MIN_PROFIT_USD / start_price * scale(&first.sell_token)) as u64;
+            println!("\nExecuting trade...");
+            match executor
+                .execute_cycle(
+                    venues, pools, cycle, 50, /* 0.5% slippage */
+                    min_profit,
+                )
+                .await
+            {
+                Ok(sig) => println!("Cycle executed: {}", sig),
+                // The opportunity is gone or out of reach, the next one may not be
+                Err(libcheese::Error::Unsupported { what }) => {
+                    println!("\nNo swap path for {}, not executing", what)
+                }
+                // Whatever stopped this cycle, from a stale pool to a busy
+                // RPC node, the next one may still go through
+                Err(e) => warn!(error = %e, "Skipping cycle"),
             }
         }
     }
@@ -518,66 +815,199 @@ async fn run_iteration(executor: &Option<TradeExecutor>) -> Result<()> {
     Ok(())
 }
 
-fn find_arbitrage_opportunities(
-    pools: &[MeteoraPool],
-    cheese_usdc_price: f64,
-) -> Result<Vec<ArbitrageOpportunity>> {
-    let mut opportunities = Vec::new();
+/// What executing `cycle` should cost, in SOL, from the priority fees paid
+/// lately on the pools it write-locks. Cycles go out as one transaction, so
+/// they pay one signature fee.
+async fn cycle_cost_sol(executor: &TradeExecutor, cycle: &ArbitrageCycle) -> Result<f64> {
+    let pools: Vec<Pubkey> = cycle
+        .steps
+        .iter()
+        .map(|step| Pubkey::from_str(&step.pool_address))
+        .collect::<Result<_, _>>()?;
+    let lamports = executor
+        .estimate_transaction_cost(&pools, cycle_compute_units(cycle.steps.len()))
+        .await?;
+    Ok(lamports as f64 / LAMPORTS_PER_SOL as f64)
+}
 
-    for pool in pools {
-        // Skip USDC pool and pools with derived prices
-        if pool.pool_address == "2rkTh46zo8wUvPJvACPTJ16RNUHEM9EZ1nLYkUxZEHkw" || pool.derived {
-            continue;
-        }
+/// Compute units a cycle of `legs` swaps asks for, within one transaction's
+/// limit
+fn cycle_compute_units(legs: usize) -> u32 {
+    let units = LEG_COMPUTE_UNITS as u64 * legs as u64 + PROFIT_CHECK_COMPUTE_UNITS as u64;
+    units.min(MAX_COMPUTE_UNITS as u64) as u32
+}
 
-        let (cheese_ix, other_ix) = if pool.pool_token_mints[0] == CHEESE_MINT {
-            (0, 1)
-        } else {
-            (1, 0)
-        };
+/// Send libcheese's and our own diagnostics to stderr, leaving stdout to
+/// the pool table and cycle reports
+fn init_logging(args: &Args) -> Result<()> {
+    let format = log_format(args)?;
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level(args)));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+    Ok(())
+}
 
-        let cheese_qty: f64 = pool.pool_token_amounts[cheese_ix].parse()?;
-        let other_qty: f64 = pool.pool_token_amounts[other_ix].parse()?;
-        let fee_percent: f64 = pool.total_fee_pct.trim_end_matches('%').parse::<f64>()? / 100.0;
+#[derive(Debug, PartialEq, Eq)]
+enum LogFormat {
+    Human,
+    Json,
+}
 
-        if cheese_qty <= 0.0 || other_qty <= 0.0 {
-            continue;
-        }
+fn log_format(args: &Args) -> Result<LogFormat> {
+    match args.log_format.as_str() {
+        "human" => Ok(LogFormat::Human),
+        "json" => Ok(LogFormat::Json),
+        other => Err(anyhow::anyhow!("Invalid --log-format: {}", other)),
+    }
+}
 
-        let implied_price = (other_qty * cheese_usdc_price) / cheese_qty;
-        let price_diff_pct = ((implied_price - cheese_usdc_price) / cheese_usdc_price) * 100.0;
-
-        // If price difference is significant (>1%)
-        if price_diff_pct.abs() > 1.0 {
-            let max_trade_size = cheese_qty * 0.1; // 10% of pool liquidity
-            let price_diff_per_cheese = (implied_price - cheese_usdc_price).abs();
-            let gross_profit = max_trade_size * price_diff_per_cheese;
-
-            // Calculate fees for the full USDC -> CHEESE -> Target -> CHEESE -> USDC path
-            let total_fees = (max_trade_size * cheese_usdc_price * 0.0025) + // First USDC->CHEESE 0.25%
-                           (max_trade_size * implied_price * fee_percent) +   // CHEESE->Target fee
-                           (max_trade_size * implied_price * fee_percent) +   // Target->CHEESE fee
-                           (max_trade_size * cheese_usdc_price * 0.0025) +   // Final CHEESE->USDC 0.25%
-                           (SOL_PER_TX * 4.0); // 4 transactions total
-
-            let net_profit = gross_profit - total_fees;
-
-            if net_profit >= MIN_PROFIT_USD {
-                opportunities.push(ArbitrageOpportunity {
-                    pool_address: pool.pool_address.clone(),
-                    symbol: parse_other_token_name(&pool.pool_name),
-                    cheese_qty,
-                    other_qty,
-                    implied_price,
-                    usdc_price: cheese_usdc_price,
-                    max_trade_size,
-                    net_profit_usd: net_profit,
-                    is_sell: implied_price > cheese_usdc_price, // If true, we buy CHEESE in USDC pool and sell in target
-                });
-            }
+/// Filter for -v and -q, when RUST_LOG doesn't set one
+fn log_level(args: &Args) -> &'static str {
+    match (args.verbose, args.quiet) {
+        (0, 0) => "info",
+        (1, _) => "debug",
+        (_, 0) => "trace",
+        (_, 1) => "warn",
+        _ => "error",
+    }
+}
+
+/// The token to scan pools of: CHEESE unless --base-mint names another
+fn base_token(args: &Args) -> Result<BaseToken> {
+    let mut base = BaseToken::cheese();
+    if let Some(mint) = &args.base_mint {
+        let mint = Pubkey::from_str(mint)?;
+        if mint != base.mint {
+            base = BaseToken::new(mint, &mint.to_string()[..6], "");
         }
     }
+    if let Some(symbol) = &args.base_symbol {
+        base.symbol = symbol.clone();
+    }
+    if let Some(emoji) = &args.base_emoji {
+        base.emoji = emoji.clone();
+    }
+    if let Some(pool) = &args.quote_pool {
+        base.quote_pool = Some(Pubkey::from_str(pool)?);
+    }
+    Ok(base)
+}
 
-    opportunities.sort_by(|a, b| b.net_profit_usd.partial_cmp(&a.net_profit_usd).unwrap());
-    Ok(opportunities)
+/// Whether a Jupiter price is too shaky to value a pool with
+fn is_untrusted(price: &TokenPrice, now: u64) -> bool {
+    price.confidence == Some(Confidence::Low) || price.is_stale(now, MAX_PRICE_AGE_SECS)
+}
+
+/// Decimals of `mint`, from any pool that trades it
+fn token_decimals(pools: &[Pool], mint: &str) -> u8 {
+    let Ok(key) = Pubkey::from_str(mint) else {
+        return 0;
+    };
+    pools
+        .iter()
+        .find_map(|pool| Some(pool.decimals[pool.index_of(&key)?]))
+        .unwrap_or(0)
+}
+
+/// Symbol for any token: Raydium's mint list first, then whatever a venue
+/// called it, then the start of the mint address
+fn token_symbol(mint_to_symbol: &HashMap<String, String>, pools: &[Pool], mint: &str) -> String {
+    let key = Pubkey::from_str(mint).ok();
+    mint_to_symbol
+        .get(mint)
+        .cloned()
+        .or_else(|| {
+            pools.iter().find_map(|pool| {
+                let index = pool.index_of(key.as_ref()?)?;
+                pool.symbols[index].clone()
+            })
+        })
+        .unwrap_or_else(|| mint.chars().take(6).collect())
+}
+
+/// Symbol for a pool's other token: Raydium's mint list first, then the
+/// venue's own name for it, then the start of the mint address
+fn other_symbol(mint_to_symbol: &HashMap<String, String>, side: &PoolSide) -> String {
+    let mint = side.other_mint.to_string();
+    mint_to_symbol
+        .get(&mint)
+        .cloned()
+        .or_else(|| side.other_symbol.clone())
+        .unwrap_or_else(|| mint[..6].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcheese::common::CHEESE_MINT;
+
+    #[test]
+    fn defaults_the_base_token_to_cheese() {
+        let args = Args::parse_from(["cheese", "cold"]);
+        assert_eq!(base_token(&args).unwrap(), BaseToken::cheese());
+
+        let args = Args::parse_from(["cheese", "cold", "--base-mint", CHEESE_MINT]);
+        assert_eq!(base_token(&args).unwrap(), BaseToken::cheese());
+    }
+
+    #[test]
+    fn parses_a_custom_base_token() {
+        let sol = WSOL_MINT.to_string();
+        let args = Args::parse_from(["cheese", "cold", "--base-mint", &sol]);
+        let base = base_token(&args).unwrap();
+        assert_eq!(base.mint, WSOL_MINT);
+        assert_eq!((base.symbol.as_str(), base.mark()), ("So1111", "So1111"));
+
+        let args = Args::parse_from([
+            "cheese",
+            "cold",
+            "--base-mint",
+            &sol,
+            "--base-symbol",
+            "SOL",
+            "--base-emoji",
+            "◎",
+            "--quote-pool",
+            &USDC_MINT.to_string(),
+        ]);
+        let base = base_token(&args).unwrap();
+        assert_eq!((base.symbol.as_str(), base.mark()), ("SOL", "◎"));
+        assert_eq!(base.quote_pool, Some(USDC_MINT));
+
+        let args = Args::parse_from(["cheese", "cold", "--base-mint", "not-a-mint"]);
+        assert!(base_token(&args).is_err());
+    }
+
+    #[test]
+    fn sizes_compute_units_by_leg_count() {
+        assert_eq!(cycle_compute_units(2), 310_000);
+        assert_eq!(cycle_compute_units(4), 610_000);
+        assert_eq!(cycle_compute_units(20), MAX_COMPUTE_UNITS);
+    }
+
+    #[test]
+    fn parses_log_flags() {
+        for (flags, level, format) in [
+            (&[][..], "info", LogFormat::Human),
+            (&["-v"], "debug", LogFormat::Human),
+            (&["-vv", "--log-format", "json"], "trace", LogFormat::Json),
+            (&["-vvv"], "trace", LogFormat::Human),
+            (&["-q"], "warn", LogFormat::Human),
+            (&["-qq", "--log-format", "json"], "error", LogFormat::Json),
+        ] {
+            let args = Args::parse_from(["cheese", "cold"].iter().chain(flags));
+            assert_eq!(log_level(&args), level, "{:?}", flags);
+            assert_eq!(log_format(&args).unwrap(), format, "{:?}", flags);
+        }
+
+        let args = Args::parse_from(["cheese", "cold", "--log-format", "xml"]);
+        assert!(log_format(&args).is_err());
+        assert!(Args::try_parse_from(["cheese", "cold", "-v", "-q"]).is_err());
+    }
 }
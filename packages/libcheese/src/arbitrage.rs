@@ -0,0 +1,594 @@
+//! Arbitrage search over a graph of tokens joined by pools.
+//!
+//! Every pool is an edge each way between its two tokens. Cycles through
+//! the graph are found by depth-first search from a start token, screened
+//! by the product of their after-fee spot rates, and the most promising are
+//! sized to the input that makes the most and then replayed leg by leg with
+//! each venue's exact quote.
+
+use crate::common::WSOL_MINT;
+use crate::error::{Error, Result};
+use crate::raydium::amm_v4::AMM_V4_PROGRAM_ID;
+use crate::raydium::cpmm::CPMM_PROGRAM_ID;
+use crate::venue::{venue_for, Pool, Venue};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// A closed trading loop that starts and ends in the same token
+#[derive(Debug)]
+pub struct ArbitrageCycle {
+    pub steps: Vec<TradeStep>,
+    /// Start token put in and taken out, in UI units
+    pub initial_amount: f64,
+    pub final_amount: f64,
+    pub total_fees_sol: f64,
+    /// Trade fee of each leg, in USD
+    pub pool_fees_paid: Vec<f64>,
+    pub initial_usdc_value: f64, // Value of the initial amount in USDC
+    pub final_usdc_value: f64,   // Value of the final amount in USDC
+    pub fees_usdc_value: f64,    // Value of all fees in USDC
+    /// Profit in USD once the transaction is paid for
+    pub net_profit_usd: f64,
+}
+
+/// One swap inside an `ArbitrageCycle`, amounts in UI units
+#[derive(Debug, Clone)]
+pub struct TradeStep {
+    pub pool_address: String,
+    pub source: String,
+    pub sell_token: String,
+    pub buy_token: String,
+    pub amount_in: f64,
+    pub expected_out: f64,
+    pub fee_percent: f64,
+}
+
+// -----------------------------------
+// Token Graph
+// -----------------------------------
+
+/// One direction through a pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolEdge {
+    /// Index of the pool in the slice the graph was built from
+    pub pool: usize,
+    pub from: Pubkey,
+    pub to: Pubkey,
+    /// Whole `to` one whole `from` buys at the current price, after fees
+    pub rate: f64,
+}
+
+/// Tokens joined by every direction of every pool
+pub struct TokenGraph<'a> {
+    pools: &'a [Pool],
+    edges: HashMap<Pubkey, Vec<PoolEdge>>,
+}
+
+impl<'a> TokenGraph<'a> {
+    /// Graph of `pools`, leaving out pools priced off other pools and those
+    /// without a usable price or reserves
+    pub fn new(pools: &'a [Pool]) -> Self {
+        let mut edges: HashMap<Pubkey, Vec<PoolEdge>> = HashMap::new();
+        for (index, pool) in pools.iter().enumerate() {
+            if pool.derived
+                || pool.reserves.contains(&0)
+                || !(pool.price.is_finite() && pool.price > 0.0)
+            {
+                continue;
+            }
+            let keep = 1.0 - pool.fees.trade_fee;
+            for (from, rate) in [(0, pool.price * keep), (1, keep / pool.price)] {
+                edges.entry(pool.mints[from]).or_default().push(PoolEdge {
+                    pool: index,
+                    from: pool.mints[from],
+                    to: pool.mints[1 - from],
+                    rate,
+                });
+            }
+        }
+        Self { pools, edges }
+    }
+
+    pub fn pools(&self) -> &'a [Pool] {
+        self.pools
+    }
+
+    pub fn edges_from(&self, token: &Pubkey) -> &[PoolEdge] {
+        self.edges.get(token).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every cycle of two to `max_hops` swaps from `start` back to it that
+    /// visits no other token or pool twice and gains at spot rates, best
+    /// rate first
+    pub fn find_cycles(&self, start: &Pubkey, max_hops: usize) -> Vec<Vec<PoolEdge>> {
+        let mut cycles = Vec::new();
+        let mut path = Vec::new();
+        self.extend_path(start, start, max_hops, &mut path, &mut cycles);
+        cycles.sort_by(|a, b| spot_rate(b).total_cmp(&spot_rate(a)));
+        cycles
+    }
+
+    fn extend_path(
+        &self,
+        start: &Pubkey,
+        token: &Pubkey,
+        hops_left: usize,
+        path: &mut Vec<PoolEdge>,
+        cycles: &mut Vec<Vec<PoolEdge>>,
+    ) {
+        if hops_left == 0 {
+            return;
+        }
+        for edge in self.edges_from(token) {
+            if path.iter().any(|step| step.pool == edge.pool) {
+                continue;
+            }
+            path.push(edge.clone());
+            if edge.to == *start {
+                if path.len() >= 2 && spot_rate(path) > 1.0 {
+                    cycles.push(path.clone());
+                }
+            } else if !path.iter().any(|step| step.from == edge.to) {
+                self.extend_path(start, &edge.to, hops_left - 1, path, cycles);
+            }
+            path.pop();
+        }
+    }
+}
+
+/// Start tokens a cycle returns per start token, at spot rates after fees
+pub fn spot_rate(path: &[PoolEdge]) -> f64 {
+    path.iter().map(|edge| edge.rate).product()
+}
+
+// -----------------------------------
+// Cycle Search
+// -----------------------------------
+
+/// How far `find_arbitrage_cycles` looks
+#[derive(Debug, Clone)]
+pub struct CycleSearch {
+    /// Most swaps in one cycle
+    pub max_hops: usize,
+    /// Most cycles quoted on chain per start token, best spot rate first
+    pub max_candidates: usize,
+    /// Most USD one cycle may put in, whatever the wallet holds
+    pub max_trade_usd: Option<f64>,
+}
+
+impl Default for CycleSearch {
+    fn default() -> Self {
+        Self {
+            max_hops: 3,
+            max_candidates: 10,
+            max_trade_usd: None,
+        }
+    }
+}
+
+/// Cycles from each of `start_mints` that stay profitable when every leg is
+/// quoted by its venue, most profitable first. Each is sized by
+/// `size_cycle`, capped by the start token's entry in `balances` (base
+/// units, tokens left out aren't capped) and by `search.max_trade_usd`.
+/// `usd_prices` values the start tokens and SOL, keyed by mint.
+pub async fn find_arbitrage_cycles(
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+    start_mints: &[Pubkey],
+    search: &CycleSearch,
+    balances: &HashMap<Pubkey, u64>,
+    usd_prices: &HashMap<String, f64>,
+    tx_cost_sol: f64,
+) -> Vec<ArbitrageCycle> {
+    let graph = TokenGraph::new(pools);
+    let mut cycles = Vec::new();
+    for start in start_mints {
+        let mut max_in = balances.get(start).copied().unwrap_or(u64::MAX);
+        if let Some(max_usd) = search.max_trade_usd {
+            let price = usd_prices.get(&start.to_string()).copied().unwrap_or(0.0);
+            let decimals = pools
+                .iter()
+                .find_map(|pool| Some(pool.decimals[pool.index_of(start)?]))
+                .unwrap_or(0);
+            // Without a price the risk limit can't be checked, so don't trade
+            max_in = if price > 0.0 {
+                max_in.min((max_usd / price * 10f64.powi(decimals as i32)) as u64)
+            } else {
+                0
+            };
+        }
+        if max_in == 0 {
+            continue;
+        }
+
+        for path in graph
+            .find_cycles(start, search.max_hops)
+            .into_iter()
+            .take(search.max_candidates)
+        {
+            let amount_in = match size_cycle(venues, pools, &path, max_in).await {
+                Ok(0) => continue,
+                Ok(amount_in) => amount_in,
+                Err(e) => {
+                    debug!(%start, error = %e, "Skipping cycle");
+                    continue;
+                }
+            };
+            match simulate_cycle(venues, pools, &path, amount_in, usd_prices, tx_cost_sol).await {
+                Ok(cycle) if cycle.final_amount > cycle.initial_amount => cycles.push(cycle),
+                Ok(_) => {}
+                Err(e) => debug!(%start, error = %e, "Skipping cycle"),
+            }
+        }
+    }
+    cycles.sort_by(|a, b| b.net_profit_usd.total_cmp(&a.net_profit_usd));
+    cycles
+}
+
+// -----------------------------------
+// Trade Sizing
+// -----------------------------------
+
+/// Programs whose pools trade along x * y = k
+const CONSTANT_PRODUCT_PROGRAMS: [Pubkey; 2] = [AMM_V4_PROGRAM_ID, CPMM_PROGRAM_ID];
+
+/// Golden-section steps when sizing a cycle through other kinds of pool
+const SIZING_STEPS: usize = 16;
+
+/// Base units of its start token, at most `max_in`, that `path` makes the
+/// most from; 0 when no amount makes anything. Chains of constant-product
+/// pools are solved in closed form from their reserves; anything else is
+/// searched with venue quotes.
+pub async fn size_cycle(
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+    path: &[PoolEdge],
+    max_in: u64,
+) -> Result<u64> {
+    if let Some(best) = constant_product_input(pools, path) {
+        return Ok((best as u64).min(max_in));
+    }
+
+    // More than the first pool holds of the start token only buys slippage
+    let first = path
+        .first()
+        .ok_or_else(|| Error::invalid("Cycle has no legs"))?;
+    let pool = &pools[first.pool];
+    let reserve = pool.index_of(&first.from).map_or(0, |i| pool.reserves[i]);
+    search_input(venues, pools, path, max_in.min(reserve)).await
+}
+
+/// Profit-maximizing input of a cycle made only of constant-product pools.
+///
+/// One such leg turns `x` into `a·x / (b + c·x)`, with `a = keep · reserve
+/// out`, `b = reserve in` and `c = keep`, and a chain of them has the same
+/// shape. Output minus input then peaks at `x = (√(a·b) - b) / c`.
+fn constant_product_input(pools: &[Pool], path: &[PoolEdge]) -> Option<f64> {
+    let (mut a, mut b, mut c) = (1.0, 1.0, 0.0);
+    for edge in path {
+        let pool = &pools[edge.pool];
+        if !CONSTANT_PRODUCT_PROGRAMS.contains(&pool.program_id) {
+            return None;
+        }
+        let keep = 1.0 - pool.fees.trade_fee;
+        let reserve_in = pool.reserves[pool.index_of(&edge.from)?] as f64;
+        let reserve_out = pool.reserves[pool.index_of(&edge.to)?] as f64;
+        let (leg_a, leg_b, leg_c) = (keep * reserve_out, reserve_in, keep);
+        // Scaled by the new b so long chains stay in range
+        let next_b = leg_b * b;
+        (a, b, c) = (leg_a * a / next_b, 1.0, (leg_b * c + leg_c * a) / next_b);
+    }
+    Some(if a > b { ((a * b).sqrt() - b) / c } else { 0.0 })
+}
+
+/// Golden-section search for the input up to `max_in` that `path` makes
+/// the most from. Amounts the pools can't fill count as a loss.
+async fn search_input(
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+    path: &[PoolEdge],
+    max_in: u64,
+) -> Result<u64> {
+    const SHRINK: f64 = 0.618_033_988_749_895;
+    let profit = |amount: f64| async move {
+        let amount = amount.round() as u64;
+        match quote_cycle(venues, pools, path, amount).await {
+            Ok(out) => (amount, out as f64 - amount as f64),
+            Err(_) => (amount, f64::NEG_INFINITY),
+        }
+    };
+
+    let (mut lo, mut hi) = (0.0, max_in as f64);
+    let mut left = profit(hi - SHRINK * (hi - lo)).await;
+    let mut right = profit(lo + SHRINK * (hi - lo)).await;
+    for _ in 0..SIZING_STEPS {
+        if left.1 < right.1 {
+            lo = left.0 as f64;
+            left = right;
+            right = profit(lo + SHRINK * (hi - lo)).await;
+        } else {
+            hi = right.0 as f64;
+            right = left;
+            left = profit(hi - SHRINK * (hi - lo)).await;
+        }
+    }
+    let best = if left.1 >= right.1 { left } else { right };
+    Ok(if best.1 > 0.0 { best.0 } else { 0 })
+}
+
+/// Start token base units `path` is quoted to return for `amount_in`
+async fn quote_cycle(
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+    path: &[PoolEdge],
+    amount_in: u64,
+) -> Result<u64> {
+    let mut amount = amount_in;
+    for edge in path {
+        let pool = &pools[edge.pool];
+        amount = venue_for(venues, pool)?
+            .quote(pool, &edge.from, amount)
+            .await?
+            .out_amount;
+    }
+    Ok(amount)
+}
+
+// -----------------------------------
+// Simulation
+// -----------------------------------
+
+/// Replay `path` with `amount_in` base units of its start token, each leg
+/// spending what the previous one is quoted to return
+pub async fn simulate_cycle(
+    venues: &[Box<dyn Venue + '_>],
+    pools: &[Pool],
+    path: &[PoolEdge],
+    amount_in: u64,
+    usd_prices: &HashMap<String, f64>,
+    tx_cost_sol: f64,
+) -> Result<ArbitrageCycle> {
+    let start = path
+        .first()
+        .ok_or_else(|| Error::invalid("Cycle has no legs"))?
+        .from;
+    let usd = |mint: &Pubkey| usd_prices.get(&mint.to_string()).copied().unwrap_or(0.0);
+    let sol_usd = usd(&WSOL_MINT);
+    if tx_cost_sol > 0.0 && sol_usd <= 0.0 {
+        return Err(Error::invalid("No SOL price to cost the transaction with"));
+    }
+
+    let mut steps = Vec::new();
+    let mut pool_fees_paid = Vec::new();
+    let mut amount = amount_in;
+    let mut start_decimals = 0;
+    for (i, edge) in path.iter().enumerate() {
+        let pool = &pools[edge.pool];
+        let venue = venue_for(venues, pool)?;
+        let (from, to) = (
+            pool.index_of(&edge.from).unwrap_or(0),
+            pool.index_of(&edge.to).unwrap_or(1),
+        );
+        if i == 0 {
+            start_decimals = pool.decimals[from];
+        }
+        let quote = venue.quote(pool, &edge.from, amount).await?;
+        let ui =
+            |amount: u64, index: usize| amount as f64 / 10f64.powi(pool.decimals[index] as i32);
+
+        let amount_in = ui(amount, from);
+        pool_fees_paid.push(amount_in * usd(&edge.from) * pool.fees.trade_fee);
+        steps.push(TradeStep {
+            pool_address: pool.address.to_string(),
+            source: pool.source.to_string(),
+            sell_token: edge.from.to_string(),
+            buy_token: edge.to.to_string(),
+            amount_in,
+            expected_out: ui(quote.out_amount, to),
+            fee_percent: pool.fees.trade_fee,
+        });
+        amount = quote.out_amount;
+    }
+
+    let scale = 10f64.powi(start_decimals as i32);
+    let initial_amount = amount_in as f64 / scale;
+    let final_amount = amount as f64 / scale;
+    let initial_usdc_value = initial_amount * usd(&start);
+    let final_usdc_value = final_amount * usd(&start);
+    Ok(ArbitrageCycle {
+        steps,
+        initial_amount,
+        final_amount,
+        total_fees_sol: tx_cost_sol,
+        fees_usdc_value: pool_fees_paid.iter().sum(),
+        pool_fees_paid,
+        initial_usdc_value,
+        final_usdc_value,
+        net_profit_usd: final_usdc_value - initial_usdc_value - tx_cost_sol * sol_usd,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{SwapInstructions, SwapQuote};
+    use crate::venue::Fees;
+    use async_trait::async_trait;
+
+    /// Quotes every pool as a constant product over its reserves
+    struct ConstantProduct;
+
+    #[async_trait]
+    impl Venue for ConstantProduct {
+        fn name(&self) -> &'static str {
+            "Test"
+        }
+
+        async fn discover_pools(&self, _mint: &Pubkey) -> Result<Vec<Pool>> {
+            Ok(Vec::new())
+        }
+
+        async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+            Ok(pool.clone())
+        }
+
+        async fn quote(
+            &self,
+            pool: &Pool,
+            input_mint: &Pubkey,
+            amount_in: u64,
+        ) -> Result<SwapQuote> {
+            let from = pool.index_of(input_mint).unwrap();
+            let fee = (amount_in as f64 * pool.fees.trade_fee) as u64;
+            let (x, y) = (pool.reserves[from] as u128, pool.reserves[1 - from] as u128);
+            let dx = (amount_in - fee) as u128;
+            Ok(SwapQuote {
+                out_amount: (y * dx / (x + dx)) as u64,
+                fee,
+            })
+        }
+
+        async fn swap_instructions(
+            &self,
+            _pool: &Pool,
+            _owner: &Pubkey,
+            _input_mint: &Pubkey,
+            _amount_in: u64,
+            _slippage_bps: u64,
+        ) -> Result<SwapInstructions> {
+            Err(Error::unsupported("Swapping on the test venue"))
+        }
+    }
+
+    /// Pool of `reserves` whole tokens, both with 6 decimals
+    fn pool(a: Pubkey, b: Pubkey, reserves: [u64; 2]) -> Pool {
+        Pool {
+            address: Pubkey::new_unique(),
+            source: "Test",
+            pool_type: "CPMM".to_string(),
+            program_id: CPMM_PROGRAM_ID,
+            mints: [a, b],
+            decimals: [6, 6],
+            reserves: reserves.map(|r| r * 1_000_000),
+            price: reserves[1] as f64 / reserves[0] as f64,
+            fees: Fees {
+                trade_fee: 0.0025,
+                ..Fees::default()
+            },
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+
+    /// CHEESE -> BONK -> SOL -> CHEESE gains 20% at spot, through a shallow
+    /// CHEESE/BONK pool and two deep ones
+    fn pools() -> (Vec<Pool>, [Pubkey; 3]) {
+        let [cheese, bonk, sol] = [(); 3].map(|_| Pubkey::new_unique());
+        let pools = vec![
+            pool(cheese, bonk, [1_000_000, 1_200_000]),
+            pool(bonk, sol, [100_000_000, 10_000_000]),
+            pool(sol, cheese, [1_000_000, 10_000_000]),
+        ];
+        (pools, [cheese, bonk, sol])
+    }
+
+    #[test]
+    fn finds_cycles_that_gain_at_spot_rates() {
+        let (pools, [cheese, bonk, sol]) = pools();
+        let graph = TokenGraph::new(&pools);
+
+        let cycles = graph.find_cycles(&cheese, 3);
+        assert_eq!(cycles.len(), 1);
+        let tokens: Vec<Pubkey> = cycles[0].iter().map(|edge| edge.to).collect();
+        assert_eq!(tokens, [bonk, sol, cheese]);
+        assert!((spot_rate(&cycles[0]) - 1.2 * 0.9975f64.powi(3)).abs() < 1e-12);
+
+        // Two hops can't close this loop
+        assert!(graph.find_cycles(&cheese, 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sizes_cycles_to_their_most_profitable_input() {
+        let (mut pools, [cheese, ..]) = pools();
+        let venues: Vec<Box<dyn Venue>> = vec![Box::new(ConstantProduct)];
+        let path = TokenGraph::new(&pools).find_cycles(&cheese, 3).remove(0);
+        let best = size_cycle(&venues, &pools, &path, u64::MAX).await.unwrap();
+        let mut profits = Vec::new();
+        for amount in [best * 99 / 100, best, best * 101 / 100] {
+            let out = quote_cycle(&venues, &pools, &path, amount).await.unwrap();
+            profits.push(out as i128 - amount as i128);
+        }
+        assert!(profits[1] > 0);
+        assert!(profits[1] > profits[0] && profits[1] > profits[2]);
+        assert_eq!(
+            size_cycle(&venues, &pools, &path, 1_000).await.unwrap(),
+            1_000
+        );
+
+        // Off the constant-product programs the size comes from quotes alone
+        for pool in &mut pools {
+            pool.program_id = Pubkey::new_unique();
+        }
+        let searched = size_cycle(&venues, &pools, &path, u64::MAX).await.unwrap();
+        assert!((searched as f64 / best as f64 - 1.0).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn ranks_cycles_by_quoted_profit() {
+        let (pools, [cheese, ..]) = pools();
+        let venues: Vec<Box<dyn Venue>> = vec![Box::new(ConstantProduct)];
+        let prices = HashMap::from([(cheese.to_string(), 0.5)]);
+        let search = CycleSearch {
+            max_trade_usd: Some(10_000.0),
+            ..CycleSearch::default()
+        };
+
+        let cycles = find_arbitrage_cycles(
+            &venues,
+            &pools,
+            &[cheese],
+            &search,
+            &HashMap::new(),
+            &prices,
+            0.0,
+        )
+        .await;
+        assert_eq!(cycles.len(), 1);
+        let cycle = &cycles[0];
+        assert_eq!(cycle.steps.len(), 3);
+        // The risk limit binds before the best size does
+        assert_eq!(cycle.initial_amount, 20_000.0);
+        assert!(cycle.final_amount > cycle.initial_amount);
+        assert!(cycle.final_amount < cycle.initial_amount * 1.2);
+        assert_eq!(
+            cycle.net_profit_usd,
+            (cycle.final_amount - cycle.initial_amount) * 0.5
+        );
+        for pair in cycle.steps.windows(2) {
+            assert_eq!(pair[0].expected_out, pair[1].amount_in);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_cost_transactions_without_a_sol_price() {
+        let (pools, [cheese, ..]) = pools();
+        let venues: Vec<Box<dyn Venue>> = vec![Box::new(ConstantProduct)];
+        let path = TokenGraph::new(&pools).find_cycles(&cheese, 3).remove(0);
+        let mut prices = HashMap::from([(cheese.to_string(), 0.5)]);
+
+        let error = simulate_cycle(&venues, &pools, &path, 1_000_000, &prices, 0.001)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidInput { .. }));
+
+        prices.insert(WSOL_MINT.to_string(), 200.0);
+        let cycle = simulate_cycle(&venues, &pools, &path, 1_000_000, &prices, 0.001)
+            .await
+            .unwrap();
+        let gross = (cycle.final_amount - cycle.initial_amount) * 0.5;
+        assert!((cycle.net_profit_usd - (gross - 0.2)).abs() < 1e-9);
+    }
+}
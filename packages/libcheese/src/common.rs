@@ -1,7 +1,19 @@
+use crate::error::{Error, Result};
 use serde::de::{self, Deserializer};
 use serde::Deserialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::MAX_MULTIPLE_ACCOUNTS;
+use solana_sdk::{clock::Clock, instruction::Instruction, pubkey, pubkey::Pubkey};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use std::collections::HashMap;
 
 pub const CHEESE_MINT: &str = "A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump";
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+pub const WSOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
 
 pub fn de_string_to_f64<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
 where
@@ -11,21 +23,292 @@ where
     s.parse::<f64>().map_err(de::Error::custom)
 }
 
+// -----------------------------------
+// Base Token
+// -----------------------------------
+
+/// The token a scan is built around: every pool it lists trades this
+/// token, and every cycle starts and ends in it or USDC
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseToken {
+    pub mint: Pubkey,
+    pub symbol: String,
+    /// Mark pools and tables use for the token, e.g. "🧀"
+    pub emoji: String,
+    /// Pool to read the token's price from alone. `None` leaves it to
+    /// `pricing::reference_price` across every stablecoin and SOL pool.
+    pub quote_pool: Option<Pubkey>,
+}
+
+impl BaseToken {
+    pub fn new(mint: Pubkey, symbol: &str, emoji: &str) -> Self {
+        Self {
+            mint,
+            symbol: symbol.to_string(),
+            emoji: emoji.to_string(),
+            quote_pool: None,
+        }
+    }
+
+    pub fn cheese() -> Self {
+        Self::new(Pubkey::from_str_const(CHEESE_MINT), "CHEESE", "🧀")
+    }
+
+    /// The emoji, or the symbol for tokens without one
+    pub fn mark(&self) -> &str {
+        if self.emoji.is_empty() {
+            &self.symbol
+        } else {
+            &self.emoji
+        }
+    }
+
+    /// Whether `name`, one side of a pool name, refers to this token
+    pub fn is_named(&self, name: &str) -> bool {
+        (!self.emoji.is_empty() && name.contains(&self.emoji))
+            || name.to_lowercase().contains(&self.symbol.to_lowercase())
+    }
+}
+
+impl Default for BaseToken {
+    fn default() -> Self {
+        Self::cheese()
+    }
+}
+
 // -----------------------------------
 // Helper Functions
 // -----------------------------------
-pub fn parse_other_token_name(pool_name: &str) -> String {
+/// The side of a pool name like "🧀-SOL" that isn't `base`
+pub fn parse_other_token_name(pool_name: &str, base: &BaseToken) -> String {
     let parts: Vec<&str> = pool_name.split('-').collect();
     if parts.len() == 2 {
         let left = parts[0].trim();
         let right = parts[1].trim();
-        if left.contains("🧀") || left.to_lowercase().contains("cheese") {
+        if base.is_named(left) {
             return right.to_string();
         }
-        if right.contains("🧀") || right.to_lowercase().contains("cheese") {
+        if base.is_named(right) {
             return left.to_string();
         }
         return right.to_string();
     }
     pool_name.to_string()
 }
+
+/// Apply a slippage tolerance in basis points to a quoted output amount,
+/// rounding down. Tolerances above 100% are rejected.
+pub fn min_out_amount(amount_out: u64, slippage_bps: u64) -> Result<u64> {
+    if slippage_bps > 10_000 {
+        return Err(Error::invalid(format!(
+            "Slippage of {} bps is over 100%",
+            slippage_bps
+        )));
+    }
+    let keep_bps = 10_000 - slippage_bps;
+    Ok((amount_out as u128 * keep_bps as u128 / 10_000) as u64)
+}
+
+/// Exact output of a swap, in base units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapQuote {
+    pub out_amount: u64,
+    /// Fees charged on the swap, in the token the pool collects them in
+    pub fee: u64,
+}
+
+/// Instructions for one swap built from on-chain state
+#[derive(Debug, Clone)]
+pub struct SwapInstructions {
+    pub instructions: Vec<Instruction>,
+    /// Token account the swap pays out to
+    pub output_account: Pubkey,
+    pub out_amount: u64,
+    pub min_out_amount: u64,
+}
+
+/// `owner`'s associated token accounts for `mints`, derived under the token
+/// program that owns each mint, and instructions creating the ones that
+/// don't exist yet
+async fn user_token_accounts(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    mints: &[Pubkey],
+) -> Result<(Vec<Pubkey>, Vec<Instruction>)> {
+    let mint_accounts = rpc_client.get_multiple_accounts(mints).await?;
+    let mut token_accounts = Vec::new();
+    let mut token_programs = Vec::new();
+    for (mint, account) in mints.iter().zip(mint_accounts) {
+        let token_program = account
+            .ok_or(Error::AccountNotFound { account: *mint })?
+            .owner;
+        token_accounts.push(get_associated_token_address_with_program_id(
+            owner,
+            mint,
+            &token_program,
+        ));
+        token_programs.push(token_program);
+    }
+
+    let existing = rpc_client.get_multiple_accounts(&token_accounts).await?;
+    let create = mints
+        .iter()
+        .zip(&token_programs)
+        .zip(existing)
+        .filter(|(_, account)| account.is_none())
+        .map(|((mint, token_program), _)| {
+            create_associated_token_account_idempotent(owner, owner, mint, token_program)
+        })
+        .collect();
+    Ok((token_accounts, create))
+}
+
+/// Complete a venue's swap: apply slippage to `quote`, then put the
+/// instruction `swap_instruction` builds from the input account, output
+/// account and minimum output behind any token accounts `owner` is missing
+pub(crate) async fn swap_with_token_accounts(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    quote: SwapQuote,
+    slippage_bps: u64,
+    swap_instruction: impl FnOnce(&Pubkey, &Pubkey, u64) -> Result<Instruction>,
+) -> Result<SwapInstructions> {
+    let min_out = min_out_amount(quote.out_amount, slippage_bps)?;
+    let (token_accounts, mut instructions) =
+        user_token_accounts(rpc_client, owner, &[*input_mint, *output_mint]).await?;
+    let (input_account, output_account) = (token_accounts[0], token_accounts[1]);
+    instructions.push(swap_instruction(&input_account, &output_account, min_out)?);
+
+    Ok(SwapInstructions {
+        instructions,
+        output_account,
+        out_amount: quote.out_amount,
+        min_out_amount: min_out,
+    })
+}
+
+/// Fetch `keys` in one request into `accounts`, failing if any is missing
+pub(crate) async fn fetch_accounts(
+    rpc_client: &RpcClient,
+    keys: &[Pubkey],
+    accounts: &mut HashMap<Pubkey, Vec<u8>>,
+) -> Result<()> {
+    let fetched = rpc_client.get_multiple_accounts(keys).await?;
+    for (key, account) in keys.iter().zip(fetched) {
+        let account = account.ok_or(Error::AccountNotFound { account: *key })?;
+        accounts.insert(*key, account.data);
+    }
+    Ok(())
+}
+
+/// Fetch any number of accounts into `accounts`, deduplicated and split into
+/// requests the RPC node accepts
+pub(crate) async fn fetch_accounts_batched(
+    rpc_client: &RpcClient,
+    mut keys: Vec<Pubkey>,
+    accounts: &mut HashMap<Pubkey, Vec<u8>>,
+) -> Result<()> {
+    keys.sort();
+    keys.dedup();
+    for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        fetch_accounts(rpc_client, chunk, accounts).await?;
+    }
+    Ok(())
+}
+
+// -----------------------------------
+// Account Decoding
+// -----------------------------------
+pub(crate) fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            Error::account(
+                "account data",
+                format!("need {} bytes, got {}", offset + N, data.len()),
+                data,
+            )
+        })
+}
+
+pub(crate) fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
+    Ok(read_bytes::<1>(data, offset)?[0])
+}
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_i32(data: &[u8], offset: usize) -> Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_i64(data: &[u8], offset: usize) -> Result<i64> {
+    Ok(i64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_u128(data: &[u8], offset: usize) -> Result<u128> {
+    Ok(u128::from_le_bytes(read_bytes(data, offset)?))
+}
+
+pub(crate) fn read_pubkey(data: &[u8], offset: usize) -> Result<Pubkey> {
+    Ok(Pubkey::new_from_array(read_bytes(data, offset)?))
+}
+
+/// The clock sysvar in `data`
+pub(crate) fn read_clock(data: &[u8]) -> Result<Clock> {
+    bincode::deserialize(data).map_err(|e| Error::account("clock sysvar", e.to_string(), data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_out_rounds_down() {
+        assert_eq!(min_out_amount(1_000_000, 50).unwrap(), 995_000);
+        // 999 * 9_950 / 10_000 = 994.005
+        assert_eq!(min_out_amount(999, 50).unwrap(), 994);
+        assert_eq!(min_out_amount(1, 1).unwrap(), 0);
+        assert_eq!(
+            min_out_amount(u64::MAX, 1).unwrap(),
+            18_444_899_399_302_180_659
+        );
+    }
+
+    #[test]
+    fn min_out_takes_slippage_from_zero_to_all() {
+        assert_eq!(min_out_amount(12_345, 0).unwrap(), 12_345);
+        assert_eq!(min_out_amount(12_345, 10_000).unwrap(), 0);
+        let error = min_out_amount(12_345, 10_001).unwrap_err();
+        assert!(matches!(error, Error::InvalidInput { .. }));
+    }
+
+    #[test]
+    fn finds_the_other_token_by_base_name_or_mark() {
+        let cheese = BaseToken::default();
+        assert_eq!(cheese.mark(), "🧀");
+        assert!(cheese.is_named("🧀") && cheese.is_named("cheese"));
+        assert!(!cheese.is_named("SOL"));
+        assert_eq!(parse_other_token_name("🧀-SOL", &cheese), "SOL");
+        assert_eq!(parse_other_token_name("USDC - CHEESE", &cheese), "USDC");
+        assert_eq!(parse_other_token_name("BONK-WIF", &cheese), "WIF");
+        assert_eq!(parse_other_token_name("DLMM", &cheese), "DLMM");
+
+        let bonk = BaseToken::new(Pubkey::new_unique(), "BONK", "");
+        assert_eq!(bonk.mark(), "BONK");
+        assert!(!bonk.is_named("🧀"));
+        assert_eq!(parse_other_token_name("Bonk-SOL", &bonk), "SOL");
+        assert_eq!(parse_other_token_name("🧀-BONK", &bonk), "🧀");
+    }
+}
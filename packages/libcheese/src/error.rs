@@ -0,0 +1,193 @@
+//! Errors from libcheese's API, RPC and trading calls.
+//!
+//! Each variant says what went wrong rather than only how to word it, so a
+//! caller can back off from a rate limit, skip a pool that can't be read or
+//! stop trading when a swap would come up short.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use reqwest::StatusCode;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::pubsub_client::PubsubClientError;
+use solana_sdk::pubkey::Pubkey;
+use std::num::TryFromIntError;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An API answered with an error status
+    #[error("{service} request failed: {status} {body}")]
+    Http {
+        service: &'static str,
+        status: StatusCode,
+        body: String,
+        /// How long the API asked us to wait before trying again
+        retry_after: Option<Duration>,
+    },
+    /// An API answered but said the call failed
+    #[error("{service} returned success=false")]
+    Api { service: &'static str },
+    /// A response or account didn't have the shape we expect
+    #[error("Failed to decode {what}: {reason}")]
+    Decode {
+        what: String,
+        reason: String,
+        /// What we were given, as text. Account data is base64 encoded.
+        payload: String,
+    },
+    /// An HTTP request got no answer
+    #[error("HTTP request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    /// A host failed too often lately to be called for now
+    #[error("Not calling {host} for another {retry_in:?} after repeated failures")]
+    CircuitOpen { host: String, retry_in: Duration },
+    #[error("RPC request failed: {0}")]
+    Rpc(Box<ClientError>),
+    #[error("Websocket subscription failed: {0}")]
+    Pubsub(Box<PubsubClientError>),
+    /// An account we need doesn't exist on chain
+    #[error("Account {account} not found")]
+    AccountNotFound { account: Pubkey },
+    /// A pool can't fill the swap as asked, from its current state
+    #[error("Can't quote swap: {reason}")]
+    Quote { reason: String },
+    /// A pool, program or feature we don't handle
+    #[error("{what} is not supported")]
+    Unsupported { what: String },
+    /// The caller asked for something that can't be done
+    #[error("Invalid input: {reason}")]
+    InvalidInput { reason: String },
+    /// A transaction couldn't be built, signed or checked before sending
+    #[error("Invalid transaction: {reason}")]
+    Transaction { reason: String },
+    /// The transaction fails in simulation
+    #[error("Simulation failed: {message}")]
+    Simulation { message: String, logs: Vec<String> },
+    /// The wallet holds less than a trade spends, in base units
+    #[error("Insufficient balance of {mint}: have {have}, need {need}")]
+    InsufficientBalance { mint: String, have: u64, need: u64 },
+    /// A swap would pay out less than its minimum, in base units
+    #[error("Slippage exceeded: expected at least {min_out}, got {out}")]
+    SlippageExceeded { min_out: u64, out: u64 },
+}
+
+impl Error {
+    /// The API asked us to slow down
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, Self::Http { status, .. } if *status == StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    /// The same call may well succeed if tried again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Http { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Request(e) => e.is_timeout() || e.is_connect(),
+            Self::Rpc(e) => matches!(
+                e.kind(),
+                ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_)
+            ),
+            _ => false,
+        }
+    }
+
+    /// `data` of a `what` account that couldn't be decoded
+    pub(crate) fn account(what: impl Into<String>, reason: impl Into<String>, data: &[u8]) -> Self {
+        Self::Decode {
+            what: what.into(),
+            reason: reason.into(),
+            payload: BASE64.encode(data),
+        }
+    }
+
+    pub(crate) fn quote(reason: impl Into<String>) -> Self {
+        Self::Quote {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn unsupported(what: impl Into<String>) -> Self {
+        Self::Unsupported { what: what.into() }
+    }
+
+    pub(crate) fn invalid(reason: impl Into<String>) -> Self {
+        Self::InvalidInput {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn transaction(reason: impl Into<String>) -> Self {
+        Self::Transaction {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(error: ClientError) -> Self {
+        Self::Rpc(Box::new(error))
+    }
+}
+
+/// Quote math that doesn't fit back into a token amount
+impl From<TryFromIntError> for Error {
+    fn from(_: TryFromIntError) -> Self {
+        Self::quote("Amount out of range")
+    }
+}
+
+impl From<PubsubClientError> for Error {
+    fn from(error: PubsubClientError) -> Self {
+        Self::Pubsub(Box::new(error))
+    }
+}
+
+/// Return `error` unless `condition` holds
+macro_rules! ensure {
+    ($condition:expr, $error:expr $(,)?) => {
+        if !$condition {
+            return Err($error);
+        }
+    };
+}
+pub(crate) use ensure;
+
+/// `value` as a public key, keeping the text when it isn't one
+pub(crate) fn parse_pubkey(value: &str) -> Result<Pubkey> {
+    Pubkey::from_str(value).map_err(|e| Error::Decode {
+        what: "public key".to_string(),
+        reason: e.to_string(),
+        payload: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_retryable_failures() {
+        let error = Error::Http {
+            service: "Test",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(error.is_rate_limited() && error.is_retryable());
+
+        let error = Error::Http {
+            service: "Test",
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(!error.is_rate_limited() && error.is_retryable());
+
+        assert!(!Error::quote("pool is closed").is_retryable());
+        let error = Error::account("Test pool", "too short", &[1, 2, 3]);
+        assert!(matches!(error, Error::Decode { ref payload, .. } if payload == "AQID"));
+    }
+}
@@ -0,0 +1,396 @@
+//! Shared HTTP client for the pool, price and swap APIs.
+//!
+//! Every request goes through a token bucket for its host, is retried with
+//! jittered exponential backoff when the failure looks transient (waiting as
+//! long as a `Retry-After` header asks, up to a cap) and is refused outright while the
+//! host's circuit breaker is open after a run of failures.
+
+use crate::error::{Error, Result};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, IntoUrl, Request, RequestBuilder};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, instrument, warn};
+
+/// How `HttpClient` paces, retries and gives up on requests
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// Timeout for each attempt, connecting included
+    pub timeout: Duration,
+    /// Attempts after the first before a transient failure is returned
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    /// Longest backoff between retries, unless the server asks for more
+    pub max_delay: Duration,
+    /// Longest wait a `Retry-After` header can ask for
+    pub max_retry_after: Duration,
+    /// Requests per second allowed to each host
+    pub requests_per_second: f64,
+    /// Requests a host may take at once after being idle
+    pub burst: u32,
+    /// `requests_per_second` overrides, by host name
+    pub host_rates: HashMap<String, f64>,
+    /// Failures in a row that open a host's circuit breaker
+    pub breaker_threshold: u32,
+    /// How long an open breaker refuses requests. Once it passes, requests
+    /// go through again until the next failure reopens it.
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+            requests_per_second: 5.0,
+            burst: 10,
+            host_rates: HashMap::new(),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A `reqwest::Client` that rate limits, retries and trips per host.
+/// Clones share their limits and breakers.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    settings: Arc<HttpSettings>,
+    hosts: Arc<Mutex<HashMap<String, HostState>>>,
+}
+
+impl HttpClient {
+    pub fn new(settings: HttpSettings) -> Result<Self> {
+        let client = Client::builder().timeout(settings.timeout).build()?;
+        Ok(Self {
+            client,
+            settings: Arc::new(settings),
+            hosts: Arc::default(),
+        })
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Send `request` to `service` and parse its body as `T`, retrying
+    /// transient failures
+    #[instrument(skip_all, fields(service))]
+    pub async fn json<T: DeserializeOwned>(
+        &self,
+        service: &'static str,
+        request: RequestBuilder,
+    ) -> Result<T> {
+        let request = request.build()?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        let mut attempt = 0;
+        loop {
+            self.admit(&host).await?;
+            let result = self.attempt(service, &request).await;
+            let failed = match &result {
+                Ok(_) => false,
+                // Being told to slow down says nothing about the host's health
+                Err(e) => e.is_retryable() && !e.is_rate_limited(),
+            };
+            self.record(&host, failed);
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(e) if e.is_retryable() && attempt < self.settings.max_retries => e,
+                Err(e) => return Err(e),
+            };
+            let delay = retry_delay(&self.settings, &error, attempt);
+            attempt += 1;
+            warn!(
+                %host,
+                error = %error,
+                delay_ms = delay.as_millis() as u64,
+                attempt,
+                max_retries = self.settings.max_retries,
+                "Retrying API request"
+            );
+            sleep(delay).await;
+        }
+    }
+
+    async fn attempt<T: DeserializeOwned>(
+        &self,
+        service: &'static str,
+        request: &Request,
+    ) -> Result<T> {
+        let request = request.try_clone().ok_or_else(|| Error::Decode {
+            what: format!("{} request", service),
+            reason: "body can't be resent".to_string(),
+            payload: request.url().to_string(),
+        })?;
+        let url = request.url().clone();
+        let started = Instant::now();
+        let resp = self.client.execute(request).await?;
+        let status = resp.status();
+        debug!(
+            host = url.host_str().unwrap_or_default(),
+            path = url.path(),
+            status = status.as_u16(),
+            latency_ms = started.elapsed().as_millis() as u64,
+            "API response"
+        );
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(Error::Http {
+                service,
+                status,
+                body,
+                retry_after,
+            });
+        }
+        serde_json::from_str(&body).map_err(|e| Error::Decode {
+            what: format!("{} response", service),
+            reason: e.to_string(),
+            payload: body,
+        })
+    }
+
+    /// Wait for `host`'s token bucket, or fail while its breaker is open
+    async fn admit(&self, host: &str) -> Result<()> {
+        let wait = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let state = hosts
+                .entry(host.to_string())
+                .or_insert_with(|| HostState::new(&self.settings, host));
+            let now = Instant::now();
+            if let Some(retry_in) = state.breaker.open_for(now) {
+                return Err(Error::CircuitOpen {
+                    host: host.to_string(),
+                    retry_in,
+                });
+            }
+            state.bucket.take(now)
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    fn record(&self, host: &str, failed: bool) {
+        if let Some(state) = self.hosts.lock().unwrap().get_mut(host) {
+            if state.breaker.record(Instant::now(), failed, &self.settings) {
+                warn!(
+                    host,
+                    cooldown_secs = self.settings.breaker_cooldown.as_secs(),
+                    "Circuit breaker open"
+                );
+            }
+        }
+    }
+}
+
+/// Delay before retry number `attempt + 1`: doubling from `base_delay`,
+/// capped at `max_delay`, then jittered down by up to half
+fn backoff(settings: &HttpSettings, attempt: u32) -> Duration {
+    let delay = settings
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(settings.max_delay);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Delay before retrying after `error`: what its `Retry-After` asked for,
+/// up to `max_retry_after`, or else the usual backoff
+fn retry_delay(settings: &HttpSettings, error: &Error, attempt: u32) -> Duration {
+    match error {
+        Error::Http {
+            retry_after: Some(retry_after),
+            ..
+        } => (*retry_after).min(settings.max_retry_after),
+        _ => backoff(settings, attempt),
+    }
+}
+
+// -----------------------------------
+// Per-host state
+// -----------------------------------
+
+struct HostState {
+    bucket: TokenBucket,
+    breaker: CircuitBreaker,
+}
+
+impl HostState {
+    fn new(settings: &HttpSettings, host: &str) -> Self {
+        let rate = settings
+            .host_rates
+            .get(host)
+            .copied()
+            .unwrap_or(settings.requests_per_second);
+        Self {
+            bucket: TokenBucket::new(rate, settings.burst, Instant::now()),
+            breaker: CircuitBreaker::default(),
+        }
+    }
+}
+
+/// Refills at `rate` tokens a second up to `capacity`. Callers take a token
+/// even when none is left and wait for the debt to refill, so concurrent
+/// callers queue up in order.
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    /// Take a token, returning how long to wait before using it
+    fn take(&mut self, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity) - 1.0;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Opens after `breaker_threshold` failures in a row. Once the cooldown is
+/// over requests go through again, but the next failure reopens it.
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// How much longer the breaker stays open, if it is
+    fn open_for(&self, now: Instant) -> Option<Duration> {
+        self.open_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Count a request's outcome, returning whether it opened the breaker
+    fn record(&mut self, now: Instant, failed: bool, settings: &HttpSettings) -> bool {
+        if !failed {
+            self.failures = 0;
+            self.open_until = None;
+            return false;
+        }
+        self.failures += 1;
+        if self.failures < settings.breaker_threshold.max(1) {
+            return false;
+        }
+        self.open_until = Some(now + settings.breaker_cooldown);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn bucket_spaces_requests_past_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2, start);
+        assert_eq!(bucket.take(start), Duration::ZERO);
+        assert_eq!(bucket.take(start), Duration::ZERO);
+        assert_eq!(bucket.take(start), Duration::from_millis(500));
+        assert_eq!(bucket.take(start), Duration::from_secs(1));
+        // Two seconds later the debt is paid off and the bucket is full
+        assert_eq!(bucket.take(start + Duration::from_secs(2)), Duration::ZERO);
+    }
+
+    #[test]
+    fn breaker_opens_after_repeated_failures() {
+        let settings = HttpSettings {
+            breaker_threshold: 2,
+            breaker_cooldown: Duration::from_secs(30),
+            ..HttpSettings::default()
+        };
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        assert!(!breaker.record(start, true, &settings));
+        assert_eq!(breaker.open_for(start), None);
+        assert!(breaker.record(start, true, &settings));
+        assert_eq!(breaker.open_for(start), Some(Duration::from_secs(30)));
+
+        // After the cooldown one more failure is enough to reopen it
+        let later = start + Duration::from_secs(31);
+        assert_eq!(breaker.open_for(later), None);
+        assert!(breaker.record(later, true, &settings));
+        assert!(breaker.open_for(later).is_some());
+        assert!(!breaker.record(later, false, &settings));
+        assert_eq!(breaker.open_for(later), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let settings = HttpSettings {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            ..HttpSettings::default()
+        };
+        for (attempt, full) in [(0, 100), (1, 200), (2, 400), (3, 500), (10, 500)] {
+            let delay = backoff(&settings, attempt);
+            assert!(delay <= Duration::from_millis(full));
+            assert!(delay >= Duration::from_millis(full / 2));
+        }
+    }
+
+    #[test]
+    fn caps_retry_after() {
+        let settings = HttpSettings {
+            max_retry_after: Duration::from_secs(60),
+            ..HttpSettings::default()
+        };
+        let rate_limited = |seconds| Error::Http {
+            service: "Test",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+            retry_after: Some(Duration::from_secs(seconds)),
+        };
+        assert_eq!(
+            retry_delay(&settings, &rate_limited(5), 0),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            retry_delay(&settings, &rate_limited(86_400), 0),
+            Duration::from_secs(60)
+        );
+    }
+}
@@ -1,14 +1,93 @@
-use anyhow::{anyhow, Result};
-use reqwest::Client;
-use serde::Deserialize;
+use crate::error::{Error, Result};
+use crate::http::HttpClient;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use tracing::{debug, instrument, warn};
+
+/// How much Jupiter trusts a price
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+/// A token's USD price along with the quotes and swaps behind it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenPrice {
+    pub price: f64,
+    /// USD paid per token when buying, and when Jupiter quoted it (unix seconds)
+    pub buy_price: Option<f64>,
+    pub buy_at: Option<u64>,
+    /// USD received per token when selling, and when Jupiter quoted it
+    pub sell_price: Option<f64>,
+    pub sell_at: Option<u64>,
+    /// Prices of the last swaps through Jupiter either way, and when they landed
+    pub last_buy_price: Option<f64>,
+    pub last_buy_at: Option<u64>,
+    pub last_sell_price: Option<f64>,
+    pub last_sell_at: Option<u64>,
+    /// `None` when Jupiter didn't say
+    pub confidence: Option<Confidence>,
+}
+
+impl TokenPrice {
+    /// Gap between the buy and sell quotes, as a fraction of the price
+    pub fn spread(&self) -> Option<f64> {
+        match (self.buy_price, self.sell_price) {
+            (Some(buy), Some(sell)) if self.price > 0.0 => Some((buy - sell) / self.price),
+            _ => None,
+        }
+    }
+
+    /// When the newer of the buy and sell quotes was taken
+    pub fn quoted_at(&self) -> Option<u64> {
+        self.buy_at.max(self.sell_at)
+    }
+
+    /// When the last swap either way landed
+    pub fn last_swap_at(&self) -> Option<u64> {
+        self.last_buy_at.max(self.last_sell_at)
+    }
+
+    /// Whether the quotes are missing or older than `max_age_secs` at `now`
+    pub fn is_stale(&self, now: u64, max_age_secs: u64) -> bool {
+        self.quoted_at()
+            .is_none_or(|at| now.saturating_sub(at) > max_age_secs)
+    }
+
+    fn from_item(price: f64, extra: Option<JupiterV2ExtraInfo>) -> Self {
+        let parse = |value: Option<String>| value.and_then(|v| v.parse::<f64>().ok());
+        let extra = extra.unwrap_or_default();
+        let quoted = extra.quotedPrice.unwrap_or_default();
+        let swapped = extra.lastSwappedPrice.unwrap_or_default();
+        Self {
+            price,
+            buy_price: parse(quoted.buyPrice),
+            buy_at: quoted.buyAt,
+            sell_price: parse(quoted.sellPrice),
+            sell_at: quoted.sellAt,
+            last_buy_price: parse(swapped.lastJupiterBuyPrice),
+            last_buy_at: swapped.lastJupiterBuyAt,
+            last_sell_price: parse(swapped.lastJupiterSellPrice),
+            last_sell_at: swapped.lastJupiterSellAt,
+            confidence: match extra.confidenceLevel.as_deref() {
+                Some("high") => Some(Confidence::High),
+                Some("medium") => Some(Confidence::Medium),
+                Some("low") => Some(Confidence::Low),
+                _ => None,
+            },
+        }
+    }
+}
 
 /// calls Jupiter v2 price endpoint with `showExtraInfo=true` for the given mints
-/// returns a map from mint -> float price (and ignores extra info).
+/// returns a map from mint -> price with its quotes and confidence.
+#[instrument(skip_all, fields(mints = mints.len()))]
 pub async fn fetch_jupiter_prices(
-    client: &Client,
+    client: &HttpClient,
     mints: &[String],
-) -> Result<HashMap<String, f64>> {
+) -> Result<HashMap<String, TokenPrice>> {
     if mints.is_empty() {
         return Ok(HashMap::new());
     }
@@ -19,36 +98,24 @@ pub async fn fetch_jupiter_prices(
         "https://api.jup.ag/price/v2?ids={}&showExtraInfo=true",
         joined
     );
-    println!("Fetching Jupiter v2 prices from: {}", url);
-
-    let resp = client.get(&url).send().await?;
-    if !resp.status().is_success() {
-        return Err(anyhow!(
-            "Jupiter v2 price request failed: {}",
-            resp.status()
-        ));
-    }
+    debug!(%url, "Fetching Jupiter v2 prices");
 
-    let parsed: JupiterV2PriceResponse = resp.json().await?;
+    let parsed: JupiterV2PriceResponse = client.json("Jupiter v2 price", client.get(&url)).await?;
 
-    // Now let's create a simpler map: mint -> price
     let mut result_map = HashMap::new();
 
     for (mint, maybe_item) in parsed.data {
         if let Some(item) = maybe_item {
             // item.price is a string, parse to f64
             if let Ok(val) = item.price.parse::<f64>() {
-                result_map.insert(mint, val);
+                result_map.insert(mint, TokenPrice::from_item(val, item.extraInfo));
             } else {
-                // If parse fails, store 0.0 or skip
-                println!(
-                    "Warning: Jupiter price for mint {} is not parseable: {:?}",
-                    mint, item.price
-                );
+                // If parse fails, skip the mint
+                warn!(%mint, price = ?item.price, "Jupiter price is not parseable");
             }
         } else {
             // the API returned null for this mint
-            println!("Jupiter returned null for mint {mint}");
+            debug!(%mint, "Jupiter returned no price");
         }
     }
 
@@ -59,22 +126,19 @@ pub async fn fetch_jupiter_prices(
 struct JupiterV2PriceResponse {
     // The top-level has a `data` object containing many mints
     data: HashMap<String, Option<JupiterV2PriceItem>>,
-    #[serde(default)]
-    timeTaken: f64, // optional
 }
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
 struct JupiterV2PriceItem {
-    id: String,
-    #[serde(default)]
-    r#type: String,
     // The "price" is returned as string - we can parse to f64
     price: String,
     #[serde(default)]
     extraInfo: Option<JupiterV2ExtraInfo>,
 }
 
-#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Deserialize)]
 struct JupiterV2ExtraInfo {
     #[serde(default)]
     lastSwappedPrice: Option<JupiterV2LastSwapped>,
@@ -85,7 +149,8 @@ struct JupiterV2ExtraInfo {
     // Depth or other fields omitted for brevity
 }
 
-#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Deserialize)]
 struct JupiterV2LastSwapped {
     #[serde(default)]
     lastJupiterSellAt: Option<u64>,
@@ -98,7 +163,8 @@ struct JupiterV2LastSwapped {
     // etc.
 }
 
-#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Deserialize)]
 struct JupiterV2QuotedPrice {
     #[serde(default)]
     buyPrice: Option<String>,
@@ -109,3 +175,237 @@ struct JupiterV2QuotedPrice {
     #[serde(default)]
     sellAt: Option<u64>,
 }
+
+// -----------------------------------
+// Quote and Swap API
+// -----------------------------------
+const JUPITER_SWAP_API: &str = "https://api.jup.ag/swap/v1";
+
+/// What to ask Jupiter's quote endpoint for
+#[derive(Debug, Clone)]
+pub struct JupiterQuoteParams {
+    pub input_mint: String,
+    pub output_mint: String,
+    /// Exact input, in base units
+    pub amount: u64,
+    pub slippage_bps: u64,
+    /// Only accept routes through a single pool
+    pub only_direct_routes: bool,
+    /// Only route through these DEXes, by Jupiter's labels (e.g. "Raydium")
+    pub dexes: Option<Vec<String>>,
+}
+
+impl JupiterQuoteParams {
+    pub fn new(input_mint: &str, output_mint: &str, amount: u64, slippage_bps: u64) -> Self {
+        Self {
+            input_mint: input_mint.to_string(),
+            output_mint: output_mint.to_string(),
+            amount,
+            slippage_bps,
+            only_direct_routes: false,
+            dexes: None,
+        }
+    }
+}
+
+/// Jupiter's best route for a swap. Sent back verbatim to build the swap
+/// transaction, so fields we don't read are kept in `extra`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JupiterQuote {
+    pub input_mint: String,
+    pub in_amount: String,
+    pub output_mint: String,
+    pub out_amount: String,
+    /// Least output the swap accepts after slippage
+    pub other_amount_threshold: String,
+    pub swap_mode: String,
+    pub slippage_bps: u64,
+    pub price_impact_pct: String,
+    pub route_plan: Vec<JupiterRoutePlanStep>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// One pool a route passes through, with the share of the input it takes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JupiterRoutePlanStep {
+    pub swap_info: JupiterSwapInfo,
+    pub percent: u8,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JupiterSwapInfo {
+    pub amm_key: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: String,
+    pub out_amount: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JupiterQuote {
+    pub fn out_amount(&self) -> Result<u64> {
+        parse_amount("outAmount", &self.out_amount)
+    }
+
+    pub fn min_out_amount(&self) -> Result<u64> {
+        parse_amount("otherAmountThreshold", &self.other_amount_threshold)
+    }
+
+    /// DEX labels of the pools the route passes through, in order
+    pub fn route_labels(&self) -> Vec<&str> {
+        self.route_plan
+            .iter()
+            .map(|step| step.swap_info.label.as_deref().unwrap_or("?"))
+            .collect()
+    }
+}
+
+/// Ask Jupiter for its best exact-input route
+#[instrument(skip(client))]
+pub async fn get_jupiter_quote(
+    client: &HttpClient,
+    params: &JupiterQuoteParams,
+) -> Result<JupiterQuote> {
+    let mut query = vec![
+        ("inputMint", params.input_mint.clone()),
+        ("outputMint", params.output_mint.clone()),
+        ("amount", params.amount.to_string()),
+        ("slippageBps", params.slippage_bps.to_string()),
+        ("swapMode", "ExactIn".to_string()),
+        ("onlyDirectRoutes", params.only_direct_routes.to_string()),
+    ];
+    if let Some(dexes) = &params.dexes {
+        query.push(("dexes", dexes.join(",")));
+    }
+
+    let request = client
+        .get(format!("{}/quote", JUPITER_SWAP_API))
+        .query(&query);
+    client.json("Jupiter quote", request).await
+}
+
+/// Fetch the unsigned, base64 encoded transaction for a Jupiter quote.
+/// SOL stays wrapped so every leg moves between token accounts.
+#[instrument(skip(client, quote))]
+pub async fn get_jupiter_swap_transaction(
+    client: &HttpClient,
+    quote: &JupiterQuote,
+    user_pubkey: &str,
+) -> Result<String> {
+    let swap_request = JupiterSwapRequest {
+        user_public_key: user_pubkey.to_string(),
+        quote_response: quote.clone(),
+        wrap_and_unwrap_sol: false,
+    };
+
+    let request = client
+        .post(format!("{}/swap", JUPITER_SWAP_API))
+        .json(&swap_request);
+    let swap: JupiterSwapResponse = client.json("Jupiter swap", request).await?;
+    Ok(swap.swap_transaction)
+}
+
+/// A quote amount, which Jupiter sends as a string
+fn parse_amount(field: &str, value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|e: std::num::ParseIntError| Error::Decode {
+            what: format!("Jupiter quote {}", field),
+            reason: e.to_string(),
+            payload: value.to_string(),
+        })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JupiterSwapRequest {
+    user_public_key: String,
+    quote_response: JupiterQuote,
+    wrap_and_unwrap_sol: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JupiterSwapResponse {
+    swap_transaction: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_quotes_and_confidence_from_extra_info() {
+        let item: JupiterV2PriceItem = serde_json::from_value(serde_json::json!({
+            "id": "A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump",
+            "type": "derivedPrice",
+            "price": "0.0000575",
+            "extraInfo": {
+                "lastSwappedPrice": {
+                    "lastJupiterSellAt": 1_700_000_100u64,
+                    "lastJupiterSellPrice": "0.0000570",
+                    "lastJupiterBuyAt": 1_700_000_050u64,
+                    "lastJupiterBuyPrice": "0.0000580"
+                },
+                "quotedPrice": {
+                    "buyPrice": "0.0000580",
+                    "buyAt": 1_700_000_200u64,
+                    "sellPrice": "0.0000569",
+                    "sellAt": 1_700_000_200u64
+                },
+                "confidenceLevel": "low"
+            }
+        }))
+        .unwrap();
+        let price = TokenPrice::from_item(item.price.parse().unwrap(), item.extraInfo);
+
+        assert_eq!(price.confidence, Some(Confidence::Low));
+        assert_eq!(price.last_swap_at(), Some(1_700_000_100));
+        assert!((price.spread().unwrap() - 0.0000011 / 0.0000575).abs() < 1e-9);
+        assert!(!price.is_stale(1_700_000_260, 60));
+        assert!(price.is_stale(1_700_000_261, 60));
+        assert!(TokenPrice::from_item(1.0, None).is_stale(0, 60));
+    }
+
+    #[test]
+    fn quotes_round_trip_fields_we_do_not_read() {
+        let json = serde_json::json!({
+            "inputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+            "inAmount": "1000000",
+            "outputMint": "A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump",
+            "outAmount": "17500000",
+            "otherAmountThreshold": "17412500",
+            "swapMode": "ExactIn",
+            "slippageBps": 50,
+            "priceImpactPct": "0.0012",
+            "routePlan": [{
+                "swapInfo": {
+                    "ammKey": "2rkTh46zo8wUvPJvACPTJ16RNUHEM9EZ1nLYkUxZEHkw",
+                    "label": "Meteora",
+                    "inputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                    "outputMint": "A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump",
+                    "inAmount": "1000000",
+                    "outAmount": "17500000",
+                    "feeAmount": "2500",
+                    "feeMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+                },
+                "percent": 100
+            }],
+            "contextSlot": 312345678,
+            "timeTaken": 0.01
+        });
+
+        let quote: JupiterQuote = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(quote.out_amount().unwrap(), 17_500_000);
+        assert_eq!(quote.min_out_amount().unwrap(), 17_412_500);
+        assert_eq!(quote.route_labels(), ["Meteora"]);
+        assert_eq!(serde_json::to_value(&quote).unwrap(), json);
+    }
+}
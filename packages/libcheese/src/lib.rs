@@ -1,5 +1,15 @@
+pub mod arbitrage;
 pub mod common;
+pub mod error;
+pub mod http;
 pub mod jupiter;
 pub mod meteora;
+pub mod orca;
+pub mod pricing;
+pub mod pumpfun;
 pub mod raydium;
 pub mod solana;
+pub mod stream;
+pub mod venue;
+
+pub use error::{Error, Result};
@@ -1,12 +1,39 @@
-use crate::common::{de_string_to_f64, CHEESE_MINT};
-use anyhow::{anyhow, Result};
-use reqwest::Client;
+use crate::common::{
+    de_string_to_f64, min_out_amount, swap_with_token_accounts, SwapInstructions, SwapQuote,
+    CHEESE_MINT,
+};
+use crate::error::{parse_pubkey, Error, Result};
+use crate::http::HttpClient;
+use crate::venue::{Fees, Pool, Venue};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::{debug, info, instrument, warn};
+
+pub mod dlmm;
+pub mod dynamic_amm;
+mod stable_swap;
+
+use dynamic_amm::{DynamicPool, PoolFees, PoolSnapshot, DYNAMIC_AMM_PROGRAM_ID};
 
 // -----------------------------------
 // Networking
 // -----------------------------------
-pub async fn fetch_meteora_cheese_pools(client: &Client) -> Result<Vec<MeteoraPool>> {
+pub async fn fetch_meteora_cheese_pools(client: &HttpClient) -> Result<Vec<MeteoraPool>> {
+    let pools = fetch_meteora_pools(client, &parse_pubkey(CHEESE_MINT)?).await?;
+    info!(
+        venue = "Meteora",
+        pools = pools.len(),
+        "Fetched CHEESE pools"
+    );
+    Ok(pools)
+}
+
+/// Every dynamic AMM pool trading `mint`, as listed by Meteora's API
+pub async fn fetch_meteora_pools(client: &HttpClient, mint: &Pubkey) -> Result<Vec<MeteoraPool>> {
     let base_url = "https://amm-v2.meteora.ag";
     let search_url = format!("{}/pools/search", base_url);
 
@@ -15,27 +42,20 @@ pub async fn fetch_meteora_cheese_pools(client: &Client) -> Result<Vec<MeteoraPo
     let size = 50;
 
     loop {
-        println!("Requesting page {page} from {search_url}");
-        let resp = client
-            .get(&search_url)
-            .query(&[
-                ("page".to_string(), page.to_string()),
-                ("size".to_string(), size.to_string()),
-                ("include_token_mints".to_string(), CHEESE_MINT.to_string()),
-            ])
-            .send()
-            .await?;
-
-        if !resp.status().is_success() {
-            return Err(anyhow!("Meteora request failed: {}", resp.status()));
-        }
-
-        let parsed: PaginatedPoolSearchResponse = resp.json().await?;
-        println!(
-            "Got {} pools on page {}, total_count={}",
-            parsed.data.len(),
-            parsed.page,
-            parsed.total_count
+        debug!(venue = "Meteora", %mint, page, "Requesting pools page");
+        let request = client.get(&search_url).query(&[
+            ("page".to_string(), page.to_string()),
+            ("size".to_string(), size.to_string()),
+            ("include_token_mints".to_string(), mint.to_string()),
+        ]);
+        let parsed: PaginatedPoolSearchResponse =
+            client.json("Meteora pool search", request).await?;
+        debug!(
+            venue = "Meteora",
+            page = parsed.page,
+            pools = parsed.data.len(),
+            count = parsed.total_count,
+            "Got pools page"
         );
 
         all_pools.extend(parsed.data);
@@ -47,11 +67,6 @@ pub async fn fetch_meteora_cheese_pools(client: &Client) -> Result<Vec<MeteoraPo
         page += 1;
     }
 
-    println!(
-        "\nFetched a total of {} Cheese pools from Meteora.\n",
-        all_pools.len()
-    );
-
     Ok(all_pools)
 }
 
@@ -94,75 +109,58 @@ pub struct PaginatedPoolSearchResponse {
 // -----------------------------------
 // Trading
 // -----------------------------------
+/// Quote a swap from the pool's on-chain state. Amounts are in base units
+/// and match what the swap instruction would pay out at the fetched slot.
 pub async fn get_meteora_quote(
-    client: &Client,
+    rpc_client: &RpcClient,
     pool_address: &str,
     input_mint: &str,
     output_mint: &str,
     amount_in: u64,
+    slippage_bps: u64,
 ) -> Result<MeteoraQuoteResponse> {
-    // Get current pool state
-    let pool = fetch_pool_state(client, pool_address).await?;
-
-    // Find the indices for input and output tokens
-    let (in_idx, out_idx) = if pool.pool_token_mints[0] == input_mint {
-        (0, 1)
+    let snapshot = PoolSnapshot::fetch(rpc_client, &parse_pubkey(pool_address)?).await?;
+    let input = parse_pubkey(input_mint)?;
+    let output = parse_pubkey(output_mint)?;
+
+    let pool = &snapshot.pool;
+    let (token_a_amount, token_b_amount) = snapshot.reserves()?;
+    let (in_reserve, out_reserve) = if input == pool.token_a_mint && output == pool.token_b_mint {
+        (token_a_amount, token_b_amount)
+    } else if input == pool.token_b_mint && output == pool.token_a_mint {
+        (token_b_amount, token_a_amount)
     } else {
-        (1, 0)
+        return Err(Error::invalid(format!(
+            "Pool {} does not trade {} -> {}",
+            pool_address, input_mint, output_mint
+        )));
     };
 
-    // Parse pool amounts
-    let in_amount_pool: f64 = pool.pool_token_amounts[in_idx].parse()?;
-    let out_amount_pool: f64 = pool.pool_token_amounts[out_idx].parse()?;
-
-    // Calculate fee
-    let fee_pct: f64 = pool.total_fee_pct.trim_end_matches('%').parse::<f64>()? / 100.0;
-    let amount_in_after_fee = amount_in as f64 * (1.0 - fee_pct);
+    let quote = snapshot.quote(&input, amount_in)?;
 
-    // Calculate out amount using constant product formula: (x + Δx)(y - Δy) = xy
-    let amount_out =
-        (out_amount_pool * amount_in_after_fee) / (in_amount_pool + amount_in_after_fee);
-    let fee_amount = (amount_in as f64 * fee_pct) as u64;
-
-    // Calculate price impact
-    let price_before = out_amount_pool / in_amount_pool;
-    let price_after = (out_amount_pool - amount_out) / (in_amount_pool + amount_in as f64);
+    // Execution price against the spot price implied by the reserves
+    let price_before = out_reserve as f64 / in_reserve as f64;
+    let price_after = quote.out_amount as f64 / amount_in as f64;
     let price_impact = ((price_before - price_after) / price_before * 100.0).to_string();
 
+    // Lowest output we accept once the swap lands
+    let min_out_amount = min_out_amount(quote.out_amount, slippage_bps)?;
+
     Ok(MeteoraQuoteResponse {
         pool_address: pool_address.to_string(),
         input_mint: input_mint.to_string(),
         output_mint: output_mint.to_string(),
         in_amount: amount_in.to_string(),
-        out_amount: amount_out.to_string(),
-        fee_amount: fee_amount.to_string(),
+        out_amount: quote.out_amount.to_string(),
+        min_out_amount: min_out_amount.to_string(),
+        slippage_bps,
+        fee_amount: quote.fee.to_string(),
         price_impact,
     })
 }
 
-async fn fetch_pool_state(client: &Client, pool_address: &str) -> Result<MeteoraPool> {
-    let base_url = "https://amm-v2.meteora.ag";
-    let pools_url = format!("{}/pools", base_url);
-
-    let resp = client
-        .get(&pools_url)
-        .query(&[("address", &[pool_address.to_string()])])
-        .send()
-        .await?;
-
-    if !resp.status().is_success() {
-        return Err(anyhow!("Failed to fetch pool state: {}", resp.status()));
-    }
-
-    let pools: Vec<MeteoraPool> = resp.json().await?;
-    pools
-        .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("Pool not found: {}", pool_address))
-}
-
 pub async fn get_meteora_swap_transaction(
-    client: &Client,
+    client: &HttpClient,
     quote: &MeteoraQuoteResponse,
     user_pubkey: &str,
 ) -> Result<String> {
@@ -174,16 +172,10 @@ pub async fn get_meteora_swap_transaction(
         quote_response: quote.clone(),
     };
 
-    // Log the swap request
-    println!("Sending Meteora swap request: {:?}", swap_request);
-
-    let resp = client.post(&swap_url).json(&swap_request).send().await?;
+    debug!(request = ?swap_request, "Sending Meteora swap request");
 
-    if !resp.status().is_success() {
-        return Err(anyhow!("Meteora swap request failed: {}", resp.status()));
-    }
-
-    let swap: MeteoraSwapResponse = resp.json().await?;
+    let request = client.post(&swap_url).json(&swap_request);
+    let swap: MeteoraSwapResponse = client.json("Meteora swap", request).await?;
     Ok(swap.transaction)
 }
 
@@ -194,6 +186,8 @@ pub struct MeteoraQuoteResponse {
     pub output_mint: String,
     pub in_amount: String,
     pub out_amount: String,
+    pub min_out_amount: String,
+    pub slippage_bps: u64,
     pub fee_amount: String,
     pub price_impact: String,
 }
@@ -208,3 +202,181 @@ struct MeteoraSwapRequest {
 struct MeteoraSwapResponse {
     transaction: String,
 }
+
+// -----------------------------------
+// Venue
+// -----------------------------------
+
+/// Meteora dynamic AMM pools, listed by Meteora's API and read from chain
+pub struct MeteoraVenue<'a> {
+    client: &'a HttpClient,
+    rpc_client: &'a RpcClient,
+}
+
+impl<'a> MeteoraVenue<'a> {
+    pub fn new(client: &'a HttpClient, rpc_client: &'a RpcClient) -> Self {
+        Self { client, rpc_client }
+    }
+
+    /// Normalize a pool from its API listing and on-chain snapshot
+    fn pool(
+        &self,
+        listing: &MeteoraPool,
+        address: Pubkey,
+        snapshot: &PoolSnapshot,
+        decimals: [u8; 2],
+    ) -> Result<Pool> {
+        let (token_a_amount, token_b_amount) = snapshot.reserves()?;
+        let mut pool = Pool {
+            address,
+            source: self.name(),
+            pool_type: listing.pool_type.clone(),
+            program_id: DYNAMIC_AMM_PROGRAM_ID,
+            mints: [snapshot.pool.token_a_mint, snapshot.pool.token_b_mint],
+            decimals,
+            reserves: [token_a_amount, token_b_amount],
+            price: 0.0,
+            fees: pool_fees(&snapshot.pool.fees),
+            symbols: [None, None],
+            tvl_usd: Some(listing.pool_tvl),
+            volume_24h_usd: Some(listing.daily_volume),
+            derived: listing.derived,
+        };
+        // The price moves along the curve, but the reserve ratio is what the
+        // pool is quoted at for small trades
+        pool.price = pool.ui_reserve(1) / pool.ui_reserve(0);
+        // Pool names read "A-B" in mint order
+        if let Some((a, b)) = listing.pool_name.split_once('-') {
+            pool.symbols = [Some(a.trim().to_string()), Some(b.trim().to_string())];
+        }
+        Ok(pool)
+    }
+}
+
+/// Trade fee and protocol fee are both charged on the input
+fn pool_fees(fees: &PoolFees) -> Fees {
+    let ratio = |numerator: u64, denominator: u64| {
+        if denominator == 0 {
+            0.0
+        } else {
+            numerator as f64 / denominator as f64
+        }
+    };
+    let lp_fee = ratio(fees.trade_fee_numerator, fees.trade_fee_denominator);
+    let protocol_fee = ratio(
+        fees.protocol_trade_fee_numerator,
+        fees.protocol_trade_fee_denominator,
+    );
+    let trade_fee = lp_fee + protocol_fee;
+    Fees {
+        trade_fee,
+        protocol_share: if trade_fee > 0.0 {
+            protocol_fee / trade_fee
+        } else {
+            0.0
+        },
+        dynamic: false,
+    }
+}
+
+#[async_trait]
+impl Venue for MeteoraVenue<'_> {
+    fn name(&self) -> &'static str {
+        "Meteora"
+    }
+
+    #[instrument(skip_all, fields(venue = "Meteora", %mint))]
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>> {
+        let listings = fetch_meteora_pools(self.client, mint).await?;
+        let mut by_address = HashMap::new();
+        for listing in &listings {
+            match Pubkey::from_str(&listing.pool_address) {
+                Ok(address) => {
+                    by_address.insert(address, listing);
+                }
+                Err(e) => {
+                    warn!(pool = %listing.pool_address, error = %e, "Skipping Meteora pool")
+                }
+            }
+        }
+
+        let addresses: Vec<Pubkey> = by_address.keys().copied().collect();
+        let mut pools = Vec::new();
+        for (address, snapshot, decimals) in
+            PoolSnapshot::fetch_many(self.rpc_client, &addresses).await?
+        {
+            match self.pool(by_address[&address], address, &snapshot, decimals) {
+                Ok(pool) => pools.push(pool),
+                Err(e) => warn!(pool = %address, error = %e, "Skipping Meteora pool"),
+            }
+        }
+        Ok(pools)
+    }
+
+    async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+        let snapshot = PoolSnapshot::fetch(self.rpc_client, &pool.address).await?;
+        let (token_a_amount, token_b_amount) = snapshot.reserves()?;
+        let mut refreshed = pool.clone();
+        refreshed.reserves = [token_a_amount, token_b_amount];
+        refreshed.price = refreshed.ui_reserve(1) / refreshed.ui_reserve(0);
+        refreshed.fees = pool_fees(&snapshot.pool.fees);
+        Ok(refreshed)
+    }
+
+    async fn watch_accounts(&self, pool: &Pool) -> Result<Vec<Pubkey>> {
+        // Swaps move tokens through the vaults and their LP through the
+        // pool's LP accounts, which is all the reserves are made of
+        let data = self.rpc_client.get_account_data(&pool.address).await?;
+        let pool = DynamicPool::decode(&data)?;
+        Ok(vec![
+            pool.a_vault,
+            pool.b_vault,
+            pool.a_vault_lp,
+            pool.b_vault_lp,
+        ])
+    }
+
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote> {
+        PoolSnapshot::fetch(self.rpc_client, &pool.address)
+            .await?
+            .quote(input_mint, amount_in)
+    }
+
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<SwapInstructions> {
+        let side = pool.side(input_mint).ok_or_else(|| {
+            Error::invalid(format!(
+                "Pool {} does not trade {}",
+                pool.address, input_mint
+            ))
+        })?;
+        let snapshot = PoolSnapshot::fetch(self.rpc_client, &pool.address).await?;
+        let quote = snapshot.quote(input_mint, amount_in)?;
+        swap_with_token_accounts(
+            self.rpc_client,
+            owner,
+            input_mint,
+            &side.other_mint,
+            quote,
+            slippage_bps,
+            |input_account, output_account, min_out| {
+                snapshot.swap_instruction(
+                    &pool.address,
+                    owner,
+                    input_mint,
+                    input_account,
+                    output_account,
+                    amount_in,
+                    min_out,
+                )
+            },
+        )
+        .await
+    }
+}
@@ -0,0 +1,943 @@
+//! On-chain state and exact quoting for Meteora DLMM pairs.
+//!
+//! A DLMM pair spreads its liquidity over discrete price bins, grouped 70 to
+//! a bin array account. Only the active bin holds both tokens; a swap drains
+//! it and moves on to the next bin in the direction of the trade. Quotes
+//! replay that walk bin by bin with the pair's base and volatility fees and
+//! the program's rounding, so they match what the swap instruction pays out.
+//! Swaps pass the bin arrays the quote walked as remaining accounts.
+
+use super::stable_swap::U192;
+use crate::common::{
+    fetch_accounts, fetch_accounts_batched, read_clock, read_i32, read_i64, read_pubkey, read_u128,
+    read_u16, read_u32, read_u64, read_u8, swap_with_token_accounts, SwapInstructions, SwapQuote,
+    CHEESE_MINT, TOKEN_2022_PROGRAM_ID,
+};
+use crate::error::{ensure, parse_pubkey, Error, Result};
+use crate::venue::{Fees, Pool, Venue};
+use async_trait::async_trait;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    sysvar,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use tracing::{info, instrument};
+
+pub const DLMM_PROGRAM_ID: Pubkey = pubkey!("LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo");
+/// PDA seeded with `b"__event_authority"` that Anchor emits events through
+pub const DLMM_EVENT_AUTHORITY: Pubkey = pubkey!("D1ZN9Wj1fRSUQfCjhvnu1hqDMT7hzjzBBpi12nVniYD6");
+
+const LB_PAIR_DISCRIMINATOR: [u8; 8] = [33, 11, 49, 98, 181, 101, 177, 13];
+const BIN_ARRAY_DISCRIMINATOR: [u8; 8] = [92, 142, 92, 220, 5, 148, 70, 181];
+const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+const LB_PAIR_LEN: u64 = 904;
+
+const BINS_PER_ARRAY: i64 = 70;
+const BIN_ARRAY_HEADER_LEN: usize = 56;
+const BIN_LEN: usize = 144;
+const MAX_BIN_ID: i64 = 443_636;
+const MIN_BIN_ID: i64 = -443_636;
+/// The pair's own bitmap tracks bin arrays -512..=511; the rest live in an
+/// extension account we don't read
+const BITMAP_RANGE: RangeInclusive<i64> = -512..=511;
+/// Bin arrays loaded on each side of the active one
+const BIN_ARRAYS_PER_SIDE: usize = 12;
+
+/// Fee rates are numerators over this
+const FEE_PRECISION: u128 = 1_000_000_000;
+const MAX_FEE_RATE: u128 = 100_000_000;
+const BASIS_POINT_MAX: u128 = 10_000;
+/// Scales the squared volatility term of the variable fee
+const VARIABLE_FEE_DENOMINATOR: u128 = 100_000_000_000;
+/// Bin prices are Q64.64 fixed point
+const SCALE_OFFSET: usize = 64;
+
+// -----------------------------------
+// Pair and Bin Accounts
+// -----------------------------------
+#[derive(Debug, Clone)]
+pub struct LbPair {
+    pub parameters: StaticParameters,
+    pub v_parameters: VariableParameters,
+    pub active_id: i32,
+    pub bin_step: u16,
+    pub status: u8,
+    pub token_x_mint: Pubkey,
+    pub token_y_mint: Pubkey,
+    pub reserve_x: Pubkey,
+    pub reserve_y: Pubkey,
+    pub oracle: Pubkey,
+    /// One bit per bin array that exists, for arrays -512..=511
+    pub bin_array_bitmap: [u64; 16],
+    pub token_x_program: Pubkey,
+    pub token_y_program: Pubkey,
+}
+
+/// Fee settings fixed when the pair is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticParameters {
+    pub base_factor: u16,
+    pub filter_period: u16,
+    pub decay_period: u16,
+    pub reduction_factor: u16,
+    pub variable_fee_control: u32,
+    pub max_volatility_accumulator: u32,
+    pub protocol_share: u16,
+    pub base_fee_power_factor: u8,
+    /// 0 takes the fee from the input. 1 collects it in token Y, so X to Y
+    /// swaps pay it out of the output.
+    pub collect_fee_mode: u8,
+}
+
+/// Volatility state the variable fee is computed from, updated by every swap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VariableParameters {
+    pub volatility_accumulator: u32,
+    pub volatility_reference: u32,
+    pub index_reference: i32,
+    pub last_update_timestamp: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bin {
+    pub amount_x: u64,
+    pub amount_y: u64,
+    /// Price of X in Y, Q64.64
+    pub price: u128,
+    pub open_order_amount: u64,
+    pub processed_order_remaining_amount: u64,
+    /// Resting limit orders sell X when set, Y otherwise
+    pub limit_order_ask_side: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BinArray {
+    pub index: i64,
+    pub lb_pair: Pubkey,
+    pub bins: Vec<Bin>,
+}
+
+impl LbPair {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&LB_PAIR_DISCRIMINATOR),
+            Error::account("Meteora DLMM pair", "wrong discriminator", data)
+        );
+        let mut bin_array_bitmap = [0; 16];
+        for (i, word) in bin_array_bitmap.iter_mut().enumerate() {
+            *word = read_u64(data, 584 + i * 8)?;
+        }
+
+        Ok(Self {
+            parameters: StaticParameters {
+                base_factor: read_u16(data, 8)?,
+                filter_period: read_u16(data, 10)?,
+                decay_period: read_u16(data, 12)?,
+                reduction_factor: read_u16(data, 14)?,
+                variable_fee_control: read_u32(data, 16)?,
+                max_volatility_accumulator: read_u32(data, 20)?,
+                protocol_share: read_u16(data, 32)?,
+                base_fee_power_factor: read_u8(data, 34)?,
+                collect_fee_mode: match read_u8(data, 36)? {
+                    mode @ (0 | 1) => mode,
+                    other => {
+                        return Err(Error::account(
+                            "Meteora DLMM pair",
+                            format!("unknown fee collection mode {}", other),
+                            data,
+                        ))
+                    }
+                },
+            },
+            v_parameters: VariableParameters {
+                volatility_accumulator: read_u32(data, 40)?,
+                volatility_reference: read_u32(data, 44)?,
+                index_reference: read_i32(data, 48)?,
+                last_update_timestamp: read_i64(data, 56)?,
+            },
+            active_id: read_i32(data, 76)?,
+            bin_step: read_u16(data, 80)?,
+            status: read_u8(data, 82)?,
+            token_x_mint: read_pubkey(data, 88)?,
+            token_y_mint: read_pubkey(data, 120)?,
+            reserve_x: read_pubkey(data, 152)?,
+            reserve_y: read_pubkey(data, 184)?,
+            oracle: read_pubkey(data, 552)?,
+            bin_array_bitmap,
+            token_x_program: token_program(read_u8(data, 880)?)?,
+            token_y_program: token_program(read_u8(data, 881)?)?,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Price of one base unit of X in base units of Y at the active bin
+    pub fn price(&self) -> f64 {
+        (1.0 + self.bin_step as f64 / BASIS_POINT_MAX as f64).powi(self.active_id)
+    }
+
+    /// Base fee rate over `FEE_PRECISION`, charged whatever the volatility
+    pub fn base_fee_rate(&self) -> u128 {
+        self.parameters.base_factor as u128
+            * self.bin_step as u128
+            * 10
+            * 10u128.pow(self.parameters.base_fee_power_factor as u32)
+    }
+
+    /// Total fee rate for the given volatility state, capped by the program
+    fn total_fee_rate(&self, v_parameters: &VariableParameters) -> u128 {
+        let volatility = v_parameters.volatility_accumulator as u128 * self.bin_step as u128;
+        let variable_fee = (self.parameters.variable_fee_control as u128 * volatility * volatility)
+            .div_ceil(VARIABLE_FEE_DENOMINATOR);
+        (self.base_fee_rate() + variable_fee).min(MAX_FEE_RATE)
+    }
+
+    /// Decay the volatility reference by the time since the last swap, as the
+    /// swap instruction does before walking any bins
+    fn update_references(&self, v_parameters: &mut VariableParameters, current_time: i64) {
+        let elapsed = current_time - v_parameters.last_update_timestamp;
+        if elapsed < self.parameters.filter_period as i64 {
+            return;
+        }
+        v_parameters.index_reference = self.active_id;
+        v_parameters.volatility_reference = if elapsed < self.parameters.decay_period as i64 {
+            (v_parameters.volatility_accumulator as u128 * self.parameters.reduction_factor as u128
+                / BASIS_POINT_MAX) as u32
+        } else {
+            0
+        };
+    }
+
+    /// Grow volatility with the distance the swap has moved from the reference bin
+    fn update_volatility_accumulator(&self, v_parameters: &mut VariableParameters, bin_id: i64) {
+        let distance = (v_parameters.index_reference as i64 - bin_id).unsigned_abs() as u128;
+        let accumulator = v_parameters.volatility_reference as u128 + distance * BASIS_POINT_MAX;
+        v_parameters.volatility_accumulator =
+            accumulator.min(self.parameters.max_volatility_accumulator as u128) as u32;
+    }
+
+    fn has_bin_array(&self, index: i64) -> bool {
+        if !BITMAP_RANGE.contains(&index) {
+            return false;
+        }
+        let bit = (index - BITMAP_RANGE.start()) as usize;
+        self.bin_array_bitmap[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    /// Up to `limit` existing bin arrays past `from`, nearest first
+    fn bin_arrays_from(&self, from: i64, step: i64, limit: usize) -> Vec<i64> {
+        std::iter::successors(Some(from + step), |index| Some(index + step))
+            .take_while(|index| BITMAP_RANGE.contains(index))
+            .filter(|index| self.has_bin_array(*index))
+            .take(limit)
+            .collect()
+    }
+}
+
+/// Token program a pair's program flag stands for
+fn token_program(flag: u8) -> Result<Pubkey> {
+    match flag {
+        0 => Ok(spl_token::id()),
+        1 => Ok(TOKEN_2022_PROGRAM_ID),
+        other => Err(Error::unsupported(format!(
+            "DLMM token program flag {}",
+            other
+        ))),
+    }
+}
+
+impl Bin {
+    fn decode(data: &[u8], offset: usize) -> Result<Self> {
+        Ok(Self {
+            amount_x: read_u64(data, offset)?,
+            amount_y: read_u64(data, offset + 8)?,
+            price: read_u128(data, offset + 16)?,
+            open_order_amount: read_u64(data, offset + 112)?,
+            processed_order_remaining_amount: read_u64(data, offset + 128)?,
+            limit_order_ask_side: read_u8(data, offset + 140)? != 0,
+        })
+    }
+
+    /// Output-token liquidity in the order a swap fills it: the bin's own
+    /// reserve, then limit orders already being processed, then open ones
+    fn liquidity(&self, swap_for_y: bool) -> [u64; 3] {
+        let reserve = if swap_for_y {
+            self.amount_y
+        } else {
+            self.amount_x
+        };
+        // Bid orders pay out Y, so only they can fill an X to Y swap
+        if self.limit_order_ask_side == swap_for_y {
+            return [reserve, 0, 0];
+        }
+        [
+            reserve,
+            self.processed_order_remaining_amount,
+            self.open_order_amount,
+        ]
+    }
+}
+
+impl BinArray {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&BIN_ARRAY_DISCRIMINATOR),
+            Error::account("Meteora DLMM bin array", "wrong discriminator", data)
+        );
+        let bins = (0..BINS_PER_ARRAY as usize)
+            .map(|i| Bin::decode(data, BIN_ARRAY_HEADER_LEN + i * BIN_LEN))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            index: read_i64(data, 8)?,
+            lb_pair: read_pubkey(data, 24)?,
+            bins,
+        })
+    }
+}
+
+/// Index of the bin array holding `bin_id`. Negative ids round down.
+pub fn bin_array_index(bin_id: i64) -> i64 {
+    bin_id.div_euclid(BINS_PER_ARRAY)
+}
+
+pub fn bin_array_address(lb_pair: &Pubkey, index: i64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"bin_array", lb_pair.as_ref(), &index.to_le_bytes()],
+        &DLMM_PROGRAM_ID,
+    )
+    .0
+}
+
+// -----------------------------------
+// Discovery
+// -----------------------------------
+
+/// A DLMM pair, its reserves and token decimals
+#[derive(Debug, Clone)]
+pub struct DlmmPair {
+    pub address: Pubkey,
+    pub pair: LbPair,
+    pub reserve_x_amount: u64,
+    pub reserve_y_amount: u64,
+    pub decimals_x: u8,
+    pub decimals_y: u8,
+}
+
+impl DlmmPair {
+    /// Price of one whole X in whole Y at the active bin
+    pub fn ui_price(&self) -> f64 {
+        self.pair.price() * 10f64.powi(self.decimals_x as i32 - self.decimals_y as i32)
+    }
+}
+
+/// Every DLMM pair trading CHEESE
+pub async fn fetch_dlmm_cheese_pairs(rpc_client: &RpcClient) -> Result<Vec<DlmmPair>> {
+    let pairs = fetch_dlmm_pairs(rpc_client, &parse_pubkey(CHEESE_MINT)?).await?;
+    info!(
+        venue = "Meteora DLMM",
+        pairs = pairs.len(),
+        "Fetched CHEESE pairs"
+    );
+    Ok(pairs)
+}
+
+/// Every DLMM pair trading `mint`, found by scanning the program's pair
+/// accounts for the mint on either side
+pub async fn fetch_dlmm_pairs(rpc_client: &RpcClient, mint: &Pubkey) -> Result<Vec<DlmmPair>> {
+    let mut pairs = Vec::new();
+    for mint_offset in [88, 120] {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(LB_PAIR_LEN),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(mint_offset, mint.as_ref())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = rpc_client
+            .get_program_accounts_with_config(&DLMM_PROGRAM_ID, config)
+            .await?;
+        for (address, account) in accounts {
+            pairs.push((address, LbPair::decode(&account.data)?));
+        }
+    }
+    load_pairs(rpc_client, pairs).await
+}
+
+/// Attach reserves and decimals to decoded pairs
+async fn load_pairs(rpc_client: &RpcClient, pairs: Vec<(Pubkey, LbPair)>) -> Result<Vec<DlmmPair>> {
+    let keys = pairs
+        .iter()
+        .flat_map(|(_, pair)| {
+            [
+                pair.reserve_x,
+                pair.reserve_y,
+                pair.token_x_mint,
+                pair.token_y_mint,
+            ]
+        })
+        .collect();
+    let mut accounts = HashMap::new();
+    fetch_accounts_batched(rpc_client, keys, &mut accounts).await?;
+
+    pairs
+        .into_iter()
+        .map(|(address, pair)| {
+            Ok(DlmmPair {
+                address,
+                reserve_x_amount: read_u64(&accounts[&pair.reserve_x], 64)?,
+                reserve_y_amount: read_u64(&accounts[&pair.reserve_y], 64)?,
+                decimals_x: read_u8(&accounts[&pair.token_x_mint], 44)?,
+                decimals_y: read_u8(&accounts[&pair.token_y_mint], 44)?,
+                pair,
+            })
+        })
+        .collect()
+}
+
+// -----------------------------------
+// Quoting
+// -----------------------------------
+
+/// A pair and the bin arrays around its active bin, read at one point in time
+#[derive(Debug, Clone)]
+pub struct DlmmSnapshot {
+    pub address: Pubkey,
+    pub pair: LbPair,
+    pub bin_arrays: BTreeMap<i64, BinArray>,
+    /// Bin arrays whose contents are known, loaded or known not to exist
+    pub known_arrays: RangeInclusive<i64>,
+    pub current_time: i64,
+}
+
+impl DlmmSnapshot {
+    /// Read the pair, the clock and the bin arrays nearest the active bin
+    pub async fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self> {
+        let pair_data = rpc_client.get_account_data(address).await?;
+        let pair = LbPair::decode(&pair_data)?;
+
+        let active = bin_array_index(pair.active_id as i64);
+        let mut keys = vec![sysvar::clock::ID];
+        keys.extend(
+            std::iter::once(active)
+                .filter(|index| pair.has_bin_array(*index))
+                .chain(pair.bin_arrays_from(active, -1, BIN_ARRAYS_PER_SIDE))
+                .chain(pair.bin_arrays_from(active, 1, BIN_ARRAYS_PER_SIDE))
+                .map(|index| bin_array_address(address, index)),
+        );
+        let mut accounts = HashMap::new();
+        fetch_accounts(rpc_client, &keys, &mut accounts).await?;
+
+        let clock = read_clock(&accounts[&sysvar::clock::ID])?;
+        Self::from_accounts(*address, pair, &accounts, clock.unix_timestamp)
+    }
+
+    /// Assemble a snapshot from already fetched account data. Bin arrays are
+    /// taken outwards from the active one until the first that's missing.
+    pub fn from_accounts(
+        address: Pubkey,
+        pair: LbPair,
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+        current_time: i64,
+    ) -> Result<Self> {
+        let active = bin_array_index(pair.active_id as i64);
+        ensure!(
+            BITMAP_RANGE.contains(&active),
+            Error::quote(format!(
+                "Active bin array {} is outside the pair's bitmap",
+                active
+            ))
+        );
+
+        let mut bin_arrays = BTreeMap::new();
+        let mut bounds = [active, active];
+        for (bound, step) in bounds.iter_mut().zip([-1, 1]) {
+            let indices = std::iter::once(active)
+                .filter(|index| pair.has_bin_array(*index))
+                .chain(pair.bin_arrays_from(active, step, usize::MAX));
+            let mut exhausted = true;
+            for index in indices {
+                let Some(data) = accounts.get(&bin_array_address(&address, index)) else {
+                    exhausted = false;
+                    break;
+                };
+                let array = BinArray::decode(data)?;
+                ensure!(
+                    array.lb_pair == address && array.index == index,
+                    Error::account(
+                        "Meteora DLMM bin array",
+                        format!("bin array {} does not belong to pair {}", index, address),
+                        data,
+                    )
+                );
+                bin_arrays.insert(index, array);
+                *bound = index;
+            }
+            // Past the last array that exists there is nothing left to trade
+            if exhausted {
+                *bound = if step < 0 {
+                    *BITMAP_RANGE.start()
+                } else {
+                    *BITMAP_RANGE.end()
+                };
+            }
+        }
+
+        Ok(Self {
+            address,
+            pair,
+            bin_arrays,
+            known_arrays: bounds[0]..=bounds[1],
+            current_time,
+        })
+    }
+
+    fn bin(&self, bin_id: i64) -> Option<&Bin> {
+        let index = bin_array_index(bin_id);
+        self.bin_arrays
+            .get(&index)
+            .map(|array| &array.bins[(bin_id - index * BINS_PER_ARRAY) as usize])
+    }
+
+    /// Quote swapping `in_amount` base units of `input_mint`. The fee is in
+    /// the input token unless the pair collects it from the output.
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        Ok(self.swap(input_mint, in_amount)?.0)
+    }
+
+    /// Replay the swap, returning its quote and the index of the last bin
+    /// array it traded in
+    fn swap(&self, input_mint: &Pubkey, in_amount: u64) -> Result<(SwapQuote, i64)> {
+        ensure!(
+            self.pair.enabled(),
+            Error::quote("Meteora DLMM pair is disabled")
+        );
+        let swap_for_y = if *input_mint == self.pair.token_x_mint {
+            true
+        } else if *input_mint == self.pair.token_y_mint {
+            false
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pair",
+                input_mint
+            )));
+        };
+        // X is worth more Y in higher bins, so selling X walks down
+        let step = if swap_for_y { -1 } else { 1 };
+        let fee_on_input = self.pair.parameters.collect_fee_mode == 0 || !swap_for_y;
+
+        let mut v_parameters = self.pair.v_parameters;
+        self.pair
+            .update_references(&mut v_parameters, self.current_time);
+
+        let mut bin_id = self.pair.active_id as i64;
+        let mut remaining = in_amount;
+        let mut out_amount = 0u64;
+        let mut fee = 0u64;
+        let mut last_array = bin_array_index(bin_id);
+        while remaining > 0 {
+            ensure!(
+                (MIN_BIN_ID..=MAX_BIN_ID).contains(&bin_id),
+                Error::quote("Swap runs past the last bin")
+            );
+            let index = bin_array_index(bin_id);
+            if !self.known_arrays.contains(&index) {
+                ensure!(
+                    BITMAP_RANGE.contains(&index),
+                    Error::quote("Not enough liquidity in the pair for this swap")
+                );
+                return Err(Error::quote("Swap needs bin arrays beyond those loaded"));
+            }
+            let Some(bin) = self.bin(bin_id) else {
+                // No array, so no liquidity: skip straight to the next one
+                bin_id = if swap_for_y {
+                    index * BINS_PER_ARRAY - 1
+                } else {
+                    (index + 1) * BINS_PER_ARRAY
+                };
+                continue;
+            };
+            let liquidity = bin.liquidity(swap_for_y);
+            if liquidity.iter().all(|amount| *amount == 0) {
+                bin_id += step;
+                continue;
+            }
+            last_array = index;
+
+            // The fee rate climbs as the swap moves away from the reference bin
+            self.pair
+                .update_volatility_accumulator(&mut v_parameters, bin_id);
+            let fee_rate = self.pair.total_fee_rate(&v_parameters);
+            let available = if fee_on_input {
+                remaining - fee_amount(remaining, fee_rate)?
+            } else {
+                remaining
+            };
+
+            let mut left = available as u128;
+            let mut used = 0u128;
+            let mut out = 0u128;
+            for reserve in liquidity {
+                if left == 0 {
+                    break;
+                }
+                let max_in = amount_in(bin.price, reserve, swap_for_y)?;
+                if left >= max_in {
+                    left -= max_in;
+                    used += max_in;
+                    out += reserve as u128;
+                } else {
+                    used += left;
+                    out += amount_out(bin.price, left, swap_for_y)?;
+                    left = 0;
+                }
+            }
+            let used = u64::try_from(used)?;
+            let mut out = u64::try_from(out)?;
+
+            if fee_on_input {
+                let spent = if left == 0 {
+                    remaining
+                } else {
+                    fee_inclusive(used, fee_rate)?
+                };
+                fee += spent - used;
+                remaining = remaining
+                    .checked_sub(spent)
+                    .ok_or_else(|| Error::quote("Bin fee exceeds the remaining input"))?;
+            } else {
+                let skimmed = fee_amount(out, fee_rate)?;
+                fee += skimmed;
+                out -= skimmed;
+                remaining = if left == 0 { 0 } else { remaining - used };
+            }
+            out_amount = out_amount
+                .checked_add(out)
+                .ok_or_else(|| Error::quote("Swap output overflows"))?;
+            bin_id += step;
+        }
+
+        Ok((SwapQuote { out_amount, fee }, last_array))
+    }
+
+    /// `swap` instruction selling exactly `amount_in` of `input_mint` from
+    /// `input_account` into `output_account`, both owned by `owner`.
+    ///
+    /// The program wants the bin arrays the swap reaches, in order, from the
+    /// active one. We pass the ones our own quote walked plus the next, in
+    /// case the fill runs slightly further than quoted.
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Result<Instruction> {
+        let pair = &self.pair;
+        let (_, last_array) = self.swap(input_mint, amount_in)?;
+        let step = if *input_mint == pair.token_x_mint {
+            -1
+        } else {
+            1
+        };
+
+        let active = bin_array_index(pair.active_id as i64);
+        let mut bin_arrays = Vec::new();
+        for index in std::iter::once(active)
+            .filter(|index| pair.has_bin_array(*index))
+            .chain(pair.bin_arrays_from(active, step, usize::MAX))
+        {
+            bin_arrays.push(bin_array_address(&self.address, index));
+            if (index - last_array) * step > 0 {
+                break;
+            }
+        }
+
+        let mut data = SWAP_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+
+        // Optional accounts we leave out are passed as the program itself
+        let mut accounts = vec![
+            AccountMeta::new(self.address, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+            AccountMeta::new(pair.reserve_x, false),
+            AccountMeta::new(pair.reserve_y, false),
+            AccountMeta::new(*input_account, false),
+            AccountMeta::new(*output_account, false),
+            AccountMeta::new_readonly(pair.token_x_mint, false),
+            AccountMeta::new_readonly(pair.token_y_mint, false),
+            AccountMeta::new(pair.oracle, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(pair.token_x_program, false),
+            AccountMeta::new_readonly(pair.token_y_program, false),
+            AccountMeta::new_readonly(DLMM_EVENT_AUTHORITY, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+        ];
+        accounts.extend(
+            bin_arrays
+                .into_iter()
+                .map(|key| AccountMeta::new(key, false)),
+        );
+        Ok(Instruction {
+            program_id: DLMM_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+}
+
+/// Fee on `amount` at `fee_rate`, rounded up
+fn fee_amount(amount: u64, fee_rate: u128) -> Result<u64> {
+    Ok(u64::try_from(
+        (amount as u128 * fee_rate).div_ceil(FEE_PRECISION),
+    )?)
+}
+
+/// Gross input that leaves `amount` once the fee at `fee_rate` is taken
+fn fee_inclusive(amount: u64, fee_rate: u128) -> Result<u64> {
+    Ok(u64::try_from(
+        (amount as u128 * FEE_PRECISION).div_ceil(FEE_PRECISION - fee_rate),
+    )?)
+}
+
+/// Output of trading `amount` in a bin at `price`, rounded down
+fn amount_out(price: u128, amount: u128, swap_for_y: bool) -> Result<u128> {
+    let (numerator, denominator) = if swap_for_y {
+        (
+            U192::from(amount) * U192::from(price),
+            U192::one() << SCALE_OFFSET,
+        )
+    } else {
+        (U192::from(amount) << SCALE_OFFSET, U192::from(price))
+    };
+    ensure!(!denominator.is_zero(), Error::quote("Bin has a zero price"));
+    u128::try_from(numerator / denominator).map_err(|_| Error::quote("Bin output overflows"))
+}
+
+/// Input that takes all of `reserve` out of a bin at `price`, rounded up
+fn amount_in(price: u128, reserve: u64, swap_for_y: bool) -> Result<u128> {
+    let (numerator, denominator) = if swap_for_y {
+        (U192::from(reserve) << SCALE_OFFSET, U192::from(price))
+    } else {
+        (
+            U192::from(reserve) * U192::from(price),
+            U192::one() << SCALE_OFFSET,
+        )
+    };
+    ensure!(!denominator.is_zero(), Error::quote("Bin has a zero price"));
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    let quotient = if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    };
+    u128::try_from(quotient).map_err(|_| Error::quote("Bin input overflows"))
+}
+
+// -----------------------------------
+// Venue
+// -----------------------------------
+
+/// Meteora DLMM pairs, found and traded straight from chain
+pub struct DlmmVenue<'a> {
+    rpc_client: &'a RpcClient,
+}
+
+impl<'a> DlmmVenue<'a> {
+    pub fn new(rpc_client: &'a RpcClient) -> Self {
+        Self { rpc_client }
+    }
+
+    fn pool(&self, pair: &DlmmPair) -> Pool {
+        let params = &pair.pair.parameters;
+        Pool {
+            address: pair.address,
+            source: self.name(),
+            pool_type: "DLMM".to_string(),
+            program_id: DLMM_PROGRAM_ID,
+            mints: [pair.pair.token_x_mint, pair.pair.token_y_mint],
+            decimals: [pair.decimals_x, pair.decimals_y],
+            reserves: [pair.reserve_x_amount, pair.reserve_y_amount],
+            price: pair.ui_price(),
+            fees: Fees {
+                trade_fee: pair.pair.base_fee_rate() as f64 / FEE_PRECISION as f64,
+                protocol_share: params.protocol_share as f64 / BASIS_POINT_MAX as f64,
+                dynamic: params.variable_fee_control > 0,
+            },
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+}
+
+#[async_trait]
+impl Venue for DlmmVenue<'_> {
+    fn name(&self) -> &'static str {
+        "Meteora DLMM"
+    }
+
+    #[instrument(skip_all, fields(venue = "Meteora DLMM", %mint))]
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>> {
+        let pairs = fetch_dlmm_pairs(self.rpc_client, mint).await?;
+        Ok(pairs.iter().map(|pair| self.pool(pair)).collect())
+    }
+
+    async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+        let data = self.rpc_client.get_account_data(&pool.address).await?;
+        let pairs = load_pairs(
+            self.rpc_client,
+            vec![(pool.address, LbPair::decode(&data)?)],
+        )
+        .await?;
+        Ok(self.pool(&pairs[0]))
+    }
+
+    async fn watch_accounts(&self, pool: &Pool) -> Result<Vec<Pubkey>> {
+        let data = self.rpc_client.get_account_data(&pool.address).await?;
+        let pair = LbPair::decode(&data)?;
+        Ok(vec![pool.address, pair.reserve_x, pair.reserve_y])
+    }
+
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote> {
+        DlmmSnapshot::fetch(self.rpc_client, &pool.address)
+            .await?
+            .quote(input_mint, amount_in)
+    }
+
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<SwapInstructions> {
+        let side = pool.side(input_mint).ok_or_else(|| {
+            Error::invalid(format!(
+                "Pair {} does not trade {}",
+                pool.address, input_mint
+            ))
+        })?;
+        let snapshot = DlmmSnapshot::fetch(self.rpc_client, &pool.address).await?;
+        let quote = snapshot.quote(input_mint, amount_in)?;
+        swap_with_token_accounts(
+            self.rpc_client,
+            owner,
+            input_mint,
+            &side.other_mint,
+            quote,
+            slippage_bps,
+            |input_account, output_account, min_out| {
+                snapshot.swap_instruction(
+                    owner,
+                    input_mint,
+                    input_account,
+                    output_account,
+                    amount_in,
+                    min_out,
+                )
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use std::{fs, path::Path};
+
+    const SOL_USDC: Pubkey = pubkey!("HTvjzsfX3yU6BUodCjZ5vZkUrAxMDTrBs3CJaq43ashR");
+    const SOL: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+
+    /// Mainnet accounts dumped with `solana account --output json`
+    fn fixture_accounts() -> HashMap<Pubkey, Vec<u8>> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/meteora_dlmm");
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let json: serde_json::Value =
+                    serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+                let key = json["pubkey"].as_str().unwrap().parse().unwrap();
+                let data = json["account"]["data"][0].as_str().unwrap();
+                (key, BASE64.decode(data).unwrap())
+            })
+            .collect()
+    }
+
+    fn snapshot(accounts: &HashMap<Pubkey, Vec<u8>>) -> DlmmSnapshot {
+        let pair = LbPair::decode(&accounts[&SOL_USDC]).unwrap();
+        DlmmSnapshot::from_accounts(SOL_USDC, pair, accounts, 1_783_662_993).unwrap()
+    }
+
+    #[test]
+    fn decodes_pair_and_bin_arrays() {
+        let snapshot = snapshot(&fixture_accounts());
+        let pair = &snapshot.pair;
+        assert_eq!(pair.token_x_mint, SOL);
+        assert_eq!(pair.active_id, -25_369);
+        assert_eq!(pair.bin_step, 1);
+        assert_eq!(pair.parameters.base_factor, 10_000);
+        assert_eq!(pair.v_parameters.volatility_accumulator, 4_614);
+        assert_eq!(pair.base_fee_rate(), 100_000);
+        assert_eq!(snapshot.known_arrays, -366..=-363);
+        assert_eq!(snapshot.bin_arrays.len(), 4);
+    }
+
+    // Expected outputs come from Meteora's SDK `swapQuote` against these
+    // same accounts, with the clock at the time they were dumped.
+    #[test]
+    fn quotes_match_sdk_swaps() {
+        let snapshot = snapshot(&fixture_accounts());
+        for (amount, expected) in [
+            (1_000_000_000, 79_109_650),
+            (10_000_000_000, 790_983_110),
+            (100_000_000_000, 7_901_542_802),
+        ] {
+            let quote = snapshot.quote(&SOL, amount).unwrap();
+            assert_eq!(quote.out_amount, expected, "{}", amount);
+        }
+    }
+
+    #[test]
+    fn rejects_swaps_the_loaded_bins_cannot_fill() {
+        let accounts = fixture_accounts();
+        let snapshot = snapshot(&accounts);
+        let error = snapshot.quote(&SOL, 1_000_000_000_000).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Quote { ref reason } if reason == "Swap needs bin arrays beyond those loaded"
+        ));
+
+        // With no arrays below the loaded ones the pair is simply drained
+        let mut pair = snapshot.pair.clone();
+        pair.bin_array_bitmap = [0; 16];
+        pair.bin_array_bitmap[2] = 0b1111 << 18;
+        let drained = DlmmSnapshot::from_accounts(SOL_USDC, pair, &accounts, 1_783_662_993);
+        let error = drained.unwrap().quote(&SOL, 1_000_000_000_000).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Quote { ref reason } if reason == "Not enough liquidity in the pair for this swap"
+        ));
+    }
+}
@@ -0,0 +1,1149 @@
+//! Discovery, on-chain state and exact quoting for Orca Whirlpools.
+//!
+//! A whirlpool concentrates liquidity between ticks like Raydium's CLMM,
+//! with 88 ticks to a tick array account and no record of which arrays
+//! exist. A swap crosses at most the three tick arrays passed to it,
+//! starting from the one holding the current tick. Arrays that were never
+//! created count as empty. Quotes replay Orca's own swap quote over those
+//! three arrays with the same Q64.64 math and rounding.
+//!
+//! Pools with adaptive fees are rejected, as are swaps that would run past
+//! the third tick array.
+
+use crate::common::{
+    fetch_accounts_batched, read_i32, read_pubkey, read_u128, read_u16, read_u64, read_u8,
+    swap_with_token_accounts, SwapInstructions, SwapQuote, CHEESE_MINT, MEMO_PROGRAM_ID,
+};
+use crate::error::{ensure, parse_pubkey, Error, Result};
+use crate::venue::{Fees, Pool, Venue};
+use async_trait::async_trait;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::instrument;
+
+use bn::U256;
+
+#[allow(clippy::manual_div_ceil)]
+mod bn {
+    uint::construct_uint! {
+        pub(crate) struct U256(4);
+    }
+}
+
+pub const WHIRLPOOL_PROGRAM_ID: Pubkey = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+
+const WHIRLPOOL_DISCRIMINATOR: [u8; 8] = [63, 149, 209, 12, 225, 128, 99, 9];
+const TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [69, 97, 189, 190, 110, 7, 66, 187];
+const DYNAMIC_TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [17, 216, 246, 142, 225, 199, 218, 56];
+const SWAP_V2_DISCRIMINATOR: [u8; 8] = [43, 4, 237, 11, 26, 201, 30, 98];
+const WHIRLPOOL_LEN: u64 = 653;
+
+const TICKS_PER_ARRAY: i32 = 88;
+const TICK_ARRAY_HEADER_LEN: usize = 12;
+const TICK_LEN: usize = 113;
+/// Dynamic tick arrays store ticks after the pool key and an initialized bitmap
+const DYNAMIC_TICK_ARRAY_HEADER_LEN: usize = 60;
+/// Tick arrays a single swap can cross
+const TICK_ARRAYS_PER_SWAP: usize = 3;
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+const MIN_SQRT_PRICE_X64: u128 = 4_295_048_016;
+const MAX_SQRT_PRICE_X64: u128 = 79_226_673_515_401_279_992_447_579_055;
+
+/// Fee rates are numerators over this
+const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+/// Protocol fee rates are a share of the fee over this
+const PROTOCOL_FEE_RATE_DENOMINATOR: u64 = 10_000;
+/// Sqrt prices are Q64.64 fixed point
+const RESOLUTION: usize = 64;
+
+// -----------------------------------
+// Pool and Tick Accounts
+// -----------------------------------
+#[derive(Debug, Clone)]
+pub struct Whirlpool {
+    pub whirlpools_config: Pubkey,
+    pub tick_spacing: u16,
+    /// Equals the tick spacing unless the pool was created with adaptive fees
+    pub fee_tier_index: u16,
+    pub fee_rate: u16,
+    pub protocol_fee_rate: u16,
+    pub liquidity: u128,
+    /// Square root of the price of token A in token B, Q64.64
+    pub sqrt_price: u128,
+    pub tick_current_index: i32,
+    pub token_mint_a: Pubkey,
+    pub token_vault_a: Pubkey,
+    pub token_mint_b: Pubkey,
+    pub token_vault_b: Pubkey,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tick {
+    pub initialized: bool,
+    /// Liquidity added when the price crosses this tick upwards
+    pub liquidity_net: i128,
+}
+
+#[derive(Debug, Clone)]
+pub struct TickArray {
+    pub whirlpool: Pubkey,
+    pub start_tick_index: i32,
+    pub ticks: Vec<Tick>,
+}
+
+impl Whirlpool {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&WHIRLPOOL_DISCRIMINATOR),
+            Error::account("Orca whirlpool", "wrong discriminator", data)
+        );
+        Ok(Self {
+            whirlpools_config: read_pubkey(data, 8)?,
+            tick_spacing: read_u16(data, 41)?,
+            fee_tier_index: read_u16(data, 43)?,
+            fee_rate: read_u16(data, 45)?,
+            protocol_fee_rate: read_u16(data, 47)?,
+            liquidity: read_u128(data, 49)?,
+            sqrt_price: read_u128(data, 65)?,
+            tick_current_index: read_i32(data, 81)?,
+            token_mint_a: read_pubkey(data, 101)?,
+            token_vault_a: read_pubkey(data, 133)?,
+            token_mint_b: read_pubkey(data, 181)?,
+            token_vault_b: read_pubkey(data, 213)?,
+        })
+    }
+
+    pub fn adaptive_fee(&self) -> bool {
+        self.fee_tier_index != self.tick_spacing
+    }
+
+    /// Price of one base unit of token A in base units of token B
+    pub fn price(&self) -> f64 {
+        let sqrt_price = self.sqrt_price as f64 / 2f64.powi(RESOLUTION as i32);
+        sqrt_price * sqrt_price
+    }
+
+    /// Ticks covered by one tick array
+    fn ticks_per_array(&self) -> i32 {
+        self.tick_spacing as i32 * TICKS_PER_ARRAY
+    }
+
+    /// Start ticks of the tick arrays a swap can cross, in the order it
+    /// crosses them. Selling token B starts one tick spacing up, so a price
+    /// sitting just below an array boundary starts in the next array.
+    pub fn swap_tick_array_starts(&self, a_to_b: bool) -> [i32; TICK_ARRAYS_PER_SWAP] {
+        let ticks_per_array = self.ticks_per_array();
+        let (shift, step) = if a_to_b {
+            (0, -ticks_per_array)
+        } else {
+            (self.tick_spacing as i32, ticks_per_array)
+        };
+        let first = (self.tick_current_index + shift).div_euclid(ticks_per_array) * ticks_per_array;
+        std::array::from_fn(|i| first + i as i32 * step)
+    }
+}
+
+impl TickArray {
+    /// Decode a fixed or dynamic tick array
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let ticks = if data.starts_with(&TICK_ARRAY_DISCRIMINATOR) {
+            (0..TICKS_PER_ARRAY as usize)
+                .map(|i| {
+                    let offset = TICK_ARRAY_HEADER_LEN + i * TICK_LEN;
+                    Ok(Tick {
+                        initialized: read_u8(data, offset)? != 0,
+                        liquidity_net: read_u128(data, offset + 1)? as i128,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        } else if data.starts_with(&DYNAMIC_TICK_ARRAY_DISCRIMINATOR) {
+            // Uninitialized ticks are a lone zero tag byte
+            let mut offset = DYNAMIC_TICK_ARRAY_HEADER_LEN;
+            let mut ticks = Vec::with_capacity(TICKS_PER_ARRAY as usize);
+            for _ in 0..TICKS_PER_ARRAY {
+                if read_u8(data, offset)? == 0 {
+                    ticks.push(Tick::default());
+                    offset += 1;
+                } else {
+                    ticks.push(Tick {
+                        initialized: true,
+                        liquidity_net: read_u128(data, offset + 1)? as i128,
+                    });
+                    offset += TICK_LEN;
+                }
+            }
+            ticks
+        } else {
+            return Err(Error::account(
+                "Orca tick array",
+                "wrong discriminator",
+                data,
+            ));
+        };
+
+        let whirlpool_offset = if data.starts_with(&TICK_ARRAY_DISCRIMINATOR) {
+            TICK_ARRAY_HEADER_LEN + TICKS_PER_ARRAY as usize * TICK_LEN
+        } else {
+            12
+        };
+        Ok(Self {
+            whirlpool: read_pubkey(data, whirlpool_offset)?,
+            start_tick_index: read_i32(data, 8)?,
+            ticks,
+        })
+    }
+}
+
+/// Address of the tick array starting at `start_tick_index`. The index is
+/// seeded as a decimal string.
+pub fn tick_array_address(whirlpool: &Pubkey, start_tick_index: i32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"tick_array",
+            whirlpool.as_ref(),
+            start_tick_index.to_string().as_bytes(),
+        ],
+        &WHIRLPOOL_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn oracle_address(whirlpool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"oracle", whirlpool.as_ref()], &WHIRLPOOL_PROGRAM_ID).0
+}
+
+// -----------------------------------
+// Discovery
+// -----------------------------------
+
+/// A whirlpool, its vault balances and token decimals
+#[derive(Debug, Clone)]
+pub struct OrcaWhirlpool {
+    pub address: Pubkey,
+    pub whirlpool: Whirlpool,
+    pub vault_a_amount: u64,
+    pub vault_b_amount: u64,
+    pub decimals_a: u8,
+    pub decimals_b: u8,
+}
+
+impl OrcaWhirlpool {
+    /// Price of one whole token A in whole token B at the current tick
+    pub fn ui_price(&self) -> f64 {
+        self.whirlpool.price() * 10f64.powi(self.decimals_a as i32 - self.decimals_b as i32)
+    }
+}
+
+/// Every whirlpool trading CHEESE
+pub async fn fetch_orca_cheese_pools(rpc_client: &RpcClient) -> Result<Vec<OrcaWhirlpool>> {
+    fetch_whirlpools(rpc_client, &parse_pubkey(CHEESE_MINT)?).await
+}
+
+/// Every whirlpool trading `mint`, found by scanning the program's pool
+/// accounts for the mint on either side
+pub async fn fetch_whirlpools(rpc_client: &RpcClient, mint: &Pubkey) -> Result<Vec<OrcaWhirlpool>> {
+    let mut pools = Vec::new();
+    for mint_offset in [101, 181] {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(WHIRLPOOL_LEN),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(mint_offset, mint.as_ref())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = rpc_client
+            .get_program_accounts_with_config(&WHIRLPOOL_PROGRAM_ID, config)
+            .await?;
+        for (address, account) in accounts {
+            pools.push((address, Whirlpool::decode(&account.data)?));
+        }
+    }
+    load_whirlpools(rpc_client, pools).await
+}
+
+/// Attach vault balances and decimals to decoded whirlpools
+async fn load_whirlpools(
+    rpc_client: &RpcClient,
+    pools: Vec<(Pubkey, Whirlpool)>,
+) -> Result<Vec<OrcaWhirlpool>> {
+    let keys = pools
+        .iter()
+        .flat_map(|(_, pool)| {
+            [
+                pool.token_vault_a,
+                pool.token_vault_b,
+                pool.token_mint_a,
+                pool.token_mint_b,
+            ]
+        })
+        .collect();
+    let mut accounts = HashMap::new();
+    fetch_accounts_batched(rpc_client, keys, &mut accounts).await?;
+
+    pools
+        .into_iter()
+        .map(|(address, whirlpool)| {
+            Ok(OrcaWhirlpool {
+                address,
+                vault_a_amount: read_u64(&accounts[&whirlpool.token_vault_a], 64)?,
+                vault_b_amount: read_u64(&accounts[&whirlpool.token_vault_b], 64)?,
+                decimals_a: read_u8(&accounts[&whirlpool.token_mint_a], 44)?,
+                decimals_b: read_u8(&accounts[&whirlpool.token_mint_b], 44)?,
+                whirlpool,
+            })
+        })
+        .collect()
+}
+
+// -----------------------------------
+// Quoting
+// -----------------------------------
+
+/// A whirlpool and the tick arrays a swap either way can cross, read at one
+/// point in time
+#[derive(Debug, Clone)]
+pub struct WhirlpoolSnapshot {
+    pub address: Pubkey,
+    pub whirlpool: Whirlpool,
+    /// Token program owning each mint, A then B
+    pub token_programs: [Pubkey; 2],
+    /// Tick arrays that exist, by start tick
+    pub tick_arrays: BTreeMap<i32, TickArray>,
+}
+
+impl WhirlpoolSnapshot {
+    /// Read the pool, its mints and the tick arrays either side of the
+    /// current tick
+    pub async fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self> {
+        let pool_data = rpc_client.get_account_data(address).await?;
+        let whirlpool = Whirlpool::decode(&pool_data)?;
+
+        let mints = [whirlpool.token_mint_a, whirlpool.token_mint_b];
+        let mut keys = mints.to_vec();
+        for a_to_b in [true, false] {
+            keys.extend(
+                whirlpool
+                    .swap_tick_array_starts(a_to_b)
+                    .map(|start| tick_array_address(address, start)),
+            );
+        }
+        // Tick arrays that were never created are simply missing
+        let fetched = rpc_client.get_multiple_accounts(&keys).await?;
+        let mut accounts = HashMap::new();
+        let mut owners = HashMap::new();
+        for (key, account) in keys.iter().zip(fetched).filter_map(|(k, a)| Some((k, a?))) {
+            owners.insert(*key, account.owner);
+            accounts.insert(*key, account.data);
+        }
+        let [token_program_a, token_program_b] = mints.map(|mint| {
+            owners
+                .get(&mint)
+                .copied()
+                .ok_or(Error::AccountNotFound { account: mint })
+        });
+        let (token_program_a, token_program_b) = (token_program_a?, token_program_b?);
+
+        Self::from_accounts(
+            *address,
+            whirlpool,
+            [token_program_a, token_program_b],
+            &accounts,
+        )
+    }
+
+    /// Assemble a snapshot from already fetched account data. Tick arrays
+    /// missing from `accounts` are taken not to exist.
+    pub fn from_accounts(
+        address: Pubkey,
+        whirlpool: Whirlpool,
+        token_programs: [Pubkey; 2],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<Self> {
+        let mut tick_arrays = BTreeMap::new();
+        for a_to_b in [true, false] {
+            for start in whirlpool.swap_tick_array_starts(a_to_b) {
+                let Some(data) = accounts.get(&tick_array_address(&address, start)) else {
+                    continue;
+                };
+                let array = TickArray::decode(data)?;
+                ensure!(
+                    array.whirlpool == address && array.start_tick_index == start,
+                    Error::account(
+                        "Orca tick array",
+                        format!(
+                            "tick array {} does not belong to whirlpool {}",
+                            start, address
+                        ),
+                        data,
+                    )
+                );
+                tick_arrays.insert(start, array);
+            }
+        }
+
+        Ok(Self {
+            address,
+            whirlpool,
+            token_programs,
+            tick_arrays,
+        })
+    }
+
+    /// The tick at `index`, which must be a multiple of the tick spacing
+    fn tick(&self, index: i32) -> Option<&Tick> {
+        let ticks_per_array = self.whirlpool.ticks_per_array();
+        let start = index.div_euclid(ticks_per_array) * ticks_per_array;
+        let offset = (index - start) / self.whirlpool.tick_spacing as i32;
+        self.tick_arrays.get(&start)?.ticks.get(offset as usize)
+    }
+
+    /// The next initialized tick a swap from `tick` meets within `bounds`:
+    /// at or below it when selling token A, above it when selling token B.
+    /// At the edge of the tick arrays the edge itself is returned, without a
+    /// tick.
+    fn next_initialized_tick(
+        &self,
+        tick: i32,
+        a_to_b: bool,
+        (lower, upper): (i32, i32),
+    ) -> Result<(Option<&Tick>, i32)> {
+        let spacing = self.whirlpool.tick_spacing as i32;
+        let out_of_arrays = || Error::quote("Swap runs past the tick arrays one swap can cross");
+        if a_to_b {
+            ensure!(tick >= lower, out_of_arrays());
+            let mut index = tick.div_euclid(spacing) * spacing;
+            while index >= lower {
+                if let Some(next) = self.tick(index).filter(|t| t.initialized) {
+                    return Ok((Some(next), index));
+                }
+                index -= spacing;
+            }
+            Ok((None, lower))
+        } else {
+            ensure!(tick < upper, out_of_arrays());
+            let mut index = tick.div_euclid(spacing) * spacing + spacing;
+            while index <= upper {
+                if let Some(next) = self.tick(index).filter(|t| t.initialized) {
+                    return Ok((Some(next), index));
+                }
+                index += spacing;
+            }
+            Ok((None, upper))
+        }
+    }
+
+    /// Quote swapping `in_amount` base units of `input_mint`. The fee is in
+    /// the input token.
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        let pool = &self.whirlpool;
+        ensure!(
+            !pool.adaptive_fee(),
+            Error::unsupported("Orca whirlpools with adaptive fees")
+        );
+        ensure!(in_amount > 0, Error::invalid("Swap amount is zero"));
+        let a_to_b = if *input_mint == pool.token_mint_a {
+            true
+        } else if *input_mint == pool.token_mint_b {
+            false
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )));
+        };
+        let sqrt_price_limit = if a_to_b {
+            MIN_SQRT_PRICE_X64
+        } else {
+            MAX_SQRT_PRICE_X64
+        };
+
+        let starts = pool.swap_tick_array_starts(a_to_b);
+        let (first, last) = (starts[0].min(starts[2]), starts[0].max(starts[2]));
+        let bounds = (
+            first.max(MIN_TICK),
+            (last + pool.ticks_per_array() - 1).min(MAX_TICK),
+        );
+
+        let mut sqrt_price = pool.sqrt_price;
+        let mut tick = pool.tick_current_index;
+        let mut liquidity = pool.liquidity;
+        let mut remaining = in_amount;
+        let mut out_amount = 0u64;
+        let mut fee = 0u64;
+        while remaining > 0 && sqrt_price != sqrt_price_limit {
+            let (next, tick_next) = self.next_initialized_tick(tick, a_to_b, bounds)?;
+            let sqrt_price_next = sqrt_price_at_tick(tick_next)?;
+            let target = if a_to_b {
+                sqrt_price_next.max(sqrt_price_limit)
+            } else {
+                sqrt_price_next.min(sqrt_price_limit)
+            };
+
+            let step = compute_swap_step(
+                sqrt_price,
+                target,
+                liquidity,
+                remaining,
+                pool.fee_rate,
+                a_to_b,
+            )?;
+            remaining = remaining
+                .checked_sub(step.amount_in + step.fee)
+                .ok_or_else(|| Error::quote("Swap step spends more than the remaining input"))?;
+            out_amount = out_amount
+                .checked_add(step.amount_out)
+                .ok_or_else(|| Error::quote("Swap output overflows"))?;
+            fee += step.fee;
+
+            // A step short of its target spends the rest of the input, so the
+            // tick only matters when one is crossed
+            if step.sqrt_price_next == sqrt_price_next {
+                let liquidity_net = next.map_or(0, |t| t.liquidity_net);
+                let delta = if a_to_b {
+                    -liquidity_net
+                } else {
+                    liquidity_net
+                };
+                liquidity = liquidity
+                    .checked_add_signed(delta)
+                    .ok_or_else(|| Error::quote("Tick crossing leaves negative liquidity"))?;
+                tick = if a_to_b { tick_next - 1 } else { tick_next };
+            }
+            sqrt_price = step.sqrt_price_next;
+        }
+
+        Ok(SwapQuote { out_amount, fee })
+    }
+
+    /// `swap_v2` instruction selling exactly `amount_in` of `input_mint`
+    /// from `input_account` into `output_account`, both owned by `owner`
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Result<Instruction> {
+        let pool = &self.whirlpool;
+        let a_to_b = if *input_mint == pool.token_mint_a {
+            true
+        } else if *input_mint == pool.token_mint_b {
+            false
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )));
+        };
+        let (account_a, account_b) = if a_to_b {
+            (input_account, output_account)
+        } else {
+            (output_account, input_account)
+        };
+
+        let mut data = SWAP_V2_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        // Zero leaves the price limit to the program's default
+        data.extend_from_slice(&0u128.to_le_bytes());
+        data.push(1); // amount_specified_is_input
+        data.push(a_to_b as u8);
+        data.push(0); // no remaining accounts info
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(self.token_programs[0], false),
+            AccountMeta::new_readonly(self.token_programs[1], false),
+            AccountMeta::new_readonly(MEMO_PROGRAM_ID, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(self.address, false),
+            AccountMeta::new_readonly(pool.token_mint_a, false),
+            AccountMeta::new_readonly(pool.token_mint_b, false),
+            AccountMeta::new(*account_a, false),
+            AccountMeta::new(pool.token_vault_a, false),
+            AccountMeta::new(*account_b, false),
+            AccountMeta::new(pool.token_vault_b, false),
+        ];
+        accounts.extend(
+            pool.swap_tick_array_starts(a_to_b)
+                .map(|start| AccountMeta::new(tick_array_address(&self.address, start), false)),
+        );
+        accounts.push(AccountMeta::new(oracle_address(&self.address), false));
+        Ok(Instruction {
+            program_id: WHIRLPOOL_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+}
+
+/// Build an Orca swap from the pool's on-chain state. Any of `owner`'s
+/// token accounts the swap needs are created first if they don't exist yet.
+pub async fn get_orca_swap_instructions(
+    rpc_client: &RpcClient,
+    pool_address: &str,
+    owner: &Pubkey,
+    input_mint: &str,
+    output_mint: &str,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<SwapInstructions> {
+    let snapshot = WhirlpoolSnapshot::fetch(rpc_client, &parse_pubkey(pool_address)?).await?;
+    let input = parse_pubkey(input_mint)?;
+    let output = parse_pubkey(output_mint)?;
+    swap(
+        &snapshot,
+        rpc_client,
+        owner,
+        &input,
+        &output,
+        amount_in,
+        slippage_bps,
+    )
+    .await
+}
+
+async fn swap(
+    snapshot: &WhirlpoolSnapshot,
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<SwapInstructions> {
+    let pool = &snapshot.whirlpool;
+    ensure!(
+        *input_mint == pool.token_mint_a && *output_mint == pool.token_mint_b
+            || *input_mint == pool.token_mint_b && *output_mint == pool.token_mint_a,
+        Error::invalid(format!(
+            "Orca whirlpool {} does not trade {} -> {}",
+            snapshot.address, input_mint, output_mint
+        ))
+    );
+    let quote = snapshot.quote(input_mint, amount_in)?;
+    swap_with_token_accounts(
+        rpc_client,
+        owner,
+        input_mint,
+        output_mint,
+        quote,
+        slippage_bps,
+        |input_account, output_account, min_out| {
+            snapshot.swap_instruction(
+                owner,
+                input_mint,
+                input_account,
+                output_account,
+                amount_in,
+                min_out,
+            )
+        },
+    )
+    .await
+}
+
+// -----------------------------------
+// Sqrt Price Math
+// -----------------------------------
+
+/// One step of a swap towards a target price, within constant liquidity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SwapStep {
+    sqrt_price_next: u128,
+    amount_in: u64,
+    amount_out: u64,
+    fee: u64,
+}
+
+/// Swap as much of `amount_remaining` as fits before the price reaches
+/// `sqrt_price_target`, taking the trade fee from the input
+fn compute_swap_step(
+    sqrt_price_current: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u64,
+    fee_rate: u16,
+    a_to_b: bool,
+) -> Result<SwapStep> {
+    let fee_rate = fee_rate as u64;
+    ensure!(
+        fee_rate < FEE_RATE_DENOMINATOR,
+        Error::quote("Fee rate is out of range")
+    );
+    let remaining_less_fee = (amount_remaining as u128 * (FEE_RATE_DENOMINATOR - fee_rate) as u128
+        / FEE_RATE_DENOMINATOR as u128) as u64;
+
+    // Input needed to reach the target, if it fits in a u64 at all
+    let amount_to_target = if a_to_b {
+        delta_amount_a(sqrt_price_current, sqrt_price_target, liquidity, true)
+    } else {
+        delta_amount_b(sqrt_price_current, sqrt_price_target, liquidity, true)
+    };
+    let (sqrt_price_next, amount_in) = match amount_to_target {
+        Some(amount_in) if remaining_less_fee >= amount_in => (sqrt_price_target, amount_in),
+        _ => {
+            let sqrt_price_next = next_sqrt_price_from_input(
+                sqrt_price_current,
+                liquidity,
+                remaining_less_fee,
+                a_to_b,
+            )?;
+            let amount_in = if a_to_b {
+                delta_amount_a(sqrt_price_current, sqrt_price_next, liquidity, true)
+            } else {
+                delta_amount_b(sqrt_price_current, sqrt_price_next, liquidity, true)
+            };
+            (
+                sqrt_price_next,
+                amount_in.ok_or_else(|| Error::quote("Swap step input overflows"))?,
+            )
+        }
+    };
+    let amount_out = if a_to_b {
+        delta_amount_b(sqrt_price_current, sqrt_price_next, liquidity, false)
+    } else {
+        delta_amount_a(sqrt_price_current, sqrt_price_next, liquidity, false)
+    }
+    .ok_or_else(|| Error::quote("Swap step output overflows"))?;
+
+    let fee = if sqrt_price_next != sqrt_price_target {
+        // Short of the target the whole remainder is spent; dust goes to fees
+        amount_remaining
+            .checked_sub(amount_in)
+            .ok_or_else(|| Error::quote("Swap step spends more than the remaining input"))?
+    } else {
+        u64::try_from(
+            (amount_in as u128 * fee_rate as u128)
+                .div_ceil((FEE_RATE_DENOMINATOR - fee_rate) as u128),
+        )?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee,
+    })
+}
+
+/// `1.0001^(tick/2)` as Q64.64, computed bit by bit as the program does.
+/// Positive ticks are worked in Q96 for precision.
+pub fn sqrt_price_at_tick(tick: i32) -> Result<u128> {
+    const NEGATIVE_FACTORS: [u128; 19] = [
+        18445821805675392311,
+        18444899583751176498,
+        18443055278223354162,
+        18439367220385604838,
+        18431993317065449817,
+        18417254355718160513,
+        18387811781193591352,
+        18329067761203520168,
+        18212142134806087854,
+        17980523815641551639,
+        17526086738831147013,
+        16651378430235024244,
+        15030750278693429944,
+        12247334978882834399,
+        8131365268884726200,
+        3584323654723342297,
+        696457651847595233,
+        26294789957452057,
+        37481735321082,
+    ];
+    const POSITIVE_FACTORS: [u128; 19] = [
+        79232123823359799118286999567,
+        79236085330515764027303304731,
+        79244008939048815603706035061,
+        79259858533276714757314932305,
+        79291567232598584799939703904,
+        79355022692464371645785046466,
+        79482085999252804386437311141,
+        79736823300114093921829183326,
+        80248749790819932309965073892,
+        81282483887344747381513967011,
+        83390072131320151908154831281,
+        87770609709833776024991924138,
+        97234110755111693312479820773,
+        119332217159966728226237229890,
+        179736315981702064433883588727,
+        407748233172238350107850275304,
+        2098478828474011932436660412517,
+        55581415166113811149459800483533,
+        38992368544603139932233054999993551,
+    ];
+    ensure!(
+        (MIN_TICK..=MAX_TICK).contains(&tick),
+        Error::quote(format!("Tick {} is out of range", tick))
+    );
+
+    let abs_tick = tick.unsigned_abs();
+    if tick >= 0 {
+        let mut ratio = if abs_tick & 1 != 0 {
+            POSITIVE_FACTORS[0]
+        } else {
+            1 << 96
+        };
+        for (bit, factor) in POSITIVE_FACTORS.iter().enumerate().skip(1) {
+            if abs_tick & (1 << bit) != 0 {
+                ratio = ((U256::from(ratio) * U256::from(*factor)) >> 96).as_u128();
+            }
+        }
+        Ok(ratio >> 32)
+    } else {
+        let mut ratio = if abs_tick & 1 != 0 {
+            NEGATIVE_FACTORS[0]
+        } else {
+            1 << RESOLUTION
+        };
+        for (bit, factor) in NEGATIVE_FACTORS.iter().enumerate().skip(1) {
+            if abs_tick & (1 << bit) != 0 {
+                ratio = (ratio * factor) >> RESOLUTION;
+            }
+        }
+        Ok(ratio)
+    }
+}
+
+/// Token A between two sqrt prices: `L·(√b − √a) / (√a·√b)`. `None` if it
+/// doesn't fit in a u64.
+fn delta_amount_a(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    let (lower, upper) = if sqrt_price_a < sqrt_price_b {
+        (sqrt_price_a, sqrt_price_b)
+    } else {
+        (sqrt_price_b, sqrt_price_a)
+    };
+    if lower == 0 {
+        return None;
+    }
+    let numerator = (U256::from(liquidity) * U256::from(upper - lower)) << RESOLUTION;
+    let denominator = U256::from(lower) * U256::from(upper);
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    let amount = if round_up && !remainder.is_zero() {
+        quotient + 1
+    } else {
+        quotient
+    };
+    u64::try_from(amount).ok()
+}
+
+/// Token B between two sqrt prices: `L·(√b − √a)`. `None` if it doesn't fit
+/// in a u64.
+fn delta_amount_b(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    let product = U256::from(liquidity) * U256::from(sqrt_price_a.abs_diff(sqrt_price_b));
+    let quotient = product >> RESOLUTION;
+    let amount = if round_up && !(product & U256::from(u64::MAX)).is_zero() {
+        quotient + 1
+    } else {
+        quotient
+    };
+    u64::try_from(amount).ok()
+}
+
+/// Sqrt price after adding `amount_in` of the input token to the pool. Token
+/// A rounds the price up and token B rounds it down, both in the pool's favour.
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u64,
+    a_to_b: bool,
+) -> Result<u128> {
+    if amount_in == 0 {
+        return Ok(sqrt_price);
+    }
+    ensure!(
+        liquidity > 0,
+        Error::quote("Pool has no liquidity at this price")
+    );
+    let next = if a_to_b {
+        let numerator = (U256::from(liquidity) * U256::from(sqrt_price)) << RESOLUTION;
+        let denominator =
+            (U256::from(liquidity) << RESOLUTION) + U256::from(sqrt_price) * U256::from(amount_in);
+        let (quotient, remainder) = numerator.div_mod(denominator);
+        if remainder.is_zero() {
+            quotient
+        } else {
+            quotient + 1
+        }
+    } else {
+        U256::from(sqrt_price) + (U256::from(amount_in) << RESOLUTION) / U256::from(liquidity)
+    };
+    ensure!(
+        next >= U256::from(MIN_SQRT_PRICE_X64) && next <= U256::from(MAX_SQRT_PRICE_X64),
+        Error::quote("Sqrt price leaves the pool's range")
+    );
+    Ok(next.as_u128())
+}
+
+// -----------------------------------
+// Venue
+// -----------------------------------
+
+/// Orca whirlpools, found and traded straight from chain
+pub struct OrcaVenue<'a> {
+    rpc_client: &'a RpcClient,
+}
+
+impl<'a> OrcaVenue<'a> {
+    pub fn new(rpc_client: &'a RpcClient) -> Self {
+        Self { rpc_client }
+    }
+
+    fn pool(&self, pool: &OrcaWhirlpool) -> Pool {
+        let whirlpool = &pool.whirlpool;
+        Pool {
+            address: pool.address,
+            source: self.name(),
+            pool_type: "Whirlpool".to_string(),
+            program_id: WHIRLPOOL_PROGRAM_ID,
+            mints: [whirlpool.token_mint_a, whirlpool.token_mint_b],
+            decimals: [pool.decimals_a, pool.decimals_b],
+            reserves: [pool.vault_a_amount, pool.vault_b_amount],
+            price: pool.ui_price(),
+            fees: Fees {
+                trade_fee: whirlpool.fee_rate as f64 / FEE_RATE_DENOMINATOR as f64,
+                protocol_share: whirlpool.protocol_fee_rate as f64
+                    / PROTOCOL_FEE_RATE_DENOMINATOR as f64,
+                dynamic: whirlpool.adaptive_fee(),
+            },
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+}
+
+#[async_trait]
+impl Venue for OrcaVenue<'_> {
+    fn name(&self) -> &'static str {
+        "Orca"
+    }
+
+    #[instrument(skip_all, fields(venue = "Orca", %mint))]
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>> {
+        let pools = fetch_whirlpools(self.rpc_client, mint).await?;
+        Ok(pools.iter().map(|pool| self.pool(pool)).collect())
+    }
+
+    async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+        let data = self.rpc_client.get_account_data(&pool.address).await?;
+        let whirlpool = Whirlpool::decode(&data)?;
+        let pools = load_whirlpools(self.rpc_client, vec![(pool.address, whirlpool)]).await?;
+        Ok(self.pool(&pools[0]))
+    }
+
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote> {
+        WhirlpoolSnapshot::fetch(self.rpc_client, &pool.address)
+            .await?
+            .quote(input_mint, amount_in)
+    }
+
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<SwapInstructions> {
+        let side = pool.side(input_mint).ok_or_else(|| {
+            Error::invalid(format!(
+                "Pool {} does not trade {}",
+                pool.address, input_mint
+            ))
+        })?;
+        let snapshot = WhirlpoolSnapshot::fetch(self.rpc_client, &pool.address).await?;
+        swap(
+            &snapshot,
+            self.rpc_client,
+            owner,
+            input_mint,
+            &side.other_mint,
+            amount_in,
+            slippage_bps,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_A: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+    const TOKEN_B: Pubkey = pubkey!("A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump");
+    const POOL: Pubkey = pubkey!("2QdhepnKRTLjjSqPL1PtKNwqrUkoLee5Gqs8bvZhRdMv");
+
+    const LIQUIDITY_WIDE: i128 = 5_000_000_000;
+    const LIQUIDITY_NARROW: i128 = 20_000_000_000;
+
+    /// Fixed tick array bytes, or dynamic ones with initialized ticks inline
+    fn tick_array(start: i32, ticks: &[(i32, i128)], dynamic: bool) -> Vec<u8> {
+        let net_at = |i: i32| {
+            ticks
+                .iter()
+                .find(|(tick, _)| *tick == start + i * 8)
+                .map(|(_, net)| *net)
+        };
+        let mut data = Vec::new();
+        if dynamic {
+            data.extend_from_slice(&DYNAMIC_TICK_ARRAY_DISCRIMINATOR);
+            data.extend_from_slice(&start.to_le_bytes());
+            data.extend_from_slice(POOL.as_ref());
+            data.resize(DYNAMIC_TICK_ARRAY_HEADER_LEN, 0);
+            for i in 0..TICKS_PER_ARRAY {
+                let Some(net) = net_at(i) else {
+                    data.push(0);
+                    continue;
+                };
+                data.push(1);
+                data.extend_from_slice(&net.to_le_bytes());
+                data.extend_from_slice(&net.unsigned_abs().to_le_bytes());
+                data.resize(data.len() + TICK_LEN - 33, 0);
+            }
+        } else {
+            data.extend_from_slice(&TICK_ARRAY_DISCRIMINATOR);
+            data.extend_from_slice(&start.to_le_bytes());
+            for i in 0..TICKS_PER_ARRAY {
+                let mut tick = [0u8; TICK_LEN];
+                if let Some(net) = net_at(i) {
+                    tick[0] = 1;
+                    tick[1..17].copy_from_slice(&net.to_le_bytes());
+                    tick[17..33].copy_from_slice(&net.unsigned_abs().to_le_bytes());
+                }
+                data.extend_from_slice(&tick);
+            }
+            data.extend_from_slice(POOL.as_ref());
+        }
+        data
+    }
+
+    /// A pool at tick -100 with spacing 8 and a 0.3% fee, holding one
+    /// position over -1400..1400 and another over -600..296. The array at
+    /// -2112 was never created and the one at 0 is dynamic.
+    fn snapshot() -> WhirlpoolSnapshot {
+        let mut pool = vec![0u8; WHIRLPOOL_LEN as usize];
+        pool[..8].copy_from_slice(&WHIRLPOOL_DISCRIMINATOR);
+        pool[41..43].copy_from_slice(&8u16.to_le_bytes());
+        pool[43..45].copy_from_slice(&8u16.to_le_bytes());
+        pool[45..47].copy_from_slice(&3_000u16.to_le_bytes());
+        pool[47..49].copy_from_slice(&300u16.to_le_bytes());
+        let liquidity = (LIQUIDITY_WIDE + LIQUIDITY_NARROW) as u128;
+        pool[49..65].copy_from_slice(&liquidity.to_le_bytes());
+        let sqrt_price = sqrt_price_at_tick(-100).unwrap() + 1_000_000;
+        pool[65..81].copy_from_slice(&sqrt_price.to_le_bytes());
+        pool[81..85].copy_from_slice(&(-100i32).to_le_bytes());
+        pool[101..133].copy_from_slice(TOKEN_A.as_ref());
+        pool[181..213].copy_from_slice(TOKEN_B.as_ref());
+        let whirlpool = Whirlpool::decode(&pool).unwrap();
+
+        let ticks = [
+            (-1400, LIQUIDITY_WIDE),
+            (-600, LIQUIDITY_NARROW),
+            (296, -LIQUIDITY_NARROW),
+            (1400, -LIQUIDITY_WIDE),
+        ];
+        let accounts = [-1408, -704, 0, 704]
+            .into_iter()
+            .map(|start| {
+                let data = tick_array(start, &ticks, start == 0);
+                (tick_array_address(&POOL, start), data)
+            })
+            .collect();
+        let token_programs = [spl_token::id(), spl_token::id()];
+        WhirlpoolSnapshot::from_accounts(POOL, whirlpool, token_programs, &accounts).unwrap()
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_matches_program_bounds() {
+        assert_eq!(sqrt_price_at_tick(0).unwrap(), 1 << 64);
+        assert_eq!(sqrt_price_at_tick(MIN_TICK).unwrap(), MIN_SQRT_PRICE_X64);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK).unwrap(), MAX_SQRT_PRICE_X64);
+        assert_eq!(
+            sqrt_price_at_tick(-100).unwrap(),
+            18_354_745_142_194_483_561
+        );
+        assert_eq!(
+            sqrt_price_at_tick(1400).unwrap(),
+            19_784_214_696_299_598_671
+        );
+        assert!(sqrt_price_at_tick(MIN_TICK - 1).is_err());
+    }
+
+    // Expected amounts come from Orca's own swap quote over the same pool
+    // and tick arrays.
+    #[test]
+    fn quotes_match_orca_swaps_across_ticks() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.tick_arrays.len(), 4);
+
+        for (input, amount, out_amount, fee) in [
+            (TOKEN_A, 1_000_000, 987_041, 3_000),
+            (TOKEN_A, 100_000_000, 98_317_881, 300_000),
+            (TOKEN_A, 800_000_000, 761_688_925, 2_400_001),
+            (TOKEN_B, 1_000_000, 1_006_979, 3_000),
+            (TOKEN_B, 500_000_000, 493_617_503, 1_500_001),
+        ] {
+            let quote = snapshot.quote(&input, amount).unwrap();
+            assert_eq!(quote, SwapQuote { out_amount, fee }, "{} {}", input, amount);
+        }
+    }
+
+    #[test]
+    fn rejects_swaps_past_the_third_tick_array() {
+        let snapshot = snapshot();
+        for input in [TOKEN_A, TOKEN_B] {
+            let error = snapshot.quote(&input, 1_500_000_000).unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Quote { ref reason }
+                    if reason == "Swap runs past the tick arrays one swap can cross"
+            ));
+        }
+    }
+
+    #[test]
+    fn swaps_pass_three_tick_arrays_and_the_oracle() {
+        let snapshot = snapshot();
+        let owner = Pubkey::new_unique();
+        let (input, output) = (Pubkey::new_unique(), Pubkey::new_unique());
+        for (input_mint, starts) in [(TOKEN_A, [-704, -1408, -2112]), (TOKEN_B, [-704, 0, 704])] {
+            let ix = snapshot
+                .swap_instruction(&owner, &input_mint, &input, &output, 1_000_000, 0)
+                .unwrap();
+            let tick_arrays: Vec<Pubkey> = ix.accounts[11..14].iter().map(|a| a.pubkey).collect();
+            let expected: Vec<Pubkey> = starts
+                .into_iter()
+                .map(|start| tick_array_address(&POOL, start))
+                .collect();
+            assert_eq!(tick_arrays, expected);
+            assert_eq!(ix.accounts[14].pubkey, oracle_address(&POOL));
+            assert_eq!(ix.data[41], (input_mint == TOKEN_A) as u8);
+        }
+    }
+}
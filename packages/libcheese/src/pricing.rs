@@ -0,0 +1,162 @@
+//! USD reference price for a token from its own pools.
+//!
+//! Every pool pairing the token with USDC, USDT or SOL is a candidate. Each
+//! is weighted by the USD value of its quote side, pools straying too far
+//! from the weighted median are thrown out, and the rest are averaged.
+
+use crate::common::WSOL_MINT;
+use crate::venue::Pool;
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+pub const USDC_MINT: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+pub const USDT_MINT: Pubkey = pubkey!("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB");
+
+/// Furthest a pool's price may sit from the median and still count
+pub const DEFAULT_MAX_DEVIATION: f64 = 0.05;
+
+/// One pool's take on the price
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceSource {
+    pub pool: Pubkey,
+    pub source: &'static str,
+    /// USDC, USDT or SOL
+    pub quote_mint: Pubkey,
+    pub price_usd: f64,
+    /// USD value of the pool's quote side
+    pub depth_usd: f64,
+}
+
+/// A liquidity-weighted price and the pools behind it
+#[derive(Debug, Clone)]
+pub struct ReferencePrice {
+    pub price_usd: f64,
+    /// Pools averaged into the price, deepest first
+    pub sources: Vec<PriceSource>,
+    /// Pools left out for straying from the median
+    pub rejected: Vec<PriceSource>,
+}
+
+impl ReferencePrice {
+    /// The deepest pool behind the price
+    pub fn best(&self) -> &PriceSource {
+        &self.sources[0]
+    }
+
+    /// Share of the price each source carries
+    pub fn weight(&self, source: &PriceSource) -> f64 {
+        source.depth_usd / self.sources.iter().map(|s| s.depth_usd).sum::<f64>()
+    }
+}
+
+/// USD price of `mint` from its stablecoin and SOL pools among `pools`.
+/// SOL pools only count when `sol_usd` is known. `None` when no pool with
+/// a usable price pairs `mint` with one of them.
+pub fn reference_price(
+    pools: &[Pool],
+    mint: &Pubkey,
+    sol_usd: Option<f64>,
+    max_deviation: f64,
+) -> Option<ReferencePrice> {
+    let mut candidates: Vec<PriceSource> = pools
+        .iter()
+        .filter(|pool| !pool.derived)
+        .filter_map(|pool| {
+            let side = pool.side(mint)?;
+            let quote_usd = match side.other_mint {
+                USDC_MINT | USDT_MINT => 1.0,
+                WSOL_MINT => sol_usd?,
+                _ => return None,
+            };
+            let price_usd = side.other_per_token * quote_usd;
+            let depth_usd = side.other_qty * quote_usd;
+            (price_usd.is_finite() && price_usd > 0.0 && depth_usd > 0.0).then_some(PriceSource {
+                pool: pool.address,
+                source: pool.source,
+                quote_mint: side.other_mint,
+                price_usd,
+                depth_usd,
+            })
+        })
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+
+    // Depth-weighted median, so a thin pool can't drag the reference
+    candidates.sort_by(|a, b| a.price_usd.total_cmp(&b.price_usd));
+    let half = candidates.iter().map(|c| c.depth_usd).sum::<f64>() / 2.0;
+    let mut seen = 0.0;
+    let median = candidates
+        .iter()
+        .find(|c| {
+            seen += c.depth_usd;
+            seen >= half
+        })
+        .map_or(candidates[0].price_usd, |c| c.price_usd);
+
+    let (mut sources, rejected): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|c| (c.price_usd / median - 1.0).abs() <= max_deviation);
+    sources.sort_by(|a, b| b.depth_usd.total_cmp(&a.depth_usd));
+    let depth: f64 = sources.iter().map(|s| s.depth_usd).sum();
+    let price_usd = sources
+        .iter()
+        .map(|s| s.price_usd * s.depth_usd)
+        .sum::<f64>()
+        / depth;
+    Some(ReferencePrice {
+        price_usd,
+        sources,
+        rejected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::venue::Fees;
+
+    /// Pool of `qty` whole tokens against `other_qty` of `other`
+    fn pool(mint: Pubkey, other: Pubkey, qty: f64, other_qty: f64) -> Pool {
+        Pool {
+            address: Pubkey::new_unique(),
+            source: "Test",
+            pool_type: "CPMM".to_string(),
+            program_id: Pubkey::new_unique(),
+            mints: [mint, other],
+            decimals: [6, 6],
+            reserves: [(qty * 1e6) as u64, (other_qty * 1e6) as u64],
+            price: other_qty / qty,
+            fees: Fees::default(),
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+
+    #[test]
+    fn weights_by_depth_and_drops_outliers() {
+        let mint = Pubkey::new_unique();
+        let pools = vec![
+            pool(mint, USDC_MINT, 1_000_000.0, 30_000.0),
+            pool(mint, WSOL_MINT, 500_000.0, 153.0),
+            pool(mint, USDT_MINT, 10_000.0, 500.0),
+            pool(mint, Pubkey::new_unique(), 1_000.0, 1.0),
+        ];
+
+        let reference = reference_price(&pools, &mint, Some(100.0), 0.05).unwrap();
+        assert_eq!(reference.best().pool, pools[0].address);
+        assert_eq!(reference.sources.len(), 2);
+        assert_eq!(reference.rejected.len(), 1);
+        assert_eq!(reference.rejected[0].quote_mint, USDT_MINT);
+        let expected = (0.03 * 30_000.0 + 0.0306 * 15_300.0) / 45_300.0;
+        assert!((reference.price_usd - expected).abs() < 1e-12);
+        assert!((reference.weight(reference.best()) - 30_000.0 / 45_300.0).abs() < 1e-12);
+
+        // Without a SOL price only the stablecoin pools are left
+        let reference = reference_price(&pools, &mint, None, 0.05).unwrap();
+        assert_eq!(reference.sources.len(), 1);
+        assert!(reference_price(&pools[3..], &mint, None, 0.05).is_none());
+    }
+}
@@ -0,0 +1,347 @@
+//! pump.fun bonding curves.
+//!
+//! A pump.fun token trades against SOL on its own bonding curve until the
+//! curve sells out, then graduates to a PumpSwap pool and the curve is
+//! marked complete. The curve prices tokens as a constant product over
+//! virtual reserves, with the real reserves bounding what it can pay out.
+//! The fee is charged in SOL on both buys and sells.
+//!
+//! Curves are quoted exactly but not traded: the program swaps native SOL
+//! rather than a token account, which the shared swap path doesn't model.
+
+use crate::common::{read_u64, read_u8, SwapInstructions, SwapQuote, WSOL_MINT};
+use crate::error::{ensure, Error, Result};
+use crate::venue::{Fees, Pool, Venue};
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::MAX_MULTIPLE_ACCOUNTS;
+use solana_sdk::{pubkey, pubkey::Pubkey};
+use std::collections::HashMap;
+use tracing::instrument;
+
+pub const PUMP_PROGRAM_ID: Pubkey = pubkey!("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P");
+
+const BONDING_CURVE_DISCRIMINATOR: [u8; 8] = [23, 183, 248, 55, 96, 216, 172, 96];
+const GLOBAL_DISCRIMINATOR: [u8; 8] = [167, 232, 232, 177, 200, 108, 114, 127];
+
+/// Every pump.fun token has 6 decimals
+pub const TOKEN_DECIMALS: u8 = 6;
+const SOL_DECIMALS: u8 = 9;
+const BASIS_POINTS: u64 = 10_000;
+
+/// pump.fun mints are ground to end in "pump", though not every token on a
+/// curve is and graduated tokens keep the suffix
+pub fn is_pump_mint(mint: &str) -> bool {
+    mint.ends_with("pump")
+}
+
+pub fn bonding_curve_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"bonding-curve", mint.as_ref()], &PUMP_PROGRAM_ID).0
+}
+
+pub fn global_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"global"], &PUMP_PROGRAM_ID).0
+}
+
+// -----------------------------------
+// Accounts
+// -----------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BondingCurve {
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    pub real_token_reserves: u64,
+    pub real_sol_reserves: u64,
+    pub token_total_supply: u64,
+    /// The curve sold out and the token moved to PumpSwap
+    pub complete: bool,
+}
+
+/// Fee settings from the program's global account, in basis points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PumpFees {
+    pub protocol_fee_bps: u64,
+    pub creator_fee_bps: u64,
+}
+
+impl BondingCurve {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&BONDING_CURVE_DISCRIMINATOR),
+            Error::account("pump.fun bonding curve", "wrong discriminator", data)
+        );
+        Ok(Self {
+            virtual_token_reserves: read_u64(data, 8)?,
+            virtual_sol_reserves: read_u64(data, 16)?,
+            real_token_reserves: read_u64(data, 24)?,
+            real_sol_reserves: read_u64(data, 32)?,
+            token_total_supply: read_u64(data, 40)?,
+            complete: read_u8(data, 48)? != 0,
+        })
+    }
+
+    /// SOL per whole token at the current virtual reserves
+    pub fn ui_price(&self) -> f64 {
+        let sol = self.virtual_sol_reserves as f64 / 10f64.powi(SOL_DECIMALS as i32);
+        let tokens = self.virtual_token_reserves as f64 / 10f64.powi(TOKEN_DECIMALS as i32);
+        sol / tokens
+    }
+
+    /// Quote buying tokens with `amount_in` lamports (`buy`) or selling
+    /// `amount_in` tokens for lamports. The fee comes out of the SOL side
+    /// either way, rounded in the curve's favour.
+    pub fn quote(&self, buy: bool, amount_in: u64, fees: PumpFees) -> Result<SwapQuote> {
+        ensure!(!self.complete, Error::quote("Bonding curve is complete"));
+        ensure!(amount_in > 0, Error::invalid("Swap amount is zero"));
+        let fee_bps = (fees.protocol_fee_bps + fees.creator_fee_bps) as u128;
+        let (virtual_sol, virtual_tokens) = (
+            self.virtual_sol_reserves as u128,
+            self.virtual_token_reserves as u128,
+        );
+
+        if buy {
+            let sol_in =
+                amount_in as u128 * BASIS_POINTS as u128 / (BASIS_POINTS as u128 + fee_bps);
+            let tokens_out = sol_in * virtual_tokens / (virtual_sol + sol_in);
+            ensure!(
+                tokens_out <= self.real_token_reserves as u128,
+                Error::quote("Buy exceeds the tokens left on the curve")
+            );
+            Ok(SwapQuote {
+                out_amount: tokens_out as u64,
+                fee: amount_in - sol_in as u64,
+            })
+        } else {
+            let sol_out = amount_in as u128 * virtual_sol / (virtual_tokens + amount_in as u128);
+            ensure!(
+                sol_out <= self.real_sol_reserves as u128,
+                Error::quote("Sell exceeds the SOL held by the curve")
+            );
+            let fee = (sol_out * fee_bps).div_ceil(BASIS_POINTS as u128);
+            Ok(SwapQuote {
+                out_amount: (sol_out - fee) as u64,
+                fee: fee as u64,
+            })
+        }
+    }
+}
+
+impl PumpFees {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&GLOBAL_DISCRIMINATOR),
+            Error::account("pump.fun global account", "wrong discriminator", data)
+        );
+        Ok(Self {
+            protocol_fee_bps: read_u64(data, 105)?,
+            creator_fee_bps: read_u64(data, 154)?,
+        })
+    }
+
+    pub async fn fetch(rpc_client: &RpcClient) -> Result<Self> {
+        let data = rpc_client.get_account_data(&global_address()).await?;
+        Self::decode(&data)
+    }
+
+    pub fn total_bps(&self) -> u64 {
+        self.protocol_fee_bps + self.creator_fee_bps
+    }
+}
+
+// -----------------------------------
+// Lookup
+// -----------------------------------
+
+/// The bonding curve of `mint`, or `None` if it never launched on pump.fun
+pub async fn fetch_bonding_curve(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<BondingCurve>> {
+    Ok(fetch_bonding_curves(rpc_client, &[*mint])
+        .await?
+        .remove(mint))
+}
+
+/// Bonding curves of whichever `mints` launched on pump.fun, keyed by mint
+pub async fn fetch_bonding_curves(
+    rpc_client: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<HashMap<Pubkey, BondingCurve>> {
+    let mut curves = HashMap::new();
+    for chunk in mints.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let keys: Vec<Pubkey> = chunk.iter().map(bonding_curve_address).collect();
+        let accounts = rpc_client.get_multiple_accounts(&keys).await?;
+        for (mint, account) in chunk.iter().zip(accounts) {
+            if let Some(account) = account {
+                curves.insert(*mint, BondingCurve::decode(&account.data)?);
+            }
+        }
+    }
+    Ok(curves)
+}
+
+/// Whether `mint` still trades on its bonding curve rather than a DEX pool
+pub async fn is_on_bonding_curve(rpc_client: &RpcClient, mint: &Pubkey) -> Result<bool> {
+    let curve = fetch_bonding_curve(rpc_client, mint).await?;
+    Ok(curve.is_some_and(|curve| !curve.complete))
+}
+
+// -----------------------------------
+// Venue
+// -----------------------------------
+
+/// Live pump.fun bonding curves, each a token/SOL pool
+pub struct PumpFunVenue<'a> {
+    rpc_client: &'a RpcClient,
+}
+
+impl<'a> PumpFunVenue<'a> {
+    pub fn new(rpc_client: &'a RpcClient) -> Self {
+        Self { rpc_client }
+    }
+
+    fn pool(&self, mint: Pubkey, curve: &BondingCurve, fees: PumpFees) -> Pool {
+        Pool {
+            address: bonding_curve_address(&mint),
+            source: self.name(),
+            pool_type: "Bonding Curve".to_string(),
+            program_id: PUMP_PROGRAM_ID,
+            mints: [mint, WSOL_MINT],
+            decimals: [TOKEN_DECIMALS, SOL_DECIMALS],
+            reserves: [curve.real_token_reserves, curve.real_sol_reserves],
+            price: curve.ui_price(),
+            fees: Fees {
+                trade_fee: fees.total_bps() as f64 / BASIS_POINTS as f64,
+                protocol_share: fees.protocol_fee_bps as f64 / fees.total_bps().max(1) as f64,
+                dynamic: false,
+            },
+            symbols: [None, Some("SOL".to_string())],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+
+    async fn curve(&self, pool: &Pool) -> Result<BondingCurve> {
+        let data = self.rpc_client.get_account_data(&pool.address).await?;
+        BondingCurve::decode(&data)
+    }
+}
+
+#[async_trait]
+impl Venue for PumpFunVenue<'_> {
+    fn name(&self) -> &'static str {
+        "Pump.fun"
+    }
+
+    /// A token has at most one curve, and only while it hasn't graduated
+    #[instrument(skip_all, fields(venue = "pump.fun", %mint))]
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>> {
+        if *mint == WSOL_MINT {
+            return Ok(Vec::new());
+        }
+        let Some(curve) = fetch_bonding_curve(self.rpc_client, mint).await? else {
+            return Ok(Vec::new());
+        };
+        if curve.complete {
+            return Ok(Vec::new());
+        }
+        let fees = PumpFees::fetch(self.rpc_client).await?;
+        Ok(vec![self.pool(*mint, &curve, fees)])
+    }
+
+    async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+        let curve = self.curve(pool).await?;
+        let fees = PumpFees::fetch(self.rpc_client).await?;
+        Ok(self.pool(pool.mints[0], &curve, fees))
+    }
+
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote> {
+        ensure!(
+            pool.mints.contains(input_mint),
+            Error::invalid(format!(
+                "Bonding curve {} does not trade {}",
+                pool.address, input_mint
+            ))
+        );
+        let curve = self.curve(pool).await?;
+        let fees = PumpFees::fetch(self.rpc_client).await?;
+        curve.quote(*input_mint == WSOL_MINT, amount_in, fees)
+    }
+
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        _owner: &Pubkey,
+        _input_mint: &Pubkey,
+        _amount_in: u64,
+        _slippage_bps: u64,
+    ) -> Result<SwapInstructions> {
+        Err(Error::unsupported(format!(
+            "Swapping on pump.fun bonding curve {}",
+            pool.address
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEES: PumpFees = PumpFees {
+        protocol_fee_bps: 95,
+        creator_fee_bps: 5,
+    };
+
+    /// A fresh curve: 30 virtual SOL against 1.073B virtual tokens
+    fn curve() -> BondingCurve {
+        let mut data = vec![0u8; 81];
+        data[..8].copy_from_slice(&BONDING_CURVE_DISCRIMINATOR);
+        data[8..16].copy_from_slice(&1_073_000_000_000_000u64.to_le_bytes());
+        data[16..24].copy_from_slice(&30_000_000_000u64.to_le_bytes());
+        data[24..32].copy_from_slice(&793_100_000_000_000u64.to_le_bytes());
+        data[32..40].copy_from_slice(&1_000_000_000u64.to_le_bytes());
+        data[40..48].copy_from_slice(&1_000_000_000_000_000u64.to_le_bytes());
+        BondingCurve::decode(&data).unwrap()
+    }
+
+    #[test]
+    fn quotes_buys_and_sells_with_the_fee_in_sol() {
+        let curve = curve();
+        assert_eq!(curve.ui_price(), 30.0 / 1_073_000_000.0);
+
+        // 1 SOL pays a 1% fee on the 0.990099 SOL actually swapped
+        let buy = curve.quote(true, 1_000_000_000, FEES).unwrap();
+        assert_eq!(
+            buy,
+            SwapQuote {
+                out_amount: 34_281_150_129_545,
+                fee: 9_900_991,
+            }
+        );
+
+        let sell = curve.quote(false, 10_000_000_000_000, FEES).unwrap();
+        assert_eq!(
+            sell,
+            SwapQuote {
+                out_amount: 274_238_226,
+                fee: 2_770_084,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_swaps_the_curve_cannot_fill() {
+        let mut curve = curve();
+        let error = curve.quote(false, 100_000_000_000_000, FEES).unwrap_err();
+        assert!(
+            matches!(error, Error::Quote { ref reason } if reason == "Sell exceeds the SOL held by the curve")
+        );
+
+        curve.complete = true;
+        let error = curve.quote(true, 1_000_000_000, FEES).unwrap_err();
+        assert!(
+            matches!(error, Error::Quote { ref reason } if reason == "Bonding curve is complete")
+        );
+    }
+}
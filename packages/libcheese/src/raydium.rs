@@ -1,62 +1,205 @@
-use crate::common::CHEESE_MINT;
-use anyhow::{anyhow, Result};
-use reqwest::Client;
-use serde::Deserialize;
+use crate::common::{
+    min_out_amount, swap_with_token_accounts, SwapInstructions, SwapQuote, CHEESE_MINT,
+};
+use crate::error::{ensure, parse_pubkey, Error, Result};
+use crate::http::HttpClient;
+use crate::venue::{Fees, Pool, Venue};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+use std::collections::HashSet;
+use tracing::{debug, info, instrument, warn};
+
+pub mod amm_v4;
+pub mod clmm;
+pub mod cpmm;
+
+use amm_v4::{AmmV4Snapshot, AMM_V4_PROGRAM_ID};
+use clmm::{ClmmSnapshot, CLMM_PROGRAM_ID};
+use cpmm::{CpmmSnapshot, CPMM_PROGRAM_ID};
 
 pub async fn fetch_raydium_mint_ids(
-    client: &Client,
+    client: &HttpClient,
     mints: &[String],
 ) -> Result<Vec<Option<RaydiumMintItem>>> {
     let joined = mints.join(",");
     let url = format!("https://api-v3.raydium.io/mint/ids?mints={}", joined);
-    println!("Requesting minted data from Raydium for mints: {joined}");
+    debug!(mints = mints.len(), "Requesting Raydium mint info");
 
-    let resp = client.get(&url).send().await?;
-    if !resp.status().is_success() {
-        return Err(anyhow!(
-            "Raydium /mint/ids request failed: {}",
-            resp.status()
-        ));
-    }
-
-    let parsed: RaydiumMintIdsResponse = resp.json().await?;
+    let parsed: RaydiumMintIdsResponse = client.json("Raydium /mint/ids", client.get(&url)).await?;
     if !parsed.success {
-        return Err(anyhow!("Raydium /mint/ids returned success=false"));
+        return Err(Error::Api {
+            service: "Raydium /mint/ids",
+        });
     }
 
-    println!(
-        "Got {} minted items from Raydium (some may be None).",
-        parsed.data.len()
-    );
+    debug!(items = parsed.data.len(), "Got Raydium mint info");
     Ok(parsed.data)
 }
 
-pub async fn fetch_raydium_cheese_pools(client: &Client) -> Result<Vec<RaydiumPoolDetailed>> {
-    // fetch pools for cheese
-    let url = format!(
-        "https://api-v3.raydium.io/pools/info/mint?mint1={}&poolType=all&poolSortField=default&sortType=desc&pageSize=1000&page=1",
-        CHEESE_MINT
+pub async fn fetch_raydium_cheese_pools(client: &HttpClient) -> Result<Vec<RaydiumPoolDetailed>> {
+    let query = RaydiumPoolQuery::new(parse_pubkey(CHEESE_MINT)?);
+    let pools = query_raydium_pools(client, &query).await?;
+    info!(
+        venue = "Raydium",
+        pools = pools.len(),
+        "Fetched CHEESE pools"
     );
-    println!("Requesting Raydium cheese pools from {url}");
+    Ok(pools)
+}
 
-    let resp = client.get(&url).send().await?;
-    if !resp.status().is_success() {
-        return Err(anyhow!(
-            "Raydium cheese-pools request failed: {}",
-            resp.status()
-        ));
+/// Every pool trading `mint`, as listed by Raydium's API
+pub async fn fetch_raydium_pools(
+    client: &HttpClient,
+    mint: &Pubkey,
+) -> Result<Vec<RaydiumPoolDetailed>> {
+    query_raydium_pools(client, &RaydiumPoolQuery::new(*mint)).await
+}
+
+/// Every pool matching `query`, paging through Raydium's API until it
+/// reports no more
+pub async fn query_raydium_pools(
+    client: &HttpClient,
+    query: &RaydiumPoolQuery,
+) -> Result<Vec<RaydiumPoolDetailed>> {
+    let url = "https://api-v3.raydium.io/pools/info/mint";
+
+    let mut all_pools: Vec<RaydiumPoolDetailed> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut page = 1;
+    loop {
+        debug!(venue = "Raydium", mint = %query.mint1, page, "Requesting pools page");
+        let mut params = query.params();
+        params.push(("page", page.to_string()));
+        let request = client.get(url).query(&params);
+        let parsed: RaydiumMintPoolsResponse = client.json("Raydium pools", request).await?;
+        if !parsed.success {
+            return Err(Error::Api {
+                service: "Raydium pools",
+            });
+        }
+        debug!(
+            venue = "Raydium",
+            page,
+            pools = parsed.data.data.len(),
+            count = parsed.data.count,
+            "Got pools page"
+        );
+
+        if !add_pools_page(&mut all_pools, &mut seen, parsed.data) {
+            break;
+        }
+        page += 1;
     }
 
-    let parsed: RaydiumMintPoolsResponse = resp.json().await?;
-    if !parsed.success {
-        return Err(anyhow!("Raydium cheese-pools returned success=false"));
+    Ok(all_pools)
+}
+
+/// Add the pools on `page` not already in `pools`, and say whether another
+/// page should be asked for
+fn add_pools_page(
+    pools: &mut Vec<RaydiumPoolDetailed>,
+    seen: &mut HashSet<String>,
+    page: RaydiumMintPoolsData,
+) -> bool {
+    let more = page.hasNextPage && !page.data.is_empty();
+    // Pools can shift between pages while we read them
+    for pool in page.data {
+        if seen.insert(pool.pool_id.clone()) {
+            pools.push(pool);
+        }
     }
+    more
+}
 
-    println!(
-        "Raydium /pools/info/mint returned {} items\n",
-        parsed.data.count
-    );
-    Ok(parsed.data.data)
+/// Which pools `query_raydium_pools` lists, and in what order
+#[derive(Debug, Clone)]
+pub struct RaydiumPoolQuery {
+    pub mint1: Pubkey,
+    /// Only pools pairing `mint1` with this mint
+    pub mint2: Option<Pubkey>,
+    pub pool_type: RaydiumPoolType,
+    pub sort_field: RaydiumSortField,
+    pub descending: bool,
+    /// Pools per request, clamped to 1..=`RAYDIUM_MAX_PAGE_SIZE`
+    pub page_size: u32,
+}
+
+/// Most pools Raydium's API returns per page
+pub const RAYDIUM_MAX_PAGE_SIZE: u32 = 1000;
+
+impl RaydiumPoolQuery {
+    pub fn new(mint1: Pubkey) -> Self {
+        Self {
+            mint1,
+            mint2: None,
+            pool_type: RaydiumPoolType::All,
+            sort_field: RaydiumSortField::Default,
+            descending: true,
+            page_size: RAYDIUM_MAX_PAGE_SIZE,
+        }
+    }
+
+    /// Query string for one page, less the page number
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![("mint1", self.mint1.to_string())];
+        params.extend(self.mint2.map(|mint2| ("mint2", mint2.to_string())));
+        params.extend([
+            ("poolType", self.pool_type.as_str().to_string()),
+            ("poolSortField", self.sort_field.as_str().to_string()),
+            (
+                "sortType",
+                if self.descending { "desc" } else { "asc" }.to_string(),
+            ),
+            (
+                "pageSize",
+                self.page_size.clamp(1, RAYDIUM_MAX_PAGE_SIZE).to_string(),
+            ),
+        ]);
+        params
+    }
+}
+
+/// Raydium's `poolType` filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaydiumPoolType {
+    All,
+    /// Constant-product pools, AMM v4 and CPMM
+    Standard,
+    Concentrated,
+}
+
+impl RaydiumPoolType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Standard => "standard",
+            Self::Concentrated => "concentrated",
+        }
+    }
+}
+
+/// Raydium's `poolSortField`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaydiumSortField {
+    Default,
+    Liquidity,
+    Volume24h,
+    Fee24h,
+    Apr24h,
+}
+
+impl RaydiumSortField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Liquidity => "liquidity",
+            Self::Volume24h => "volume24h",
+            Self::Fee24h => "fee24h",
+            Self::Apr24h => "apr24h",
+        }
+    }
 }
 
 /// Raydium mint query
@@ -71,6 +214,8 @@ pub struct RaydiumMintIdsResponse {
 pub struct RaydiumMintItem {
     pub address: String,
     pub symbol: String,
+    #[serde(default)]
+    pub decimals: u8,
 }
 
 /// Raydium cheese pools
@@ -81,14 +226,15 @@ pub struct RaydiumMintPoolsResponse {
     pub data: RaydiumMintPoolsData,
 }
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct RaydiumMintPoolsData {
     pub count: u64,
     pub data: Vec<RaydiumPoolDetailed>,
-    #[allow(non_snake_case)]
     pub hasNextPage: bool,
 }
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct RaydiumPoolDetailed {
     #[serde(default)]
@@ -123,3 +269,527 @@ pub struct RaydiumDayStats {
     #[serde(default)]
     pub volume: f64,
 }
+
+// -----------------------------------
+// Trading
+// -----------------------------------
+
+/// On-chain state of a pool in any of the Raydium programs we can quote
+#[derive(Debug, Clone)]
+pub enum RaydiumSnapshot {
+    AmmV4(AmmV4Snapshot),
+    Cpmm(CpmmSnapshot),
+    Clmm(ClmmSnapshot),
+}
+
+impl RaydiumSnapshot {
+    /// Read the pool at `address`, decoding it for the program that owns it
+    pub async fn fetch(
+        rpc_client: &RpcClient,
+        program_id: &Pubkey,
+        address: &Pubkey,
+    ) -> Result<Self> {
+        Ok(match *program_id {
+            AMM_V4_PROGRAM_ID => Self::AmmV4(AmmV4Snapshot::fetch(rpc_client, address).await?),
+            CPMM_PROGRAM_ID => Self::Cpmm(CpmmSnapshot::fetch(rpc_client, address).await?),
+            CLMM_PROGRAM_ID => Self::Clmm(ClmmSnapshot::fetch(rpc_client, address).await?),
+            other => return Err(Error::unsupported(format!("Raydium program {}", other))),
+        })
+    }
+
+    pub fn address(&self) -> Pubkey {
+        match self {
+            Self::AmmV4(snapshot) => snapshot.address,
+            Self::Cpmm(snapshot) => snapshot.address,
+            Self::Clmm(snapshot) => snapshot.address,
+        }
+    }
+
+    /// The pool's two mints, in the program's order
+    pub fn mints(&self) -> (Pubkey, Pubkey) {
+        match self {
+            Self::AmmV4(snapshot) => (snapshot.amm.coin_vault_mint, snapshot.amm.pc_vault_mint),
+            Self::Cpmm(snapshot) => (snapshot.pool.token_0_mint, snapshot.pool.token_1_mint),
+            Self::Clmm(snapshot) => (snapshot.pool.token_mint_0, snapshot.pool.token_mint_1),
+        }
+    }
+
+    /// Fail unless the pool trades `input_mint` for `output_mint`
+    fn ensure_pair(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> Result<()> {
+        let (mint_0, mint_1) = self.mints();
+        ensure!(
+            *input_mint == mint_0 && *output_mint == mint_1
+                || *input_mint == mint_1 && *output_mint == mint_0,
+            Error::invalid(format!(
+                "Raydium pool {} does not trade {} -> {}",
+                self.address(),
+                input_mint,
+                output_mint
+            ))
+        );
+        Ok(())
+    }
+
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        match self {
+            Self::AmmV4(snapshot) => snapshot.quote(input_mint, in_amount),
+            Self::Cpmm(snapshot) => snapshot.quote(input_mint, in_amount),
+            Self::Clmm(snapshot) => snapshot.quote(input_mint, in_amount),
+        }
+    }
+
+    /// Accounts a swap writes the pool's new state to: the vaults for
+    /// constant-product pools, which also owe fees out of the pool account,
+    /// and the pool account alone for CLMM
+    pub fn watch_accounts(&self) -> Vec<Pubkey> {
+        match self {
+            Self::AmmV4(snapshot) => vec![
+                snapshot.address,
+                snapshot.amm.coin_vault,
+                snapshot.amm.pc_vault,
+            ],
+            Self::Cpmm(snapshot) => vec![
+                snapshot.address,
+                snapshot.pool.token_0_vault,
+                snapshot.pool.token_1_vault,
+            ],
+            Self::Clmm(snapshot) => vec![snapshot.address],
+        }
+    }
+
+    /// Base units of the other token one base unit of `input_mint` buys at
+    /// the current price, before fees
+    pub fn spot_price(&self, input_mint: &Pubkey) -> Result<f64> {
+        let price = match self {
+            Self::AmmV4(snapshot) => {
+                let (coin, pc) = snapshot.reserves()?;
+                pc as f64 / coin as f64
+            }
+            Self::Cpmm(snapshot) => {
+                let (token_0, token_1) = snapshot.reserves()?;
+                token_1 as f64 / token_0 as f64
+            }
+            Self::Clmm(snapshot) => snapshot.pool.price(),
+        };
+        let (mint_0, mint_1) = self.mints();
+        if *input_mint == mint_0 {
+            Ok(price)
+        } else if *input_mint == mint_1 {
+            Ok(1.0 / price)
+        } else {
+            Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )))
+        }
+    }
+
+    /// Swap instruction selling exactly `amount_in` of `input_mint` from
+    /// `input_account` into `output_account`, both owned by `owner`
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Result<Instruction> {
+        match self {
+            Self::AmmV4(snapshot) => Ok(snapshot.swap_instruction(
+                owner,
+                input_account,
+                output_account,
+                amount_in,
+                min_out,
+            )),
+            Self::Cpmm(snapshot) => snapshot.swap_instruction(
+                owner,
+                input_mint,
+                input_account,
+                output_account,
+                amount_in,
+                min_out,
+            ),
+            Self::Clmm(snapshot) => snapshot.swap_instruction(
+                owner,
+                input_mint,
+                input_account,
+                output_account,
+                amount_in,
+                min_out,
+            ),
+        }
+    }
+}
+
+impl RaydiumPoolDetailed {
+    /// Program that owns the pool. Falls back to the pool type when the API
+    /// leaves `programId` out, which only pins down concentrated pools.
+    pub fn program_id(&self) -> Result<Pubkey> {
+        if !self.programId.is_empty() {
+            return parse_pubkey(&self.programId);
+        }
+        match self.r#type.as_str() {
+            "Concentrated" => Ok(CLMM_PROGRAM_ID),
+            other => Err(Error::Decode {
+                what: format!("Raydium pool {}", self.pool_id),
+                reason: "no program id".to_string(),
+                payload: format!("type {:?}", other),
+            }),
+        }
+    }
+}
+
+/// Quote a swap from the pool's on-chain state. Amounts are in base units
+/// and match what the swap instruction would pay out at the fetched slot.
+pub async fn get_raydium_quote(
+    rpc_client: &RpcClient,
+    pool: &RaydiumPoolDetailed,
+    input_mint: &str,
+    output_mint: &str,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<RaydiumQuoteResponse> {
+    let program_id = pool.program_id()?;
+    let address = parse_pubkey(&pool.pool_id)?;
+    let snapshot = RaydiumSnapshot::fetch(rpc_client, &program_id, &address).await?;
+    let input = parse_pubkey(input_mint)?;
+    let output = parse_pubkey(output_mint)?;
+
+    snapshot.ensure_pair(&input, &output)?;
+
+    let quote = snapshot.quote(&input, amount_in)?;
+
+    // Execution price against the pool's spot price
+    let price_before = snapshot.spot_price(&input)?;
+    let price_after = quote.out_amount as f64 / amount_in as f64;
+    let price_impact = ((price_before - price_after) / price_before * 100.0).to_string();
+
+    Ok(RaydiumQuoteResponse {
+        pool_address: pool.pool_id.clone(),
+        program_id: program_id.to_string(),
+        input_mint: input_mint.to_string(),
+        output_mint: output_mint.to_string(),
+        in_amount: amount_in.to_string(),
+        out_amount: quote.out_amount.to_string(),
+        min_out_amount: min_out_amount(quote.out_amount, slippage_bps)?.to_string(),
+        slippage_bps,
+        fee_amount: quote.fee.to_string(),
+        price_impact,
+    })
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct RaydiumQuoteResponse {
+    pub pool_address: String,
+    pub program_id: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: String,
+    pub out_amount: String,
+    pub min_out_amount: String,
+    pub slippage_bps: u64,
+    pub fee_amount: String,
+    pub price_impact: String,
+}
+
+/// Build a Raydium swap from the pool's on-chain state, without going
+/// through Raydium's hosted API. Any of `owner`'s token accounts the swap
+/// needs are created first if they don't exist yet.
+pub async fn get_raydium_swap_instructions(
+    rpc_client: &RpcClient,
+    pool_address: &str,
+    owner: &Pubkey,
+    input_mint: &str,
+    output_mint: &str,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<SwapInstructions> {
+    let address = parse_pubkey(pool_address)?;
+    let program_id = rpc_client.get_account(&address).await?.owner;
+    let snapshot = RaydiumSnapshot::fetch(rpc_client, &program_id, &address).await?;
+    let input = parse_pubkey(input_mint)?;
+    let output = parse_pubkey(output_mint)?;
+    snapshot.ensure_pair(&input, &output)?;
+
+    let quote = snapshot.quote(&input, amount_in)?;
+    let instructions = swap_with_token_accounts(
+        rpc_client,
+        owner,
+        &input,
+        &output,
+        quote,
+        slippage_bps,
+        |input_account, output_account, min_out| {
+            snapshot.swap_instruction(
+                owner,
+                &input,
+                input_account,
+                output_account,
+                amount_in,
+                min_out,
+            )
+        },
+    )
+    .await?;
+    Ok(instructions)
+}
+
+// -----------------------------------
+// Venue
+// -----------------------------------
+
+/// Raydium AMM v4, CPMM and CLMM pools, listed by Raydium's API and traded
+/// from on-chain state
+pub struct RaydiumVenue<'a> {
+    client: &'a HttpClient,
+    rpc_client: &'a RpcClient,
+}
+
+impl<'a> RaydiumVenue<'a> {
+    pub fn new(client: &'a HttpClient, rpc_client: &'a RpcClient) -> Self {
+        Self { client, rpc_client }
+    }
+
+    fn pool(&self, listing: &RaydiumPoolDetailed) -> Result<Pool> {
+        let base_units = |amount: f64, decimals: u8| (amount * 10f64.powi(decimals as i32)) as u64;
+        Ok(Pool {
+            address: parse_pubkey(&listing.pool_id)?,
+            source: self.name(),
+            pool_type: listing.r#type.clone(),
+            program_id: listing.program_id()?,
+            mints: [
+                parse_pubkey(&listing.mintA.address)?,
+                parse_pubkey(&listing.mintB.address)?,
+            ],
+            decimals: [listing.mintA.decimals, listing.mintB.decimals],
+            reserves: [
+                base_units(listing.mint_amount_a, listing.mintA.decimals),
+                base_units(listing.mint_amount_b, listing.mintB.decimals),
+            ],
+            // Mint B per mint A at the pool's current price, which unlike the
+            // token amounts also holds for concentrated pools
+            price: listing.price,
+            fees: Fees {
+                trade_fee: listing.feeRate,
+                ..Fees::default()
+            },
+            symbols: [
+                Some(listing.mintA.symbol.clone()),
+                Some(listing.mintB.symbol.clone()),
+            ],
+            tvl_usd: Some(listing.tvl),
+            volume_24h_usd: Some(listing.day.volume),
+            derived: false,
+        })
+    }
+}
+
+#[async_trait]
+impl Venue for RaydiumVenue<'_> {
+    fn name(&self) -> &'static str {
+        "Raydium"
+    }
+
+    #[instrument(skip_all, fields(venue = "Raydium", %mint))]
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>> {
+        let mut pools = Vec::new();
+        for listing in fetch_raydium_pools(self.client, mint).await? {
+            match self.pool(&listing) {
+                Ok(pool) => pools.push(pool),
+                Err(e) => warn!(pool = %listing.pool_id, error = %e, "Skipping Raydium pool"),
+            }
+        }
+        Ok(pools)
+    }
+
+    async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+        let snapshot =
+            RaydiumSnapshot::fetch(self.rpc_client, &pool.program_id, &pool.address).await?;
+        let mut refreshed = pool.clone();
+        // CLMM liquidity isn't a pair of reserves, so only its price moves
+        match &snapshot {
+            RaydiumSnapshot::AmmV4(amm) => {
+                let (coin, pc) = amm.reserves()?;
+                refreshed.reserves = [coin, pc];
+            }
+            RaydiumSnapshot::Cpmm(cpmm) => {
+                let (token_0, token_1) = cpmm.reserves()?;
+                refreshed.reserves = [token_0, token_1];
+            }
+            RaydiumSnapshot::Clmm(_) => {}
+        }
+        let [decimals_0, decimals_1] = pool.decimals;
+        refreshed.price = snapshot.spot_price(&pool.mints[0])?
+            * 10f64.powi(decimals_0 as i32 - decimals_1 as i32);
+        Ok(refreshed)
+    }
+
+    async fn watch_accounts(&self, pool: &Pool) -> Result<Vec<Pubkey>> {
+        let snapshot =
+            RaydiumSnapshot::fetch(self.rpc_client, &pool.program_id, &pool.address).await?;
+        Ok(snapshot.watch_accounts())
+    }
+
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote> {
+        RaydiumSnapshot::fetch(self.rpc_client, &pool.program_id, &pool.address)
+            .await?
+            .quote(input_mint, amount_in)
+    }
+
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<SwapInstructions> {
+        let side = pool.side(input_mint).ok_or_else(|| {
+            Error::invalid(format!(
+                "Pool {} does not trade {}",
+                pool.address, input_mint
+            ))
+        })?;
+        let snapshot =
+            RaydiumSnapshot::fetch(self.rpc_client, &pool.program_id, &pool.address).await?;
+        let quote = snapshot.quote(input_mint, amount_in)?;
+        swap_with_token_accounts(
+            self.rpc_client,
+            owner,
+            input_mint,
+            &side.other_mint,
+            quote,
+            slippage_bps,
+            |input_account, output_account, min_out| {
+                snapshot.swap_instruction(
+                    owner,
+                    input_mint,
+                    input_account,
+                    output_account,
+                    amount_in,
+                    min_out,
+                )
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(ids: &[&str], has_next_page: bool) -> RaydiumMintPoolsData {
+        let pools: Vec<_> = ids
+            .iter()
+            .map(|id| {
+                serde_json::json!({
+                    "id": id,
+                    "mintA": { "address": CHEESE_MINT, "symbol": "CHEESE" },
+                    "mintB": { "address": "So11111111111111111111111111111111111111112", "symbol": "WSOL" },
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "count": ids.len(),
+            "data": pools,
+            "hasNextPage": has_next_page,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn builds_pool_query_params() {
+        let mint1 = Pubkey::new_unique();
+        let mint2 = Pubkey::new_unique();
+        let query = RaydiumPoolQuery::new(mint1);
+        assert_eq!(
+            query.params(),
+            vec![
+                ("mint1", mint1.to_string()),
+                ("poolType", "all".to_string()),
+                ("poolSortField", "default".to_string()),
+                ("sortType", "desc".to_string()),
+                ("pageSize", "1000".to_string()),
+            ]
+        );
+
+        let query = RaydiumPoolQuery {
+            mint2: Some(mint2),
+            pool_type: RaydiumPoolType::Concentrated,
+            sort_field: RaydiumSortField::Liquidity,
+            descending: false,
+            page_size: 50,
+            ..query
+        };
+        assert_eq!(
+            query.params(),
+            vec![
+                ("mint1", mint1.to_string()),
+                ("mint2", mint2.to_string()),
+                ("poolType", "concentrated".to_string()),
+                ("poolSortField", "liquidity".to_string()),
+                ("sortType", "asc".to_string()),
+                ("pageSize", "50".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_every_pool_type_and_sort_field() {
+        let mut query = RaydiumPoolQuery::new(Pubkey::new_unique());
+        for (pool_type, name) in [
+            (RaydiumPoolType::All, "all"),
+            (RaydiumPoolType::Standard, "standard"),
+            (RaydiumPoolType::Concentrated, "concentrated"),
+        ] {
+            query.pool_type = pool_type;
+            assert!(query.params().contains(&("poolType", name.to_string())));
+        }
+        for (sort_field, name) in [
+            (RaydiumSortField::Default, "default"),
+            (RaydiumSortField::Liquidity, "liquidity"),
+            (RaydiumSortField::Volume24h, "volume24h"),
+            (RaydiumSortField::Fee24h, "fee24h"),
+            (RaydiumSortField::Apr24h, "apr24h"),
+        ] {
+            query.sort_field = sort_field;
+            assert!(query
+                .params()
+                .contains(&("poolSortField", name.to_string())));
+        }
+    }
+
+    #[test]
+    fn clamps_page_size() {
+        let mut query = RaydiumPoolQuery::new(Pubkey::new_unique());
+        query.page_size = 5000;
+        assert!(query.params().contains(&("pageSize", "1000".to_string())));
+        query.page_size = 0;
+        assert!(query.params().contains(&("pageSize", "1".to_string())));
+    }
+
+    #[test]
+    fn pages_until_the_last_or_an_empty_page() {
+        let mut pools = Vec::new();
+        let mut seen = HashSet::new();
+        assert!(add_pools_page(
+            &mut pools,
+            &mut seen,
+            page(&["a", "b"], true)
+        ));
+        // "b" shifted onto the second page
+        assert!(!add_pools_page(
+            &mut pools,
+            &mut seen,
+            page(&["b", "c"], false)
+        ));
+        let ids: Vec<_> = pools.iter().map(|pool| pool.pool_id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+
+        assert!(!add_pools_page(&mut pools, &mut seen, page(&[], true)));
+        assert_eq!(pools.len(), 3);
+    }
+}
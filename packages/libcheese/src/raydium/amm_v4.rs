@@ -0,0 +1,254 @@
+//! On-chain state and exact quoting for Raydium AMM v4 pools.
+//!
+//! An AMM v4 pool is a constant product pool over two token vaults. The
+//! vaults also hold PnL the pool owes to the protocol, which the program
+//! leaves out of the reserves before it prices a swap. Swaps go through
+//! `SwapBaseInV2`, which skips the OpenBook market accounts.
+
+use crate::common::{fetch_accounts, read_pubkey, read_u64, SwapQuote};
+use crate::error::{ensure, Error, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+use std::collections::HashMap;
+
+pub const AMM_V4_PROGRAM_ID: Pubkey = pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8");
+/// PDA seeded with `b"amm authority"` that owns every pool's vaults
+pub const AMM_V4_AUTHORITY: Pubkey = pubkey!("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1");
+
+/// Instruction tag of `SwapBaseInV2`
+const SWAP_BASE_IN_V2: u8 = 16;
+
+const AMM_INFO_LEN: usize = 752;
+
+/// `AmmStatus` values that accept swaps
+const STATUS_INITIALIZED: u64 = 1;
+const STATUS_SWAP_ONLY: u64 = 6;
+const STATUS_WAITING_TRADE: u64 = 7;
+
+// -----------------------------------
+// Pool Account
+// -----------------------------------
+#[derive(Debug, Clone)]
+pub struct AmmInfo {
+    pub status: u64,
+    pub coin_decimals: u64,
+    pub pc_decimals: u64,
+    pub swap_fee_numerator: u64,
+    pub swap_fee_denominator: u64,
+    pub need_take_pnl_coin: u64,
+    pub need_take_pnl_pc: u64,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub coin_vault_mint: Pubkey,
+    pub pc_vault_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub open_orders: Pubkey,
+    pub market: Pubkey,
+    pub market_program: Pubkey,
+    pub target_orders: Pubkey,
+}
+
+impl AmmInfo {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        // AMM v4 predates Anchor, so there's no discriminator to check
+        ensure!(
+            data.len() == AMM_INFO_LEN,
+            Error::account("Raydium AMM v4 pool", "wrong length", data)
+        );
+        Ok(Self {
+            status: read_u64(data, 0)?,
+            coin_decimals: read_u64(data, 32)?,
+            pc_decimals: read_u64(data, 40)?,
+            swap_fee_numerator: read_u64(data, 176)?,
+            swap_fee_denominator: read_u64(data, 184)?,
+            need_take_pnl_coin: read_u64(data, 192)?,
+            need_take_pnl_pc: read_u64(data, 200)?,
+            coin_vault: read_pubkey(data, 336)?,
+            pc_vault: read_pubkey(data, 368)?,
+            coin_vault_mint: read_pubkey(data, 400)?,
+            pc_vault_mint: read_pubkey(data, 432)?,
+            lp_mint: read_pubkey(data, 464)?,
+            open_orders: read_pubkey(data, 496)?,
+            market: read_pubkey(data, 528)?,
+            market_program: read_pubkey(data, 560)?,
+            target_orders: read_pubkey(data, 592)?,
+        })
+    }
+
+    pub fn swap_enabled(&self) -> bool {
+        matches!(
+            self.status,
+            STATUS_INITIALIZED | STATUS_SWAP_ONLY | STATUS_WAITING_TRADE
+        )
+    }
+}
+
+// -----------------------------------
+// Quoting
+// -----------------------------------
+
+/// A pool and its vault balances, read at one point in time
+#[derive(Debug, Clone)]
+pub struct AmmV4Snapshot {
+    pub address: Pubkey,
+    pub amm: AmmInfo,
+    pub coin_vault_amount: u64,
+    pub pc_vault_amount: u64,
+}
+
+impl AmmV4Snapshot {
+    pub async fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self> {
+        let amm_data = rpc_client.get_account_data(address).await?;
+        let amm = AmmInfo::decode(&amm_data)?;
+
+        let mut accounts = HashMap::new();
+        fetch_accounts(rpc_client, &[amm.coin_vault, amm.pc_vault], &mut accounts).await?;
+        Self::from_accounts(*address, amm, &accounts)
+    }
+
+    pub fn from_accounts(
+        address: Pubkey,
+        amm: AmmInfo,
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<Self> {
+        let vault_amount = |key: &Pubkey| -> Result<u64> {
+            let data = accounts
+                .get(key)
+                .ok_or(Error::AccountNotFound { account: *key })?;
+            read_u64(data, 64)
+        };
+        Ok(Self {
+            address,
+            coin_vault_amount: vault_amount(&amm.coin_vault)?,
+            pc_vault_amount: vault_amount(&amm.pc_vault)?,
+            amm,
+        })
+    }
+
+    /// Coin and pc reserves the curve sees, net of PnL owed to the protocol
+    pub fn reserves(&self) -> Result<(u64, u64)> {
+        let coin = self
+            .coin_vault_amount
+            .checked_sub(self.amm.need_take_pnl_coin)
+            .ok_or_else(|| Error::quote("Coin vault holds less than the pool's pending PnL"))?;
+        let pc = self
+            .pc_vault_amount
+            .checked_sub(self.amm.need_take_pnl_pc)
+            .ok_or_else(|| Error::quote("Pc vault holds less than the pool's pending PnL"))?;
+        Ok((coin, pc))
+    }
+
+    /// Quote swapping `in_amount` base units of `input_mint`. The fee is
+    /// taken from the input.
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        ensure!(
+            self.amm.swap_enabled(),
+            Error::quote("Raydium AMM v4 pool is not swapping")
+        );
+        let (coin, pc) = self.reserves()?;
+        let (in_reserve, out_reserve) = if *input_mint == self.amm.coin_vault_mint {
+            (coin, pc)
+        } else if *input_mint == self.amm.pc_vault_mint {
+            (pc, coin)
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )));
+        };
+        ensure!(
+            self.amm.swap_fee_denominator > 0,
+            Error::quote("Pool has no fee denominator")
+        );
+
+        let fee = (in_amount as u128 * self.amm.swap_fee_numerator as u128)
+            .div_ceil(self.amm.swap_fee_denominator as u128);
+        let in_after_fee = in_amount as u128 - fee;
+        let out_amount = in_after_fee * out_reserve as u128 / (in_reserve as u128 + in_after_fee);
+
+        Ok(SwapQuote {
+            out_amount: u64::try_from(out_amount)?,
+            fee: u64::try_from(fee)?,
+        })
+    }
+
+    /// Instruction selling `amount_in` from `input_account` into
+    /// `output_account`, both owned by `owner`. The program works out the
+    /// direction from the accounts' mints.
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Instruction {
+        let mut data = vec![SWAP_BASE_IN_V2];
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        Instruction {
+            program_id: AMM_V4_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.address, false),
+                AccountMeta::new_readonly(AMM_V4_AUTHORITY, false),
+                AccountMeta::new(self.amm.coin_vault, false),
+                AccountMeta::new(self.amm.pc_vault, false),
+                AccountMeta::new(*input_account, false),
+                AccountMeta::new(*output_account, false),
+                AccountMeta::new_readonly(*owner, true),
+            ],
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COIN: Pubkey = pubkey!("A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump");
+    const PC: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+
+    fn snapshot() -> AmmV4Snapshot {
+        let mut data = vec![0u8; AMM_INFO_LEN];
+        data[0..8].copy_from_slice(&STATUS_SWAP_ONLY.to_le_bytes());
+        data[176..184].copy_from_slice(&25u64.to_le_bytes());
+        data[184..192].copy_from_slice(&10_000u64.to_le_bytes());
+        data[192..200].copy_from_slice(&1_000u64.to_le_bytes());
+        data[400..432].copy_from_slice(COIN.as_ref());
+        data[432..464].copy_from_slice(PC.as_ref());
+        AmmV4Snapshot {
+            address: Pubkey::new_unique(),
+            amm: AmmInfo::decode(&data).unwrap(),
+            coin_vault_amount: 1_000_001_000,
+            pc_vault_amount: 50_000_000,
+        }
+    }
+
+    #[test]
+    fn quotes_constant_product_net_of_pnl() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.reserves().unwrap(), (1_000_000_000, 50_000_000));
+
+        // 0.25% of 10_000_001 rounds up to 25_001
+        let quote = snapshot.quote(&COIN, 10_000_001).unwrap();
+        assert_eq!(quote.fee, 25_001);
+        // 9_975_000 * 50_000_000 / 1_009_975_000
+        assert_eq!(quote.out_amount, 493_824);
+
+        let quote = snapshot.quote(&PC, 1_000_000).unwrap();
+        assert_eq!(quote.fee, 2_500);
+        assert_eq!(quote.out_amount, 19_559_782);
+    }
+
+    #[test]
+    fn authority_is_the_program_pda() {
+        let (authority, _) = Pubkey::find_program_address(&[b"amm authority"], &AMM_V4_PROGRAM_ID);
+        assert_eq!(authority, AMM_V4_AUTHORITY);
+    }
+}
@@ -0,0 +1,899 @@
+//! On-chain state and exact quoting for Raydium CLMM pools.
+//!
+//! A CLMM pool concentrates liquidity between ticks. Liquidity only changes
+//! at initialized ticks, which live 60 to a tick array account, and the
+//! pool's bitmap records which tick arrays exist. Quotes replay the swap
+//! instruction's walk from one initialized tick to the next with the same
+//! Q64.64 sqrt-price math and rounding, so they match what it pays out.
+//!
+//! Swaps go through `swap_v2` with the tick arrays the quote walked as
+//! remaining accounts.
+//!
+//! Limit orders resting on ticks are not filled here, so a quote that
+//! crosses them can only come out low. Pools with dynamic fees or fees
+//! collected in a single token are rejected.
+
+use crate::common::{
+    fetch_accounts, read_i32, read_pubkey, read_u128, read_u16, read_u32, read_u64, read_u8,
+    SwapQuote, MEMO_PROGRAM_ID, TOKEN_2022_PROGRAM_ID,
+};
+use crate::error::{ensure, Error, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+use bn::U512;
+
+#[allow(clippy::manual_div_ceil)]
+mod bn {
+    uint::construct_uint! {
+        pub(crate) struct U512(8);
+    }
+}
+
+pub const CLMM_PROGRAM_ID: Pubkey = pubkey!("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK");
+
+const POOL_DISCRIMINATOR: [u8; 8] = [247, 237, 227, 245, 215, 195, 222, 70];
+const CONFIG_DISCRIMINATOR: [u8; 8] = [218, 244, 33, 104, 203, 203, 43, 111];
+const TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [192, 155, 85, 205, 49, 249, 129, 42];
+const SWAP_V2_DISCRIMINATOR: [u8; 8] = [43, 4, 237, 11, 26, 201, 30, 98];
+
+const TICKS_PER_ARRAY: i32 = 60;
+const TICK_ARRAY_HEADER_LEN: usize = 44;
+const TICK_LEN: usize = 168;
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+const MIN_SQRT_PRICE_X64: u128 = 4_295_048_016;
+const MAX_SQRT_PRICE_X64: u128 = 79_226_673_521_066_979_257_578_248_091;
+/// The pool's own bitmap tracks tick arrays -512..=511, counted in arrays
+/// from tick 0; the rest live in an extension account we don't read
+const BITMAP_RANGE: RangeInclusive<i32> = -512..=511;
+/// Tick arrays loaded on each side of the current one
+const TICK_ARRAYS_PER_SIDE: usize = 8;
+
+/// Fee rates are numerators over this
+const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+/// Status bit that disables swaps
+const STATUS_SWAP_DISABLED: u8 = 1 << 4;
+/// Sqrt prices are Q64.64 fixed point
+const RESOLUTION: usize = 64;
+
+// -----------------------------------
+// Pool, Config and Tick Accounts
+// -----------------------------------
+#[derive(Debug, Clone)]
+pub struct ClmmPool {
+    pub amm_config: Pubkey,
+    pub token_mint_0: Pubkey,
+    pub token_mint_1: Pubkey,
+    pub token_vault_0: Pubkey,
+    pub token_vault_1: Pubkey,
+    pub observation_key: Pubkey,
+    pub mint_decimals_0: u8,
+    pub mint_decimals_1: u8,
+    pub tick_spacing: u16,
+    pub liquidity: u128,
+    /// Square root of the price of token 0 in token 1, Q64.64
+    pub sqrt_price_x64: u128,
+    pub tick_current: i32,
+    pub status: u8,
+    /// 0 takes the fee from the input, otherwise it is collected in one token
+    pub fee_on: u8,
+    /// One bit per tick array that exists, for arrays -512..=511
+    pub tick_array_bitmap: [u64; 16],
+    /// Non-zero when the pool charges a volatility-based fee on top of the
+    /// config's trade fee
+    pub dynamic_fee_control: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClmmConfig {
+    pub protocol_fee_rate: u32,
+    pub trade_fee_rate: u32,
+    pub tick_spacing: u16,
+    pub fund_fee_rate: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    pub tick: i32,
+    /// Liquidity added when the price crosses this tick upwards
+    pub liquidity_net: i128,
+    pub liquidity_gross: u128,
+}
+
+#[derive(Debug, Clone)]
+pub struct TickArray {
+    pub pool_id: Pubkey,
+    pub start_tick_index: i32,
+    pub ticks: Vec<Tick>,
+}
+
+impl ClmmPool {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&POOL_DISCRIMINATOR),
+            Error::account("Raydium CLMM pool", "wrong discriminator", data)
+        );
+        let mut tick_array_bitmap = [0; 16];
+        for (i, word) in tick_array_bitmap.iter_mut().enumerate() {
+            *word = read_u64(data, 904 + i * 8)?;
+        }
+
+        Ok(Self {
+            amm_config: read_pubkey(data, 9)?,
+            token_mint_0: read_pubkey(data, 73)?,
+            token_mint_1: read_pubkey(data, 105)?,
+            token_vault_0: read_pubkey(data, 137)?,
+            token_vault_1: read_pubkey(data, 169)?,
+            observation_key: read_pubkey(data, 201)?,
+            mint_decimals_0: read_u8(data, 233)?,
+            mint_decimals_1: read_u8(data, 234)?,
+            tick_spacing: read_u16(data, 235)?,
+            liquidity: read_u128(data, 237)?,
+            sqrt_price_x64: read_u128(data, 253)?,
+            tick_current: read_i32(data, 269)?,
+            status: read_u8(data, 389)?,
+            fee_on: read_u8(data, 390)?,
+            tick_array_bitmap,
+            dynamic_fee_control: read_u32(data, 1102)?,
+        })
+    }
+
+    pub fn swap_enabled(&self) -> bool {
+        self.status & STATUS_SWAP_DISABLED == 0
+    }
+
+    /// Price of one base unit of token 0 in base units of token 1
+    pub fn price(&self) -> f64 {
+        let sqrt_price = self.sqrt_price_x64 as f64 / 2f64.powi(RESOLUTION as i32);
+        sqrt_price * sqrt_price
+    }
+
+    /// Ticks covered by one tick array
+    fn ticks_per_array(&self) -> i32 {
+        self.tick_spacing as i32 * TICKS_PER_ARRAY
+    }
+
+    /// Index, counted in arrays from tick 0, of the tick array holding `tick`
+    pub fn tick_array_index(&self, tick: i32) -> i32 {
+        tick.div_euclid(self.ticks_per_array())
+    }
+
+    fn has_tick_array(&self, index: i32) -> bool {
+        if !BITMAP_RANGE.contains(&index) {
+            return false;
+        }
+        let bit = (index - BITMAP_RANGE.start()) as usize;
+        self.tick_array_bitmap[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    /// Up to `limit` existing tick arrays past `from`, nearest first
+    fn tick_arrays_from(&self, from: i32, step: i32, limit: usize) -> Vec<i32> {
+        std::iter::successors(Some(from + step), |index| Some(index + step))
+            .take_while(|index| BITMAP_RANGE.contains(index))
+            .filter(|index| self.has_tick_array(*index))
+            .take(limit)
+            .collect()
+    }
+}
+
+impl ClmmConfig {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&CONFIG_DISCRIMINATOR),
+            Error::account("Raydium CLMM config", "wrong discriminator", data)
+        );
+        Ok(Self {
+            protocol_fee_rate: read_u32(data, 43)?,
+            trade_fee_rate: read_u32(data, 47)?,
+            tick_spacing: read_u16(data, 51)?,
+            fund_fee_rate: read_u32(data, 53)?,
+        })
+    }
+}
+
+impl Tick {
+    fn decode(data: &[u8], offset: usize) -> Result<Self> {
+        Ok(Self {
+            tick: read_i32(data, offset)?,
+            liquidity_net: read_u128(data, offset + 4)? as i128,
+            liquidity_gross: read_u128(data, offset + 20)?,
+        })
+    }
+
+    pub fn initialized(&self) -> bool {
+        self.liquidity_gross != 0
+    }
+}
+
+impl TickArray {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.starts_with(&TICK_ARRAY_DISCRIMINATOR),
+            Error::account("Raydium CLMM tick array", "wrong discriminator", data)
+        );
+        let ticks = (0..TICKS_PER_ARRAY as usize)
+            .map(|i| Tick::decode(data, TICK_ARRAY_HEADER_LEN + i * TICK_LEN))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            pool_id: read_pubkey(data, 8)?,
+            start_tick_index: read_i32(data, 40)?,
+            ticks,
+        })
+    }
+}
+
+/// Address of the tick array starting at `start_tick_index`. The index is
+/// seeded big-endian.
+pub fn tick_array_address(pool: &Pubkey, start_tick_index: i32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"tick_array",
+            pool.as_ref(),
+            &start_tick_index.to_be_bytes(),
+        ],
+        &CLMM_PROGRAM_ID,
+    )
+    .0
+}
+
+// -----------------------------------
+// Quoting
+// -----------------------------------
+
+/// A pool, its config and the tick arrays around its current tick, read at
+/// one point in time
+#[derive(Debug, Clone)]
+pub struct ClmmSnapshot {
+    pub address: Pubkey,
+    pub pool: ClmmPool,
+    pub config: ClmmConfig,
+    /// Loaded tick arrays by index, counted in arrays from tick 0
+    pub tick_arrays: BTreeMap<i32, TickArray>,
+    /// Tick arrays whose contents are known, loaded or known not to exist
+    pub known_arrays: RangeInclusive<i32>,
+}
+
+impl ClmmSnapshot {
+    /// Read the pool, its config and the tick arrays nearest the current tick
+    pub async fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self> {
+        let pool_data = rpc_client.get_account_data(address).await?;
+        let pool = ClmmPool::decode(&pool_data)?;
+
+        let current = pool.tick_array_index(pool.tick_current);
+        let mut keys = vec![pool.amm_config];
+        keys.extend(
+            std::iter::once(current)
+                .filter(|index| pool.has_tick_array(*index))
+                .chain(pool.tick_arrays_from(current, -1, TICK_ARRAYS_PER_SIDE))
+                .chain(pool.tick_arrays_from(current, 1, TICK_ARRAYS_PER_SIDE))
+                .map(|index| tick_array_address(address, index * pool.ticks_per_array())),
+        );
+        let mut accounts = HashMap::new();
+        fetch_accounts(rpc_client, &keys, &mut accounts).await?;
+
+        Self::from_accounts(*address, pool, &accounts)
+    }
+
+    /// Assemble a snapshot from already fetched account data. Tick arrays are
+    /// taken outwards from the current one until the first that's missing.
+    pub fn from_accounts(
+        address: Pubkey,
+        pool: ClmmPool,
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<Self> {
+        let config_data = accounts
+            .get(&pool.amm_config)
+            .ok_or(Error::AccountNotFound {
+                account: pool.amm_config,
+            })?;
+        let config = ClmmConfig::decode(config_data)?;
+
+        let current = pool.tick_array_index(pool.tick_current);
+        ensure!(
+            BITMAP_RANGE.contains(&current),
+            Error::quote(format!(
+                "Current tick array {} is outside the pool's bitmap",
+                current
+            ))
+        );
+
+        let mut tick_arrays = BTreeMap::new();
+        let mut bounds = [current, current];
+        for (bound, step) in bounds.iter_mut().zip([-1, 1]) {
+            let indices = std::iter::once(current)
+                .filter(|index| pool.has_tick_array(*index))
+                .chain(pool.tick_arrays_from(current, step, usize::MAX));
+            let mut exhausted = true;
+            for index in indices {
+                let start_tick_index = index * pool.ticks_per_array();
+                let Some(data) = accounts.get(&tick_array_address(&address, start_tick_index))
+                else {
+                    exhausted = false;
+                    break;
+                };
+                let array = TickArray::decode(data)?;
+                ensure!(
+                    array.pool_id == address && array.start_tick_index == start_tick_index,
+                    Error::account(
+                        "Raydium CLMM tick array",
+                        format!(
+                            "tick array {} does not belong to pool {}",
+                            start_tick_index, address
+                        ),
+                        data,
+                    )
+                );
+                tick_arrays.insert(index, array);
+                *bound = index;
+            }
+            // Past the last array that exists there is nothing left to trade
+            if exhausted {
+                *bound = if step < 0 {
+                    *BITMAP_RANGE.start()
+                } else {
+                    *BITMAP_RANGE.end()
+                };
+            }
+        }
+
+        Ok(Self {
+            address,
+            pool,
+            config,
+            tick_arrays,
+            known_arrays: bounds[0]..=bounds[1],
+        })
+    }
+
+    /// The next initialized tick a swap from `tick` meets: at or below it
+    /// when selling token 0, above it when selling token 1
+    fn next_initialized_tick(&self, tick: i32, zero_for_one: bool) -> Result<&Tick> {
+        let step = if zero_for_one { -1 } else { 1 };
+        let mut index = self.pool.tick_array_index(tick);
+        loop {
+            if !self.known_arrays.contains(&index) {
+                ensure!(
+                    BITMAP_RANGE.contains(&index),
+                    Error::quote("Not enough liquidity in the pool for this swap")
+                );
+                return Err(Error::quote("Swap needs tick arrays beyond those loaded"));
+            }
+            if let Some(array) = self.tick_arrays.get(&index) {
+                let mut ticks = array.ticks.iter().filter(|t| t.initialized());
+                let next = if zero_for_one {
+                    ticks.rfind(|t| t.tick <= tick)
+                } else {
+                    ticks.find(|t| t.tick > tick)
+                };
+                if let Some(next) = next {
+                    return Ok(next);
+                }
+            }
+            index += step;
+        }
+    }
+
+    /// Quote swapping `in_amount` base units of `input_mint`. The fee is in
+    /// the input token.
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        Ok(self.swap(input_mint, in_amount)?.0)
+    }
+
+    /// Replay the swap, returning its quote and the index of the last tick
+    /// array it read
+    fn swap(&self, input_mint: &Pubkey, in_amount: u64) -> Result<(SwapQuote, i32)> {
+        ensure!(
+            self.pool.swap_enabled(),
+            Error::quote("Raydium CLMM pool is not swapping")
+        );
+        ensure!(
+            self.pool.dynamic_fee_control == 0 && self.pool.fee_on == 0,
+            Error::unsupported("Raydium CLMM pools with dynamic or single-token fees")
+        );
+        let zero_for_one = if *input_mint == self.pool.token_mint_0 {
+            true
+        } else if *input_mint == self.pool.token_mint_1 {
+            false
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )));
+        };
+        // The swap instruction's default limit when none is given
+        let sqrt_price_limit = if zero_for_one {
+            MIN_SQRT_PRICE_X64 + 1
+        } else {
+            MAX_SQRT_PRICE_X64 - 1
+        };
+
+        let mut sqrt_price = self.pool.sqrt_price_x64;
+        let mut tick = self.pool.tick_current;
+        let mut liquidity = self.pool.liquidity;
+        let mut remaining = in_amount;
+        let mut out_amount = 0u64;
+        let mut fee = 0u64;
+        let mut last_array = self.pool.tick_array_index(tick);
+        while remaining > 0 && sqrt_price != sqrt_price_limit {
+            let next = self.next_initialized_tick(tick, zero_for_one)?;
+            last_array = self.pool.tick_array_index(next.tick);
+            let tick_next = next.tick.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next = sqrt_price_at_tick(tick_next)?;
+            let target = if zero_for_one {
+                sqrt_price_next.max(sqrt_price_limit)
+            } else {
+                sqrt_price_next.min(sqrt_price_limit)
+            };
+
+            let step = compute_swap_step(
+                sqrt_price,
+                target,
+                liquidity,
+                remaining,
+                self.config.trade_fee_rate,
+                zero_for_one,
+            )?;
+            remaining = remaining
+                .checked_sub(step.amount_in + step.fee)
+                .ok_or_else(|| Error::quote("Swap step spends more than the remaining input"))?;
+            out_amount = out_amount
+                .checked_add(step.amount_out)
+                .ok_or_else(|| Error::quote("Swap output overflows"))?;
+            fee += step.fee;
+            sqrt_price = step.sqrt_price_next;
+
+            if sqrt_price == sqrt_price_next {
+                // Crossing a tick downwards removes the liquidity it added
+                let delta = if zero_for_one {
+                    -next.liquidity_net
+                } else {
+                    next.liquidity_net
+                };
+                liquidity = liquidity
+                    .checked_add_signed(delta)
+                    .ok_or_else(|| Error::quote("Tick crossing leaves negative liquidity"))?;
+                tick = if zero_for_one {
+                    tick_next - 1
+                } else {
+                    tick_next
+                };
+            }
+        }
+
+        Ok((SwapQuote { out_amount, fee }, last_array))
+    }
+
+    /// `swap_v2` instruction selling exactly `amount_in` of `input_mint`
+    /// from `input_account` into `output_account`, both owned by `owner`.
+    ///
+    /// The program wants every tick array the swap reaches, in order, from
+    /// the first that exists at or past the current tick. We pass the ones
+    /// our own quote walked plus the next, in case the fill runs slightly
+    /// further than quoted.
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Result<Instruction> {
+        let pool = &self.pool;
+        let (_, last_array) = self.swap(input_mint, amount_in)?;
+        let zero_for_one = *input_mint == pool.token_mint_0;
+        let (in_vault, out_vault, in_mint, out_mint) = if zero_for_one {
+            (
+                pool.token_vault_0,
+                pool.token_vault_1,
+                pool.token_mint_0,
+                pool.token_mint_1,
+            )
+        } else {
+            (
+                pool.token_vault_1,
+                pool.token_vault_0,
+                pool.token_mint_1,
+                pool.token_mint_0,
+            )
+        };
+
+        let step = if zero_for_one { -1 } else { 1 };
+        let current = pool.tick_array_index(pool.tick_current);
+        let mut tick_arrays = Vec::new();
+        for index in std::iter::once(current)
+            .filter(|index| pool.has_tick_array(*index))
+            .chain(pool.tick_arrays_from(current, step, usize::MAX))
+        {
+            tick_arrays.push(tick_array_address(
+                &self.address,
+                index * pool.ticks_per_array(),
+            ));
+            if (index - last_array) * step > 0 {
+                break;
+            }
+        }
+
+        let mut data = SWAP_V2_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        // Zero leaves the price limit to the program's default
+        data.extend_from_slice(&0u128.to_le_bytes());
+        data.push(1); // is_base_input
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(pool.amm_config, false),
+            AccountMeta::new(self.address, false),
+            AccountMeta::new(*input_account, false),
+            AccountMeta::new(*output_account, false),
+            AccountMeta::new(in_vault, false),
+            AccountMeta::new(out_vault, false),
+            AccountMeta::new(pool.observation_key, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(TOKEN_2022_PROGRAM_ID, false),
+            AccountMeta::new_readonly(MEMO_PROGRAM_ID, false),
+            AccountMeta::new_readonly(in_mint, false),
+            AccountMeta::new_readonly(out_mint, false),
+        ];
+        accounts.extend(
+            tick_arrays
+                .into_iter()
+                .map(|key| AccountMeta::new(key, false)),
+        );
+        Ok(Instruction {
+            program_id: CLMM_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+}
+
+// -----------------------------------
+// Sqrt Price Math
+// -----------------------------------
+
+/// One step of a swap towards a target price, within constant liquidity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SwapStep {
+    sqrt_price_next: u128,
+    amount_in: u64,
+    amount_out: u64,
+    fee: u64,
+}
+
+/// Swap as much of `amount_remaining` as fits before the price reaches
+/// `sqrt_price_target`, taking the trade fee from the input
+fn compute_swap_step(
+    sqrt_price_current: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u64,
+    fee_rate: u32,
+    zero_for_one: bool,
+) -> Result<SwapStep> {
+    let fee_rate = fee_rate as u64;
+    ensure!(
+        fee_rate < FEE_RATE_DENOMINATOR,
+        Error::quote("Fee rate is out of range")
+    );
+    let remaining_less_fee = (amount_remaining as u128 * (FEE_RATE_DENOMINATOR - fee_rate) as u128
+        / FEE_RATE_DENOMINATOR as u128) as u64;
+
+    // Input needed to reach the target, if it fits in a u64 at all
+    let amount_to_target = if zero_for_one {
+        delta_amount_0(sqrt_price_target, sqrt_price_current, liquidity, true)
+    } else {
+        delta_amount_1(sqrt_price_current, sqrt_price_target, liquidity, true)
+    };
+    let (sqrt_price_next, amount_in) = match amount_to_target {
+        Some(amount_in) if remaining_less_fee >= amount_in => (sqrt_price_target, amount_in),
+        _ => {
+            let sqrt_price_next = next_sqrt_price_from_input(
+                sqrt_price_current,
+                liquidity,
+                remaining_less_fee,
+                zero_for_one,
+            )?;
+            let amount_in = if zero_for_one {
+                delta_amount_0(sqrt_price_next, sqrt_price_current, liquidity, true)
+            } else {
+                delta_amount_1(sqrt_price_current, sqrt_price_next, liquidity, true)
+            };
+            (
+                sqrt_price_next,
+                amount_in.ok_or_else(|| Error::quote("Swap step input overflows"))?,
+            )
+        }
+    };
+    let amount_out = if zero_for_one {
+        delta_amount_1(sqrt_price_next, sqrt_price_current, liquidity, false)
+    } else {
+        delta_amount_0(sqrt_price_current, sqrt_price_next, liquidity, false)
+    }
+    .ok_or_else(|| Error::quote("Swap step output overflows"))?;
+
+    let fee = if sqrt_price_next != sqrt_price_target {
+        // Short of the target the whole remainder is spent; dust goes to fees
+        amount_remaining
+            .checked_sub(amount_in)
+            .ok_or_else(|| Error::quote("Swap step spends more than the remaining input"))?
+    } else {
+        u64::try_from(
+            (amount_in as u128 * fee_rate as u128)
+                .div_ceil((FEE_RATE_DENOMINATOR - fee_rate) as u128),
+        )?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee,
+    })
+}
+
+/// `1.0001^(tick/2)` as Q64.64, computed bit by bit as the program does
+pub fn sqrt_price_at_tick(tick: i32) -> Result<u128> {
+    const FACTORS: [u128; 19] = [
+        0xfffcb933bd6fb800,
+        0xfff97272373d4000,
+        0xfff2e50f5f657000,
+        0xffe5caca7e10f000,
+        0xffcb9843d60f7000,
+        0xff973b41fa98e800,
+        0xff2ea16466c9b000,
+        0xfe5dee046a9a3800,
+        0xfcbe86c7900bb000,
+        0xf987a7253ac65800,
+        0xf3392b0822bb6000,
+        0xe7159475a2caf000,
+        0xd097f3bdfd2f2000,
+        0xa9f746462d9f8000,
+        0x70d869a156f31c00,
+        0x31be135f97ed3200,
+        0x9aa508b5b85a500,
+        0x5d6af8dedc582c,
+        0x2216e584f5fa,
+    ];
+    let abs_tick = tick.unsigned_abs();
+    ensure!(
+        abs_tick <= MAX_TICK as u32,
+        Error::quote(format!("Tick {} is out of range", tick))
+    );
+
+    let mut ratio = if abs_tick & 1 != 0 {
+        FACTORS[0]
+    } else {
+        1 << RESOLUTION
+    };
+    for (bit, factor) in FACTORS.iter().enumerate().skip(1) {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = (ratio * factor) >> RESOLUTION;
+        }
+    }
+    if tick > 0 {
+        ratio = u128::MAX / ratio;
+    }
+    Ok(ratio)
+}
+
+/// Token 0 between two sqrt prices: `L·(√b − √a) / (√a·√b)`. `None` if it
+/// doesn't fit in a u64.
+fn delta_amount_0(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    let (lower, upper) = if sqrt_price_a < sqrt_price_b {
+        (sqrt_price_a, sqrt_price_b)
+    } else {
+        (sqrt_price_b, sqrt_price_a)
+    };
+    if lower == 0 {
+        return None;
+    }
+    let numerator = (U512::from(liquidity) << RESOLUTION) * U512::from(upper - lower);
+    let amount = if round_up {
+        div_ceil(div_ceil(numerator, U512::from(upper)), U512::from(lower))
+    } else {
+        numerator / U512::from(upper) / U512::from(lower)
+    };
+    u64::try_from(amount).ok()
+}
+
+/// Token 1 between two sqrt prices: `L·(√b − √a)`. `None` if it doesn't fit
+/// in a u64.
+fn delta_amount_1(
+    sqrt_price_a: u128,
+    sqrt_price_b: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    let difference = sqrt_price_a.abs_diff(sqrt_price_b);
+    let numerator = U512::from(liquidity) * U512::from(difference);
+    let denominator = U512::one() << RESOLUTION;
+    let amount = if round_up {
+        div_ceil(numerator, denominator)
+    } else {
+        numerator / denominator
+    };
+    u64::try_from(amount).ok()
+}
+
+/// Sqrt price after adding `amount_in` of the input token to the pool. Token
+/// 0 rounds the price up and token 1 rounds it down, both in the pool's favour.
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Result<u128> {
+    ensure!(
+        sqrt_price > 0 && liquidity > 0,
+        Error::quote("Pool has no liquidity at this price")
+    );
+    if amount_in == 0 {
+        return Ok(sqrt_price);
+    }
+    let next = if zero_for_one {
+        let numerator = U512::from(liquidity) << RESOLUTION;
+        let denominator = numerator + U512::from(amount_in) * U512::from(sqrt_price);
+        div_ceil(numerator * U512::from(sqrt_price), denominator)
+    } else {
+        U512::from(sqrt_price) + (U512::from(amount_in) << RESOLUTION) / U512::from(liquidity)
+    };
+    u128::try_from(next).map_err(|_| Error::quote("Sqrt price overflows"))
+}
+
+fn div_ceil(numerator: U512, denominator: U512) -> U512 {
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_0: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+    const TOKEN_1: Pubkey = pubkey!("A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump");
+    const CONFIG: Pubkey = pubkey!("E64NGkDLLCdQ2yFNPcavaKptrEgmiQaNykUuLC1Qgwyp");
+    const POOL: Pubkey = pubkey!("2QdhepnKRTLjjSqPL1PtKNwqrUkoLee5Gqs8bvZhRdMv");
+
+    const LIQUIDITY_WIDE: i128 = 5_000_000_000_000;
+    const LIQUIDITY_NARROW: i128 = 3_000_000_000_000;
+
+    /// A pool at tick -100 with spacing 10, holding one position over
+    /// -1200..1200 and another over -600..300
+    fn accounts() -> HashMap<Pubkey, Vec<u8>> {
+        let mut pool = vec![0u8; 1544];
+        pool[..8].copy_from_slice(&POOL_DISCRIMINATOR);
+        pool[9..41].copy_from_slice(CONFIG.as_ref());
+        pool[73..105].copy_from_slice(TOKEN_0.as_ref());
+        pool[105..137].copy_from_slice(TOKEN_1.as_ref());
+        pool[235..237].copy_from_slice(&10u16.to_le_bytes());
+        let liquidity = (LIQUIDITY_WIDE + LIQUIDITY_NARROW) as u128;
+        pool[237..253].copy_from_slice(&liquidity.to_le_bytes());
+        let sqrt_price = sqrt_price_at_tick(-100).unwrap();
+        pool[253..269].copy_from_slice(&sqrt_price.to_le_bytes());
+        pool[269..273].copy_from_slice(&(-100i32).to_le_bytes());
+
+        let mut config = vec![0u8; 117];
+        config[..8].copy_from_slice(&CONFIG_DISCRIMINATOR);
+        config[47..51].copy_from_slice(&2_500u32.to_le_bytes());
+        config[51..53].copy_from_slice(&10u16.to_le_bytes());
+
+        let mut accounts = HashMap::from([(CONFIG, config)]);
+        let ticks = [
+            (-1200i32, LIQUIDITY_WIDE),
+            (-600, LIQUIDITY_NARROW),
+            (300, -LIQUIDITY_NARROW),
+            (1200, -LIQUIDITY_WIDE),
+        ];
+        for (tick, liquidity_net) in ticks {
+            let start = tick.div_euclid(600) * 600;
+            let mut array = vec![0u8; 10240];
+            array[..8].copy_from_slice(&TICK_ARRAY_DISCRIMINATOR);
+            array[8..40].copy_from_slice(POOL.as_ref());
+            array[40..44].copy_from_slice(&start.to_le_bytes());
+            let offset = TICK_ARRAY_HEADER_LEN + ((tick - start) / 10) as usize * TICK_LEN;
+            array[offset..offset + 4].copy_from_slice(&tick.to_le_bytes());
+            array[offset + 4..offset + 20].copy_from_slice(&liquidity_net.to_le_bytes());
+            array[offset + 20..offset + 36]
+                .copy_from_slice(&liquidity_net.unsigned_abs().to_le_bytes());
+            accounts.insert(tick_array_address(&POOL, start), array);
+
+            let bit = (start / 600 + 512) as usize;
+            pool[904 + bit / 64 * 8..][..8]
+                .iter_mut()
+                .zip((1u64 << (bit % 64)).to_le_bytes())
+                .for_each(|(byte, mask)| *byte |= mask);
+        }
+        accounts.insert(POOL, pool);
+        accounts
+    }
+
+    fn snapshot(accounts: &HashMap<Pubkey, Vec<u8>>) -> ClmmSnapshot {
+        let pool = ClmmPool::decode(&accounts[&POOL]).unwrap();
+        ClmmSnapshot::from_accounts(POOL, pool, accounts).unwrap()
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_matches_program_bounds() {
+        assert_eq!(sqrt_price_at_tick(0).unwrap(), 1 << 64);
+        assert_eq!(sqrt_price_at_tick(MIN_TICK).unwrap(), MIN_SQRT_PRICE_X64);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK).unwrap(), MAX_SQRT_PRICE_X64);
+        assert!(sqrt_price_at_tick(MAX_TICK + 1).is_err());
+    }
+
+    // Expected amounts come from replaying the same swaps through the CLMM
+    // program's own `compute_swap_step` and tick math.
+    #[test]
+    fn quotes_match_program_swaps_across_ticks() {
+        let snapshot = snapshot(&accounts());
+        assert_eq!(snapshot.known_arrays, -512..=511);
+        assert_eq!(snapshot.tick_arrays.len(), 4);
+
+        for (input, amount, out_amount, fee) in [
+            (TOKEN_0, 1_000_000, 987_575, 2_500),
+            (TOKEN_0, 300_000_000_000, 285_031_533_076, 750_000_001),
+            (TOKEN_1, 1_000_000, 1_007_524, 2_500),
+            (TOKEN_1, 200_000_000_000, 196_472_083_725, 500_000_001),
+        ] {
+            let quote = snapshot.quote(&input, amount).unwrap();
+            assert_eq!(quote, SwapQuote { out_amount, fee }, "{} {}", input, amount);
+        }
+    }
+
+    #[test]
+    fn swaps_pass_the_tick_arrays_they_walk_plus_one() {
+        let snapshot = snapshot(&accounts());
+        let owner = Pubkey::new_unique();
+        let (input, output) = (Pubkey::new_unique(), Pubkey::new_unique());
+        for (input_mint, starts) in [(TOKEN_0, vec![-600, -1200]), (TOKEN_1, vec![-600, 0, 1200])] {
+            let ix = snapshot
+                .swap_instruction(&owner, &input_mint, &input, &output, 1_000_000, 0)
+                .unwrap();
+            let tick_arrays: Vec<Pubkey> = ix.accounts[13..].iter().map(|a| a.pubkey).collect();
+            let expected: Vec<Pubkey> = starts
+                .into_iter()
+                .map(|start| tick_array_address(&POOL, start))
+                .collect();
+            assert_eq!(tick_arrays, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_swaps_the_loaded_ticks_cannot_fill() {
+        let mut accounts = accounts();
+        let error = snapshot(&accounts)
+            .quote(&TOKEN_0, 400_000_000_000)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Quote { ref reason } if reason == "Not enough liquidity in the pool for this swap"
+        ));
+
+        // The array at -1200 exists but wasn't fetched
+        accounts.remove(&tick_array_address(&POOL, -1200));
+        let error = snapshot(&accounts)
+            .quote(&TOKEN_0, 400_000_000_000)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Quote { ref reason } if reason == "Swap needs tick arrays beyond those loaded"
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+//! On-chain state and exact quoting for Raydium CPMM pools.
+//!
+//! CPMM is Raydium's Anchor constant product program. Fees accrue inside
+//! the token vaults, so the reserves a swap sees are the vault balances less
+//! the protocol, fund and creator fees still waiting to be collected. Token
+//! 2022 transfer fees are not modelled.
+
+use crate::common::{fetch_accounts, read_pubkey, read_u64, read_u8, SwapQuote};
+use crate::error::{ensure, Error, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+use std::collections::HashMap;
+
+pub const CPMM_PROGRAM_ID: Pubkey = pubkey!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C");
+/// PDA seeded with `b"vault_and_lp_mint_auth_seed"` that owns every pool's
+/// vaults
+pub const CPMM_AUTHORITY: Pubkey = pubkey!("GpMZbSM2GgvTKHJirzeGfMFoaZ8UR2X7F4v8vHTvxFbL");
+
+const POOL_DISCRIMINATOR: [u8; 8] = [247, 237, 227, 245, 215, 195, 222, 70];
+const CONFIG_DISCRIMINATOR: [u8; 8] = [218, 244, 33, 104, 203, 203, 43, 111];
+const SWAP_BASE_INPUT_DISCRIMINATOR: [u8; 8] = [143, 190, 90, 218, 196, 30, 51, 222];
+
+/// Fee rates are numerators over this
+const FEE_RATE_DENOMINATOR: u128 = 1_000_000;
+/// Status bit that disables swaps
+const STATUS_SWAP_DISABLED: u8 = 1 << 2;
+
+// -----------------------------------
+// Pool and Config Accounts
+// -----------------------------------
+#[derive(Debug, Clone)]
+pub struct CpmmPool {
+    pub amm_config: Pubkey,
+    pub token_0_vault: Pubkey,
+    pub token_1_vault: Pubkey,
+    pub token_0_mint: Pubkey,
+    pub token_1_mint: Pubkey,
+    pub token_0_program: Pubkey,
+    pub token_1_program: Pubkey,
+    pub observation_key: Pubkey,
+    pub status: u8,
+    pub mint_0_decimals: u8,
+    pub mint_1_decimals: u8,
+    pub protocol_fees_token_0: u64,
+    pub protocol_fees_token_1: u64,
+    pub fund_fees_token_0: u64,
+    pub fund_fees_token_1: u64,
+    /// 0 takes the creator fee from the input, 1 only in token 0, 2 only in
+    /// token 1
+    pub creator_fee_on: u8,
+    pub enable_creator_fee: bool,
+    pub creator_fees_token_0: u64,
+    pub creator_fees_token_1: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CpmmConfig {
+    pub trade_fee_rate: u64,
+    pub protocol_fee_rate: u64,
+    pub fund_fee_rate: u64,
+    pub creator_fee_rate: u64,
+}
+
+impl CpmmPool {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.get(..8) == Some(&POOL_DISCRIMINATOR[..]),
+            Error::account("Raydium CPMM pool", "wrong discriminator", data)
+        );
+        Ok(Self {
+            amm_config: read_pubkey(data, 8)?,
+            token_0_vault: read_pubkey(data, 72)?,
+            token_1_vault: read_pubkey(data, 104)?,
+            token_0_mint: read_pubkey(data, 168)?,
+            token_1_mint: read_pubkey(data, 200)?,
+            token_0_program: read_pubkey(data, 232)?,
+            token_1_program: read_pubkey(data, 264)?,
+            observation_key: read_pubkey(data, 296)?,
+            status: read_u8(data, 329)?,
+            mint_0_decimals: read_u8(data, 331)?,
+            mint_1_decimals: read_u8(data, 332)?,
+            protocol_fees_token_0: read_u64(data, 341)?,
+            protocol_fees_token_1: read_u64(data, 349)?,
+            fund_fees_token_0: read_u64(data, 357)?,
+            fund_fees_token_1: read_u64(data, 365)?,
+            creator_fee_on: read_u8(data, 389)?,
+            enable_creator_fee: read_u8(data, 390)? != 0,
+            creator_fees_token_0: read_u64(data, 397)?,
+            creator_fees_token_1: read_u64(data, 405)?,
+        })
+    }
+
+    pub fn swap_enabled(&self) -> bool {
+        self.status & STATUS_SWAP_DISABLED == 0
+    }
+
+    /// Whether the creator fee comes out of the input when selling token 0
+    /// (`zero_for_one`) or token 1
+    fn creator_fee_on_input(&self, zero_for_one: bool) -> bool {
+        match self.creator_fee_on {
+            1 => zero_for_one,
+            2 => !zero_for_one,
+            _ => true,
+        }
+    }
+}
+
+impl CpmmConfig {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.get(..8) == Some(&CONFIG_DISCRIMINATOR[..]),
+            Error::account("Raydium CPMM config", "wrong discriminator", data)
+        );
+        Ok(Self {
+            trade_fee_rate: read_u64(data, 12)?,
+            protocol_fee_rate: read_u64(data, 20)?,
+            fund_fee_rate: read_u64(data, 28)?,
+            creator_fee_rate: read_u64(data, 108)?,
+        })
+    }
+}
+
+// -----------------------------------
+// Quoting
+// -----------------------------------
+
+/// A pool, its config and its vault balances, read at one point in time
+#[derive(Debug, Clone)]
+pub struct CpmmSnapshot {
+    pub address: Pubkey,
+    pub pool: CpmmPool,
+    pub config: CpmmConfig,
+    pub token_0_vault_amount: u64,
+    pub token_1_vault_amount: u64,
+}
+
+impl CpmmSnapshot {
+    pub async fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self> {
+        let pool_data = rpc_client.get_account_data(address).await?;
+        let pool = CpmmPool::decode(&pool_data)?;
+
+        let keys = [pool.amm_config, pool.token_0_vault, pool.token_1_vault];
+        let mut accounts = HashMap::new();
+        fetch_accounts(rpc_client, &keys, &mut accounts).await?;
+        Self::from_accounts(*address, pool, &accounts)
+    }
+
+    pub fn from_accounts(
+        address: Pubkey,
+        pool: CpmmPool,
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<Self> {
+        let account = |key: &Pubkey| {
+            accounts
+                .get(key)
+                .ok_or(Error::AccountNotFound { account: *key })
+        };
+        Ok(Self {
+            address,
+            config: CpmmConfig::decode(account(&pool.amm_config)?)?,
+            token_0_vault_amount: read_u64(account(&pool.token_0_vault)?, 64)?,
+            token_1_vault_amount: read_u64(account(&pool.token_1_vault)?, 64)?,
+            pool,
+        })
+    }
+
+    /// Token 0 and token 1 reserves the curve sees, net of uncollected fees
+    pub fn reserves(&self) -> Result<(u64, u64)> {
+        let pool = &self.pool;
+        let owed_0 =
+            pool.protocol_fees_token_0 + pool.fund_fees_token_0 + pool.creator_fees_token_0;
+        let owed_1 =
+            pool.protocol_fees_token_1 + pool.fund_fees_token_1 + pool.creator_fees_token_1;
+        Ok((
+            self.token_0_vault_amount
+                .checked_sub(owed_0)
+                .ok_or_else(|| {
+                    Error::quote("Token 0 vault holds less than the pool's uncollected fees")
+                })?,
+            self.token_1_vault_amount
+                .checked_sub(owed_1)
+                .ok_or_else(|| {
+                    Error::quote("Token 1 vault holds less than the pool's uncollected fees")
+                })?,
+        ))
+    }
+
+    /// Quote swapping `in_amount` base units of `input_mint`. The fee is what
+    /// comes out of the input; a creator fee charged on the output is netted
+    /// out of `out_amount` instead.
+    pub fn quote(&self, input_mint: &Pubkey, in_amount: u64) -> Result<SwapQuote> {
+        ensure!(
+            self.pool.swap_enabled(),
+            Error::quote("Raydium CPMM pool is not swapping")
+        );
+        let (reserve_0, reserve_1) = self.reserves()?;
+        let (zero_for_one, in_reserve, out_reserve) = if *input_mint == self.pool.token_0_mint {
+            (true, reserve_0, reserve_1)
+        } else if *input_mint == self.pool.token_1_mint {
+            (false, reserve_1, reserve_0)
+        } else {
+            return Err(Error::invalid(format!(
+                "{} is not a token of this pool",
+                input_mint
+            )));
+        };
+
+        let creator_fee_rate = if self.pool.enable_creator_fee {
+            self.config.creator_fee_rate
+        } else {
+            0
+        };
+        let fee_on_input = self.pool.creator_fee_on_input(zero_for_one);
+
+        let in_amount = in_amount as u128;
+        let mut fee = fee_amount(in_amount, self.config.trade_fee_rate);
+        if fee_on_input {
+            fee += fee_amount(in_amount, creator_fee_rate);
+        }
+        let in_less_fees = in_amount
+            .checked_sub(fee)
+            .ok_or_else(|| Error::quote("Fees exceed the swap input"))?;
+
+        let mut out_amount =
+            in_less_fees * out_reserve as u128 / (in_reserve as u128 + in_less_fees);
+        if !fee_on_input {
+            out_amount -= fee_amount(out_amount, creator_fee_rate);
+        }
+
+        Ok(SwapQuote {
+            out_amount: u64::try_from(out_amount)?,
+            fee: u64::try_from(fee)?,
+        })
+    }
+
+    /// `swap_base_input` instruction selling `amount_in` of `input_mint`
+    /// from `input_account` into `output_account`, both owned by `owner`
+    pub fn swap_instruction(
+        &self,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        input_account: &Pubkey,
+        output_account: &Pubkey,
+        amount_in: u64,
+        min_out: u64,
+    ) -> Result<Instruction> {
+        let pool = &self.pool;
+        let token_0 = (pool.token_0_mint, pool.token_0_vault, pool.token_0_program);
+        let token_1 = (pool.token_1_mint, pool.token_1_vault, pool.token_1_program);
+        let ((in_mint, in_vault, in_program), (out_mint, out_vault, out_program)) =
+            if *input_mint == pool.token_0_mint {
+                (token_0, token_1)
+            } else if *input_mint == pool.token_1_mint {
+                (token_1, token_0)
+            } else {
+                return Err(Error::invalid(format!(
+                    "{} is not a token of this pool",
+                    input_mint
+                )));
+            };
+
+        let mut data = SWAP_BASE_INPUT_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        Ok(Instruction {
+            program_id: CPMM_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(CPMM_AUTHORITY, false),
+                AccountMeta::new_readonly(pool.amm_config, false),
+                AccountMeta::new(self.address, false),
+                AccountMeta::new(*input_account, false),
+                AccountMeta::new(*output_account, false),
+                AccountMeta::new(in_vault, false),
+                AccountMeta::new(out_vault, false),
+                AccountMeta::new_readonly(in_program, false),
+                AccountMeta::new_readonly(out_program, false),
+                AccountMeta::new_readonly(in_mint, false),
+                AccountMeta::new_readonly(out_mint, false),
+                AccountMeta::new(pool.observation_key, false),
+            ],
+            data,
+        })
+    }
+}
+
+/// Fee on `amount` at `fee_rate`, rounded up
+fn fee_amount(amount: u128, fee_rate: u64) -> u128 {
+    (amount * fee_rate as u128).div_ceil(FEE_RATE_DENOMINATOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_0: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+    const TOKEN_1: Pubkey = pubkey!("A3hzGcTxZNSc7744CWB2LR5Tt9VTtEaQYpP6nwripump");
+
+    fn snapshot(creator_fee_on: u8) -> CpmmSnapshot {
+        let mut data = vec![0u8; 637];
+        data[..8].copy_from_slice(&POOL_DISCRIMINATOR);
+        data[168..200].copy_from_slice(TOKEN_0.as_ref());
+        data[200..232].copy_from_slice(TOKEN_1.as_ref());
+        data[341..349].copy_from_slice(&400u64.to_le_bytes());
+        data[365..373].copy_from_slice(&600u64.to_le_bytes());
+        data[389] = creator_fee_on;
+        data[390] = 1;
+        CpmmSnapshot {
+            address: Pubkey::new_unique(),
+            pool: CpmmPool::decode(&data).unwrap(),
+            config: CpmmConfig {
+                trade_fee_rate: 2_500,
+                protocol_fee_rate: 120_000,
+                fund_fee_rate: 40_000,
+                creator_fee_rate: 1_000,
+            },
+            token_0_vault_amount: 2_000_000_400,
+            token_1_vault_amount: 9_000_000_600,
+        }
+    }
+
+    #[test]
+    fn quotes_with_creator_fee_on_either_side() {
+        let snapshot = snapshot(0);
+        assert_eq!(snapshot.reserves().unwrap(), (2_000_000_000, 9_000_000_000));
+
+        // 0.25% trade fee and 0.1% creator fee, both off the input
+        let quote = snapshot.quote(&TOKEN_0, 100_000_000).unwrap();
+        assert_eq!(quote.fee, 350_000);
+        assert_eq!(quote.out_amount, 427_142_619);
+
+        // Creator fee only in token 0, so selling token 1 pays it from the output
+        let snapshot = self::snapshot(1);
+        let quote = snapshot.quote(&TOKEN_1, 100_000_000).unwrap();
+        assert_eq!(quote.fee, 250_000);
+        assert_eq!(quote.out_amount, 21_901_755);
+    }
+
+    #[test]
+    fn authority_is_the_program_pda() {
+        let (authority, _) =
+            Pubkey::find_program_address(&[b"vault_and_lp_mint_auth_seed"], &CPMM_PROGRAM_ID);
+        assert_eq!(authority, CPMM_AUTHORITY);
+    }
+}
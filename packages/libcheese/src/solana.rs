@@ -1,180 +1,1469 @@
-use anyhow::{anyhow, Result};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use solana_client::rpc_client::RpcClient;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig},
+    rpc_response::RpcSimulateTransactionResult,
+};
 use solana_sdk::{
+    address_lookup_table::{state::AddressLookupTable, AddressLookupTableAccount},
     commitment_config::CommitmentConfig,
+    compute_budget::{self, ComputeBudgetInstruction},
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::{
+        v0::{self, MessageAddressTableLookup},
+        VersionedMessage,
+    },
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
     signature::{Keypair, Signature},
     signer::Signer,
-    transaction::Transaction,
+    transaction::VersionedTransaction,
 };
-use std::{str::FromStr, time::Duration};
+use std::{num::ParseIntError, time::Duration};
 use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn};
 
-use crate::meteora::{self, MeteoraPool};
+use crate::arbitrage::ArbitrageCycle;
+use crate::common::{SwapInstructions, TOKEN_2022_PROGRAM_ID};
+use crate::error::{parse_pubkey, Error, Result};
+use crate::http::{HttpClient, HttpSettings};
+use crate::jupiter::{self, JupiterQuoteParams};
+use crate::venue::{venue_for, Pool, Venue};
 
 const MAX_RETRIES: u32 = 3;
 const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Most compute units a single transaction may request
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+/// Base fee charged per transaction signature
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// What a trade needs to know about a token mint
+struct MintAccount {
+    token_program: Pubkey,
+    decimals: u8,
+}
+
+/// RPC settings for a `TradeExecutor`
+#[derive(Debug, Clone)]
+pub struct RpcSettings {
+    /// Commitment used for reads, simulation and confirmation
+    pub commitment: CommitmentConfig,
+    /// Timeout for each RPC request
+    pub request_timeout: Duration,
+    /// How long to wait for a sent transaction to show up before giving up
+    pub confirm_timeout: Duration,
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        Self {
+            commitment: CommitmentConfig::confirmed(),
+            request_timeout: Duration::from_secs(30),
+            confirm_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How `TradeExecutor` prices and sizes its compute budget
+#[derive(Debug, Clone)]
+pub struct PriorityFeeSettings {
+    /// Percentile (0-100) of recent prioritization fees to pay
+    pub percentile: u8,
+    /// Upper bound on the compute unit price, in micro-lamports
+    pub max_micro_lamports: u64,
+    /// Extra compute units requested on top of what simulation consumed, in percent
+    pub compute_unit_margin_pct: u64,
+}
+
+impl Default for PriorityFeeSettings {
+    fn default() -> Self {
+        Self {
+            percentile: 75,
+            max_micro_lamports: 1_000_000,
+            compute_unit_margin_pct: 20,
+        }
+    }
+}
+
+/// Which swaps `TradeExecutor` sends through Jupiter instead of building
+/// them against the trade's own pool
+#[derive(Debug, Clone, Default)]
+pub struct JupiterRouting {
+    /// Route every leg through Jupiter, not just legs on pools we can't
+    /// trade directly
+    pub all_legs: bool,
+    /// Only accept routes through a single pool
+    pub only_direct_routes: bool,
+    /// Only route through these DEXes, by Jupiter's labels
+    pub dexes: Option<Vec<String>>,
+}
 
 pub struct TradeExecutor {
     rpc_client: RpcClient,
     wallet: Keypair,
-    http_client: Client,
+    http_client: HttpClient,
+    priority_fees: PriorityFeeSettings,
+    jupiter: Option<JupiterRouting>,
 }
 
 impl TradeExecutor {
-    pub fn new(rpc_url: &str, wallet_keypair: Keypair) -> Self {
-        let rpc_client =
-            RpcClient::new_with_commitment(rpc_url.to_string(), CommitmentConfig::confirmed());
-        let http_client = Client::new();
-        Self {
+    pub fn new(rpc_url: &str, wallet_keypair: Keypair) -> Result<Self> {
+        Self::with_settings(rpc_url, wallet_keypair, RpcSettings::default())
+    }
+
+    pub fn with_settings(
+        rpc_url: &str,
+        wallet_keypair: Keypair,
+        settings: RpcSettings,
+    ) -> Result<Self> {
+        let rpc_client = RpcClient::new_with_timeouts_and_commitment(
+            rpc_url.to_string(),
+            settings.request_timeout,
+            settings.commitment,
+            settings.confirm_timeout,
+        );
+        let http_client = HttpClient::new(HttpSettings::default())?;
+        Ok(Self {
             rpc_client,
             wallet: wallet_keypair,
             http_client,
-        }
+            priority_fees: PriorityFeeSettings::default(),
+            jupiter: None,
+        })
+    }
+
+    pub fn with_priority_fees(mut self, priority_fees: PriorityFeeSettings) -> Self {
+        self.priority_fees = priority_fees;
+        self
     }
 
-    /// Execute a trade on Meteora
-    pub async fn execute_trade(
+    /// Share `http_client`, and its rate limits, with the rest of the bot
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    pub fn with_jupiter_routing(mut self, routing: JupiterRouting) -> Self {
+        self.jupiter = Some(routing);
+        self
+    }
+
+    /// Execute a swap of `amount_in` base units of `input_mint` on `pool`,
+    /// built by the venue the pool came from
+    #[instrument(skip(self, venues, pool), fields(source = pool.source, pool = %pool.address))]
+    pub async fn execute_swap(
         &self,
-        pool: &MeteoraPool,
-        input_mint: &str,
-        output_mint: &str,
+        venues: &[Box<dyn Venue + '_>],
+        pool: &Pool,
+        input_mint: &Pubkey,
         amount_in: u64,
         slippage_bps: u64,
     ) -> Result<Signature> {
         // Check balance before trading
-        self.check_token_balance(input_mint, amount_in).await?;
+        let input_account = self.mint_token_account(input_mint).await?;
+        self.check_token_balance(&input_mint.to_string(), &input_account, amount_in)
+            .await?;
 
-        for retry in 0..MAX_RETRIES {
+        // Only building and simulating are retried. Once a transaction is
+        // sent, a failed or timed out confirmation doesn't mean it won't
+        // land, and a second one could trade twice.
+        let mut retry = 0;
+        let tx = loop {
             if retry > 0 {
-                println!(
-                    "Retrying trade execution (attempt {}/{})",
-                    retry + 1,
-                    MAX_RETRIES
+                info!(
+                    attempt = retry + 1,
+                    max_attempts = MAX_RETRIES,
+                    "Retrying trade execution"
                 );
                 sleep(RETRY_DELAY).await;
             }
 
             match self
-                .execute_trade_internal(pool, input_mint, output_mint, amount_in)
+                .prepare_swap(venues, pool, input_mint, amount_in, slippage_bps)
                 .await
             {
-                Ok(sig) => {
-                    println!("Trade executed successfully! Signature: {}", sig);
-                    println!("View transaction: https://solscan.io/tx/{}", sig);
-                    return Ok(sig);
-                }
+                Ok(tx) => break tx,
+                // Retrying can't fix an empty wallet, a response we can't
+                // read or a pool nobody can build swaps for
+                Err(
+                    e @ (Error::InsufficientBalance { .. }
+                    | Error::Decode { .. }
+                    | Error::Unsupported { .. }),
+                ) => return Err(e),
                 Err(e) if retry < MAX_RETRIES - 1 => {
-                    println!("Trade execution failed: {}. Retrying...", e);
-                    continue;
+                    warn!(error = %e, "Trade execution failed");
+                    retry += 1;
                 }
                 Err(e) => return Err(e),
             }
+        };
+
+        // 5. Send and confirm transaction
+        let sig = self.send_and_confirm_transaction(&tx).await?;
+        info!(
+            signature = %sig,
+            url = %format!("https://solscan.io/tx/{}", sig),
+            "Trade executed"
+        );
+        Ok(sig)
+    }
+
+    /// Quote, build, sign and simulate a swap, ready to send
+    async fn prepare_swap(
+        &self,
+        venues: &[Box<dyn Venue + '_>],
+        pool: &Pool,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<VersionedTransaction> {
+        // 1-3. Quote, build and sign the swap
+        let direct = self
+            .direct_swap(venues, pool, input_mint, amount_in, slippage_bps)
+            .await?;
+        let (tx, output_account, min_out) = match direct {
+            Some(swap) => {
+                let blockhash = self.rpc_client.get_latest_blockhash().await?;
+                let tx = self
+                    .with_compute_budget(&swap.instructions, &[], blockhash)
+                    .await?;
+                (tx, swap.output_account, swap.min_out_amount)
+            }
+            None => {
+                let output_mint = other_mint(pool, input_mint)?;
+                self.api_swap(input_mint, &output_mint, amount_in, slippage_bps)
+                    .await?
+            }
+        };
+
+        // 4. Simulate transaction with detailed error reporting
+        match self
+            .simulate_transaction(&tx, &output_account, min_out)
+            .await
+        {
+            Ok(_) => debug!("Transaction simulation successful"),
+            Err(e) => {
+                warn!(error = %e, "Transaction simulation failed");
+                return Err(e);
+            }
+        }
+        Ok(tx)
+    }
+
+    /// Build a swap on `pool` with the venue it came from. `None` sends the
+    /// swap through Jupiter instead, when routing puts every leg there or
+    /// the venue can't build this one.
+    async fn direct_swap(
+        &self,
+        venues: &[Box<dyn Venue + '_>],
+        pool: &Pool,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<Option<SwapInstructions>> {
+        if self
+            .jupiter
+            .as_ref()
+            .is_some_and(|routing| routing.all_legs)
+        {
+            return Ok(None);
+        }
+        let swap = match venue_for(venues, pool) {
+            Ok(venue) => {
+                venue
+                    .swap_instructions(
+                        pool,
+                        &self.wallet.pubkey(),
+                        input_mint,
+                        amount_in,
+                        slippage_bps,
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match swap {
+            Ok(swap) => {
+                debug!(
+                    %input_mint,
+                    in_amount = amount_in,
+                    out_amount = swap.out_amount,
+                    min_out = swap.min_out_amount,
+                    "Got quote"
+                );
+                Ok(Some(swap))
+            }
+            Err(Error::Unsupported { what }) if self.jupiter.is_some() => {
+                debug!(%what, "Routing swap through Jupiter");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetch and sign a swap transaction from Jupiter's API. Returns it with
+    /// the token account it pays out to and the minimum output it was
+    /// quoted with.
+    async fn api_swap(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<(VersionedTransaction, Pubkey, u64)> {
+        // Get quote and swap transaction from the API
+        let (mut tx, min_out) = self
+            .fetch_jupiter_swap_transaction(
+                &input_mint.to_string(),
+                &output_mint.to_string(),
+                amount_in,
+                slippage_bps,
+            )
+            .await?;
+
+        // Sign transaction, replacing the API's compute budget with our
+        //    own estimate when we're the only signer
+        self.sign_transaction(&mut tx).await?;
+        if tx.message.header().num_required_signatures == 1 {
+            let (instructions, tables) = self.decompile_instructions(&tx).await?;
+            let blockhash = *tx.message.recent_blockhash();
+            tx = self
+                .with_compute_budget(&instructions, &tables, blockhash)
+                .await?;
+        }
+
+        let account_keys = self.resolve_account_keys(&tx).await?;
+        debug!(
+            message = match tx.message {
+                VersionedMessage::Legacy(_) => "legacy",
+                VersionedMessage::V0(_) => "v0",
+            },
+            accounts = account_keys.len(),
+            from_lookup_tables = account_keys.len() - tx.message.static_account_keys().len(),
+            "Built swap transaction"
+        );
+
+        let output_account = self.mint_token_account(output_mint).await?;
+        if !account_keys.contains(&output_account) {
+            return Err(Error::transaction(format!(
+                "Swap transaction does not credit our {} account {}",
+                output_mint, output_account
+            )));
         }
 
-        Err(anyhow!("Max retries exceeded"))
+        Ok((tx, output_account, min_out))
     }
 
-    async fn execute_trade_internal(
+    /// Quote a swap on Jupiter's best route and fetch the unsigned
+    /// transaction for it. Returns the transaction and the minimum output
+    /// it was quoted with.
+    async fn fetch_jupiter_swap_transaction(
         &self,
-        pool: &MeteoraPool,
         input_mint: &str,
         output_mint: &str,
         amount_in: u64,
-    ) -> Result<Signature> {
-        // 1. Get quote from Meteora
-        let quote = meteora::get_meteora_quote(
-            &self.http_client,
-            &pool.pool_address,
+        slippage_bps: u64,
+    ) -> Result<(VersionedTransaction, u64)> {
+        let routing = self.jupiter.clone().unwrap_or_default();
+        let params = JupiterQuoteParams {
+            only_direct_routes: routing.only_direct_routes,
+            dexes: routing.dexes,
+            ..JupiterQuoteParams::new(input_mint, output_mint, amount_in, slippage_bps)
+        };
+        let quote = jupiter::get_jupiter_quote(&self.http_client, &params).await?;
+
+        debug!(
             input_mint,
             output_mint,
-            amount_in,
-        )
-        .await?;
-
-        println!(
-            "Got quote: {} -> {} ({} -> {})",
-            input_mint, output_mint, quote.in_amount, quote.out_amount
+            in_amount = %quote.in_amount,
+            out_amount = %quote.out_amount,
+            min_out = %quote.other_amount_threshold,
+            route = %quote.route_labels().join(" -> "),
+            "Got Jupiter quote"
         );
+        let min_out = quote.min_out_amount()?;
 
-        // 2. Get swap transaction
-        let swap_tx = meteora::get_meteora_swap_transaction(
+        let swap_tx = jupiter::get_jupiter_swap_transaction(
             &self.http_client,
             &quote,
             &self.wallet.pubkey().to_string(),
         )
         .await?;
 
-        // 3. Deserialize and sign transaction
-        let tx: Transaction = bincode::deserialize(&base64::decode(swap_tx)?)?;
+        let tx: VersionedTransaction = decode_transaction(&swap_tx)?;
+        Ok((tx, min_out))
+    }
 
-        // 4. Simulate transaction with detailed error reporting
-        match self.simulate_transaction(&tx).await {
-            Ok(_) => println!("Transaction simulation successful"),
-            Err(e) => {
-                println!("Transaction simulation failed: {}", e);
-                return Err(e);
+    /// Execute every leg of an arbitrage cycle atomically.
+    ///
+    /// All the legs go in one transaction that ends with a check reverting
+    /// unless we hold at least `min_profit` more of the starting token than
+    /// before, so the cycle either lands in full or not at all. Cycles that
+    /// don't fit in one transaction are refused as unsupported.
+    /// `pools` must hold every pool the cycle trades on.
+    #[instrument(skip_all, fields(steps = cycle.steps.len()))]
+    pub async fn execute_cycle(
+        &self,
+        venues: &[Box<dyn Venue + '_>],
+        pools: &[Pool],
+        cycle: &ArbitrageCycle,
+        slippage_bps: u64,
+        min_profit: u64,
+    ) -> Result<Signature> {
+        let first = cycle
+            .steps
+            .first()
+            .ok_or_else(|| Error::invalid("Arbitrage cycle has no steps"))?;
+        let start_mint = parse_pubkey(&first.sell_token)?;
+        let mint = self.mint_account(&start_mint).await?;
+        let start_account = self.token_account(&start_mint, &mint.token_program);
+        let amount_in = ui_to_raw(first.amount_in, mint.decimals);
+        self.check_token_balance(&first.sell_token, &start_account, amount_in)
+            .await?;
+
+        let tx = self
+            .build_cycle_transaction(venues, pools, cycle, slippage_bps, min_profit)
+            .await?;
+        self.simulate_transaction(&tx, &start_account, min_profit)
+            .await?;
+        let sig = self.send_and_confirm_transaction(&tx).await?;
+        info!(signature = %sig, "Sent cycle transaction");
+        Ok(sig)
+    }
+
+    /// Build a signed v0 transaction that runs every leg of `cycle` in order.
+    ///
+    /// Each leg spends the previous leg's minimum output, so every swap is
+    /// funded even when earlier legs fill at their slippage limit. Later legs
+    /// are quoted against the pre-cycle pool state; the closing profit check
+    /// is what protects us if that quote turns out optimistic.
+    pub async fn build_cycle_transaction(
+        &self,
+        venues: &[Box<dyn Venue + '_>],
+        pools: &[Pool],
+        cycle: &ArbitrageCycle,
+        slippage_bps: u64,
+        min_profit: u64,
+    ) -> Result<VersionedTransaction> {
+        let (first, last) = match (cycle.steps.first(), cycle.steps.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(Error::invalid("Arbitrage cycle has no steps")),
+        };
+        if first.sell_token != last.buy_token {
+            return Err(Error::invalid(format!(
+                "Arbitrage cycle starts with {} but ends with {}",
+                first.sell_token, last.buy_token
+            )));
+        }
+
+        let start_mint = parse_pubkey(&first.sell_token)?;
+        let mint = self.mint_account(&start_mint).await?;
+        let mut amount_in = ui_to_raw(first.amount_in, mint.decimals);
+
+        let mut legs = Vec::new();
+        let mut tables: Vec<AddressLookupTableAccount> = Vec::new();
+        for step in &cycle.steps {
+            let address = parse_pubkey(&step.pool_address)?;
+            let pool = pools
+                .iter()
+                .find(|pool| pool.address == address)
+                .ok_or_else(|| {
+                    Error::invalid(format!(
+                        "No {} pool {} to trade on",
+                        step.source, step.pool_address
+                    ))
+                })?;
+            let input_mint = parse_pubkey(&step.sell_token)?;
+            let direct = self
+                .direct_swap(venues, pool, &input_mint, amount_in, slippage_bps)
+                .await?;
+            let (instructions, leg_tables, min_out) = match direct {
+                Some(swap) => (swap.instructions, Vec::new(), swap.min_out_amount),
+                None => {
+                    let (tx, min_out) = self
+                        .fetch_jupiter_swap_transaction(
+                            &step.sell_token,
+                            &step.buy_token,
+                            amount_in,
+                            slippage_bps,
+                        )
+                        .await?;
+                    if tx.message.header().num_required_signatures > 1 {
+                        return Err(Error::transaction(format!(
+                            "Swap on pool {} needs signers other than our wallet",
+                            step.pool_address
+                        )));
+                    }
+                    let (instructions, tables) = self.decompile_instructions(&tx).await?;
+                    (instructions, tables, min_out)
+                }
+            };
+
+            for table in leg_tables {
+                if !tables.iter().any(|t| t.key == table.key) {
+                    tables.push(table);
+                }
             }
+            legs.push(instructions);
+            amount_in = min_out;
         }
 
-        // 5. Send and confirm transaction
-        self.send_and_confirm_transaction(&tx).await
+        let start_account = self.token_account(&start_mint, &mint.token_program);
+        let required = self
+            .token_account_amount(&start_account)
+            .await?
+            .checked_add(min_profit)
+            .ok_or_else(|| Error::invalid("Required closing balance overflows"))?;
+        let profit_check = profit_check_instruction(
+            &mint.token_program,
+            &start_mint,
+            mint.decimals,
+            &start_account,
+            &self.wallet.pubkey(),
+            required,
+        )?;
+        if let Some(last_leg) = legs.last_mut() {
+            last_leg.push(profit_check);
+        }
+
+        // Leave room for the compute budget instructions added afterwards
+        let blockhash = self.rpc_client.get_latest_blockhash().await?;
+        let budget = compute_budget_instructions(MAX_COMPUTE_UNITS, u64::MAX);
+        let chunks = pack_legs(legs, |instructions| {
+            let sized = [budget.clone(), instructions.to_vec()].concat();
+            self.compile_transaction(&sized, &tables, blockhash).is_ok()
+        })?;
+        // Sent one after another, a later transaction could fail after an
+        // earlier one landed and leave us holding an intermediate token
+        let [instructions] = chunks.as_slice() else {
+            return Err(Error::unsupported(format!(
+                "Cycle of {} legs that needs {} transactions",
+                cycle.steps.len(),
+                chunks.len()
+            )));
+        };
+
+        self.with_compute_budget(instructions, &tables, blockhash)
+            .await
     }
 
-    /// Check if the wallet has sufficient balance for the trade
-    async fn check_token_balance(&self, mint: &str, amount: u64) -> Result<()> {
-        let token_account = self.find_token_account(mint)?;
-        let balance = self.rpc_client.get_token_account_balance(&token_account)?;
+    /// Compile `instructions` behind a compute unit limit measured by
+    /// simulation and a priority fee estimated from recent blocks.
+    async fn with_compute_budget(
+        &self,
+        instructions: &[Instruction],
+        tables: &[AddressLookupTableAccount],
+        blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let probe = [
+            compute_budget_instructions(MAX_COMPUTE_UNITS, 0),
+            instructions.to_vec(),
+        ]
+        .concat();
+        let probe = self.compile_transaction(&probe, tables, blockhash)?;
 
-        if balance.ui_amount.unwrap_or(0.0) * 10f64.powi(balance.decimals as i32) < amount as f64 {
-            return Err(anyhow!(
-                "Insufficient balance: have {} {}, need {}",
-                balance.ui_amount.unwrap_or(0.0),
-                mint,
-                amount
-            ));
+        // A transaction that fails simulation here gets the maximum limit;
+        // the checked simulation before sending reports why it fails
+        let margin = 100 + self.priority_fees.compute_unit_margin_pct;
+        let unit_limit = match self.simulated_units(&probe).await? {
+            Some(units) => (units * margin / 100).min(MAX_COMPUTE_UNITS as u64) as u32,
+            None => MAX_COMPUTE_UNITS,
+        };
+
+        let mut writable: Vec<Pubkey> = Vec::new();
+        for meta in instructions.iter().flat_map(|ix| &ix.accounts) {
+            if meta.is_writable && !writable.contains(&meta.pubkey) {
+                writable.push(meta.pubkey);
+            }
+        }
+        let unit_price = self.estimate_priority_fee(&writable).await?;
+        debug!(unit_limit, micro_lamports = unit_price, "Compute budget");
+
+        let final_instructions = [
+            compute_budget_instructions(unit_limit, unit_price),
+            instructions.to_vec(),
+        ]
+        .concat();
+        self.compile_transaction(&final_instructions, tables, blockhash)
+    }
+
+    /// Compute units a transaction consumes in simulation, or `None` if the
+    /// simulation fails
+    async fn simulated_units(&self, transaction: &VersionedTransaction) -> Result<Option<u64>> {
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(self.rpc_client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = self
+            .rpc_client
+            .simulate_transaction_with_config(transaction, config)
+            .await?
+            .value;
+        Ok(match result.err {
+            Some(_) => None,
+            None => result.units_consumed,
+        })
+    }
+
+    /// Priority fee in micro-lamports per compute unit for a transaction
+    /// that write-locks `accounts`, at the configured percentile of recent
+    /// blocks and capped at the configured maximum
+    pub async fn estimate_priority_fee(&self, accounts: &[Pubkey]) -> Result<u64> {
+        // The RPC accepts at most 128 accounts
+        let accounts = &accounts[..accounts.len().min(128)];
+        let mut fees: Vec<u64> = self
+            .rpc_client
+            .get_recent_prioritization_fees(accounts)
+            .await?
+            .into_iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect();
+
+        let fee = fee_percentile(&mut fees, self.priority_fees.percentile);
+        Ok(fee.min(self.priority_fees.max_micro_lamports))
+    }
+
+    /// Estimated lamport cost of one transaction signed by our wallet that
+    /// write-locks `accounts` and uses up to `compute_units`
+    pub async fn estimate_transaction_cost(
+        &self,
+        accounts: &[Pubkey],
+        compute_units: u32,
+    ) -> Result<u64> {
+        let unit_price = self.estimate_priority_fee(accounts).await?;
+        let priority = (unit_price as u128 * compute_units as u128).div_ceil(1_000_000) as u64;
+        Ok(LAMPORTS_PER_SIGNATURE + priority)
+    }
+
+    /// Compile and sign a v0 transaction, failing if it's over the packet size
+    fn compile_transaction(
+        &self,
+        instructions: &[Instruction],
+        tables: &[AddressLookupTableAccount],
+        blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let message =
+            v0::Message::try_compile(&self.wallet.pubkey(), instructions, tables, blockhash)
+                .map_err(|e| Error::transaction(format!("Failed to compile transaction: {}", e)))?;
+        let tx = VersionedTransaction::try_new(VersionedMessage::V0(message), &[&self.wallet])
+            .map_err(|e| Error::transaction(format!("Failed to sign transaction: {}", e)))?;
+
+        let size = bincode::serialized_size(&tx)
+            .map_err(|e| Error::transaction(format!("Failed to size transaction: {}", e)))?
+            as usize;
+        if size > PACKET_DATA_SIZE {
+            return Err(Error::transaction(format!(
+                "Transaction is {} bytes, limit is {}",
+                size, PACKET_DATA_SIZE
+            )));
+        }
+        Ok(tx)
+    }
+
+    /// Turn a compiled transaction back into instructions, along with the
+    /// lookup tables needed to compile them again. Compute budget
+    /// instructions are dropped since a transaction may only carry one set.
+    async fn decompile_instructions(
+        &self,
+        tx: &VersionedTransaction,
+    ) -> Result<(Vec<Instruction>, Vec<AddressLookupTableAccount>)> {
+        let tables = match tx.message.address_table_lookups() {
+            Some(lookups) => self.load_lookup_tables(lookups).await?,
+            None => Vec::new(),
+        };
+        let keys = self.resolve_account_keys(tx).await?;
+
+        let mut instructions = Vec::new();
+        for ix in tx.message.instructions() {
+            let program_id = keys[ix.program_id_index as usize];
+            if program_id == compute_budget::id() {
+                continue;
+            }
+            let accounts = ix
+                .accounts
+                .iter()
+                .map(|&index| {
+                    let index = index as usize;
+                    let pubkey = keys[index];
+                    if tx.message.is_maybe_writable(index, None) {
+                        AccountMeta::new(pubkey, tx.message.is_signer(index))
+                    } else {
+                        AccountMeta::new_readonly(pubkey, tx.message.is_signer(index))
+                    }
+                })
+                .collect();
+            instructions.push(Instruction {
+                program_id,
+                accounts,
+                data: ix.data.clone(),
+            });
+        }
+
+        Ok((instructions, tables))
+    }
+
+    /// Sign a swap transaction returned by an API with our wallet.
+    ///
+    /// The payload must name our wallet as fee payer. If nobody else has
+    /// signed it yet we refresh the blockhash, since API-built transactions
+    /// are often close to expiry by the time they reach us.
+    async fn sign_transaction(&self, tx: &mut VersionedTransaction) -> Result<()> {
+        let payer = self.wallet.pubkey();
+        match tx.message.static_account_keys().first() {
+            Some(fee_payer) if *fee_payer == payer => {}
+            Some(fee_payer) => {
+                return Err(Error::transaction(format!(
+                    "Swap transaction fee payer {} is not our wallet {}",
+                    fee_payer, payer
+                )))
+            }
+            None => return Err(Error::transaction("Swap transaction has no account keys")),
+        }
+
+        // Some APIs ship the transaction without signature placeholders
+        let num_signers = tx.message.header().num_required_signatures as usize;
+        tx.signatures.resize(num_signers, Signature::default());
+
+        let cosigned = tx
+            .signatures
+            .iter()
+            .zip(tx.message.static_account_keys())
+            .any(|(sig, key)| *key != payer && *sig != Signature::default());
+        if !cosigned {
+            let blockhash = self.rpc_client.get_latest_blockhash().await?;
+            tx.message.set_recent_blockhash(blockhash);
+            tx.signatures.fill(Signature::default());
+        }
+
+        // The fee payer is always the first signer
+        tx.signatures[0] = self
+            .wallet
+            .try_sign_message(&tx.message.serialize())
+            .map_err(|e| Error::transaction(format!("Failed to sign transaction: {}", e)))?;
+
+        let missing: Vec<String> = tx
+            .signatures
+            .iter()
+            .zip(tx.message.static_account_keys())
+            .filter(|(sig, _)| **sig == Signature::default())
+            .map(|(_, key)| key.to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::transaction(format!(
+                "Swap transaction is missing signatures from: {}",
+                missing.join(", ")
+            )));
+        }
+
+        tx.verify_and_hash_message().map_err(|e| {
+            Error::transaction(format!("Swap transaction signature check failed: {}", e))
+        })?;
+        Ok(())
+    }
+
+    /// List every account a transaction touches, loading any address lookup
+    /// tables referenced by a v0 message. Keys are returned in the order the
+    /// runtime sees them: static keys, then writable and readonly lookups.
+    async fn resolve_account_keys(&self, tx: &VersionedTransaction) -> Result<Vec<Pubkey>> {
+        let mut keys = tx.message.static_account_keys().to_vec();
+        let lookups = match tx.message.address_table_lookups() {
+            Some(lookups) if !lookups.is_empty() => lookups,
+            _ => return Ok(keys),
+        };
+
+        let tables = self.load_lookup_tables(lookups).await?;
+        keys.extend(lookup_table_keys(lookups, &tables)?);
+        Ok(keys)
+    }
+
+    /// Fetch the address lookup tables referenced by a v0 message
+    async fn load_lookup_tables(
+        &self,
+        lookups: &[MessageAddressTableLookup],
+    ) -> Result<Vec<AddressLookupTableAccount>> {
+        if lookups.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let table_keys: Vec<Pubkey> = lookups.iter().map(|l| l.account_key).collect();
+        let accounts = self.rpc_client.get_multiple_accounts(&table_keys).await?;
+
+        table_keys
+            .into_iter()
+            .zip(accounts)
+            .map(|(key, account)| {
+                let account = account.ok_or(Error::AccountNotFound { account: key })?;
+                let table = AddressLookupTable::deserialize(&account.data).map_err(|e| {
+                    Error::account(
+                        format!("address lookup table {}", key),
+                        e.to_string(),
+                        &account.data,
+                    )
+                })?;
+                Ok(AddressLookupTableAccount {
+                    key,
+                    addresses: table.addresses.to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    /// Token program and decimals of an SPL token mint
+    async fn mint_account(&self, mint: &Pubkey) -> Result<MintAccount> {
+        let account = self.rpc_client.get_account(mint).await?;
+        let decimals = account.data.get(44).copied().ok_or_else(|| Error::Decode {
+            what: format!("mint {}", mint),
+            reason: "too short".to_string(),
+            payload: BASE64.encode(&account.data),
+        })?;
+        Ok(MintAccount {
+            token_program: account.owner,
+            decimals,
+        })
+    }
+
+    /// Base units of `mint` in the wallet's associated token account
+    pub async fn token_balance(&self, mint: &Pubkey) -> Result<u64> {
+        let token_account = self.mint_token_account(mint).await?;
+        self.token_account_amount(&token_account).await
+    }
+
+    /// Check that `token_account` holds enough `mint` for the trade
+    async fn check_token_balance(
+        &self,
+        mint: &str,
+        token_account: &Pubkey,
+        amount: u64,
+    ) -> Result<()> {
+        let balance = self
+            .rpc_client
+            .get_token_account_balance(token_account)
+            .await?;
+
+        let have: u64 = balance
+            .amount
+            .parse()
+            .map_err(|e: ParseIntError| Error::Decode {
+                what: format!("token account {} balance", token_account),
+                reason: e.to_string(),
+                payload: balance.amount.clone(),
+            })?;
+        if have < amount {
+            return Err(Error::InsufficientBalance {
+                mint: mint.to_string(),
+                have,
+                need: amount,
+            });
         }
 
         Ok(())
     }
 
-    /// Find the associated token account for a given mint
-    fn find_token_account(&self, mint: &str) -> Result<Pubkey> {
-        let mint_pubkey = Pubkey::from_str(mint)?;
-        let owner = self.wallet.pubkey();
+    /// The wallet's associated token account for `mint`, under the token
+    /// program that owns the mint
+    async fn mint_token_account(&self, mint: &Pubkey) -> Result<Pubkey> {
+        let token_program = self.mint_account(mint).await?.token_program;
+        Ok(self.token_account(mint, &token_program))
+    }
 
-        Ok(spl_associated_token_account::get_associated_token_address(
-            &owner,
-            &mint_pubkey,
-        ))
+    /// The wallet's associated token account for `mint` under `token_program`
+    fn token_account(&self, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+        spl_associated_token_account::get_associated_token_address_with_program_id(
+            &self.wallet.pubkey(),
+            mint,
+            token_program,
+        )
     }
 
-    /// Simulate a transaction before sending
-    async fn simulate_transaction(&self, transaction: &Transaction) -> Result<()> {
-        self.rpc_client
-            .simulate_transaction(transaction)
-            .map_err(|e| anyhow!("Transaction simulation failed: {}", e))?;
+    /// Simulate a transaction before sending, and make sure it credits at
+    /// least `min_out` to `output_account`
+    async fn simulate_transaction(
+        &self,
+        transaction: &VersionedTransaction,
+        output_account: &Pubkey,
+        min_out: u64,
+    ) -> Result<()> {
+        let balance_before = self.token_account_amount(output_account).await?;
+
+        let config = RpcSimulateTransactionConfig {
+            commitment: Some(self.rpc_client.commitment()),
+            accounts: Some(RpcSimulateTransactionAccountsConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                addresses: vec![output_account.to_string()],
+            }),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = self
+            .rpc_client
+            .simulate_transaction_with_config(transaction, config)
+            .await?
+            .value;
+        let received = simulated_output(result, output_account, balance_before, min_out)?;
+        debug!(received, min_out, "Simulation output");
+
         Ok(())
     }
 
+    /// Raw token amount held by a token account, zero if it doesn't exist yet
+    async fn token_account_amount(&self, token_account: &Pubkey) -> Result<u64> {
+        let account = self
+            .rpc_client
+            .get_account_with_commitment(token_account, self.rpc_client.commitment())
+            .await?
+            .value;
+        match account {
+            Some(account) => spl_token_amount(&account.data).ok_or_else(|| Error::Decode {
+                what: format!("token account {}", token_account),
+                reason: "too short".to_string(),
+                payload: BASE64.encode(&account.data),
+            }),
+            None => Ok(0),
+        }
+    }
+
     /// Send and confirm a transaction
-    async fn send_and_confirm_transaction(&self, transaction: &Transaction) -> Result<Signature> {
+    async fn send_and_confirm_transaction(
+        &self,
+        transaction: &VersionedTransaction,
+    ) -> Result<Signature> {
         let signature = self
             .rpc_client
             .send_and_confirm_transaction(transaction)
-            .map_err(|e| anyhow!("Failed to send transaction: {}", e))?;
+            .await?;
         Ok(signature)
     }
 }
 
+/// What a simulation credited to `output_account`, which held
+/// `balance_before` going in. Fails if the simulation did, or if it paid out
+/// less than `min_out`.
+fn simulated_output(
+    result: RpcSimulateTransactionResult,
+    output_account: &Pubkey,
+    balance_before: u64,
+    min_out: u64,
+) -> Result<u64> {
+    if let Some(err) = result.err {
+        return Err(Error::Simulation {
+            message: err.to_string(),
+            logs: result.logs.unwrap_or_default(),
+        });
+    }
+
+    let balance_after = result
+        .accounts
+        .and_then(|accounts| accounts.into_iter().next().flatten())
+        .and_then(|account| account.data.decode())
+        .and_then(|data| spl_token_amount(&data))
+        .ok_or_else(|| Error::Simulation {
+            message: format!("No output account {} in the result", output_account),
+            logs: Vec::new(),
+        })?;
+
+    let received = balance_after.saturating_sub(balance_before);
+    if received < min_out {
+        return Err(Error::SlippageExceeded {
+            min_out,
+            out: received,
+        });
+    }
+    Ok(received)
+}
+
+/// The token `pool` pays out for `input_mint`
+fn other_mint(pool: &Pool, input_mint: &Pubkey) -> Result<Pubkey> {
+    pool.side(input_mint)
+        .map(|side| side.other_mint)
+        .ok_or_else(|| {
+            Error::invalid(format!(
+                "Pool {} does not trade {}",
+                pool.address, input_mint
+            ))
+        })
+}
+
+/// Compute budget instructions setting the unit limit and price
+fn compute_budget_instructions(unit_limit: u32, micro_lamports: u64) -> Vec<Instruction> {
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(unit_limit),
+        ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+    ]
+}
+
+/// The fee at `percentile` (0-100) of the recent fees, zero if there are none
+fn fee_percentile(fees: &mut [u64], percentile: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let rank = (fees.len() - 1) * percentile.min(100) as usize / 100;
+    fees[rank]
+}
+
+/// Convert a UI amount to base units
+fn ui_to_raw(amount: f64, decimals: u8) -> u64 {
+    (amount * 10f64.powi(decimals as i32)) as u64
+}
+
+/// Read the `amount` field of an SPL token account (same offset for
+/// Token and Token-2022)
+fn spl_token_amount(data: &[u8]) -> Option<u64> {
+    let bytes = data.get(64..72)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Self-transfer of `required` from `account`, closing a cycle. The token
+/// program checks the balance before short-circuiting a self-transfer, so
+/// this reverts the transaction unless the account holds at least
+/// `required`. Token and Token-2022 share the `TransferChecked` layout,
+/// which Token-2022 mints with transfer fees also insist on.
+fn profit_check_instruction(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    decimals: u8,
+    account: &Pubkey,
+    owner: &Pubkey,
+    required: u64,
+) -> Result<Instruction> {
+    if *token_program != spl_token::id() && *token_program != TOKEN_2022_PROGRAM_ID {
+        return Err(Error::unsupported(format!(
+            "Mint {} owned by {}",
+            mint, token_program
+        )));
+    }
+    let mut instruction = spl_token::instruction::transfer_checked(
+        &spl_token::id(),
+        account,
+        mint,
+        account,
+        owner,
+        &[],
+        required,
+        decimals,
+    )
+    .map_err(|e| Error::transaction(format!("Failed to build the profit check: {}", e)))?;
+    instruction.program_id = *token_program;
+    Ok(instruction)
+}
+
+/// Greedily pack consecutive legs into as few transactions as `fits`
+/// accepts, keeping each leg whole and in order
+fn pack_legs(
+    legs: Vec<Vec<Instruction>>,
+    fits: impl Fn(&[Instruction]) -> bool,
+) -> Result<Vec<Vec<Instruction>>> {
+    let mut chunks: Vec<Vec<Instruction>> = Vec::new();
+    let mut current: Vec<Instruction> = Vec::new();
+    for leg in legs {
+        let mut candidate = current.clone();
+        candidate.extend(leg.iter().cloned());
+        if fits(&candidate) {
+            current = candidate;
+            continue;
+        }
+        if current.is_empty() || !fits(&leg) {
+            return Err(Error::transaction(
+                "A single swap leg does not fit in a transaction",
+            ));
+        }
+        chunks.push(std::mem::replace(&mut current, leg));
+    }
+    chunks.push(current);
+    Ok(chunks)
+}
+
+/// Addresses a v0 message loads from `tables`: every writable lookup, then
+/// every readonly one
+fn lookup_table_keys(
+    lookups: &[MessageAddressTableLookup],
+    tables: &[AddressLookupTableAccount],
+) -> Result<Vec<Pubkey>> {
+    let mut writable = Vec::new();
+    let mut readonly = Vec::new();
+    for (lookup, table) in lookups.iter().zip(tables) {
+        let get = |index: &u8| {
+            table
+                .addresses
+                .get(*index as usize)
+                .copied()
+                .ok_or_else(|| {
+                    Error::transaction(format!(
+                        "Lookup index {} out of range for table {}",
+                        index, lookup.account_key
+                    ))
+                })
+        };
+        for index in &lookup.writable_indexes {
+            writable.push(get(index)?);
+        }
+        for index in &lookup.readonly_indexes {
+            readonly.push(get(index)?);
+        }
+    }
+    writable.extend(readonly);
+    Ok(writable)
+}
+
+/// Decode a base64 transaction from a swap API
+fn decode_transaction(swap_tx: &str) -> Result<VersionedTransaction> {
+    let decode_error = |reason: String| Error::Decode {
+        what: "swap transaction".to_string(),
+        reason,
+        payload: swap_tx.to_string(),
+    };
+    let bytes = BASE64
+        .decode(swap_tx)
+        .map_err(|e| decode_error(e.to_string()))?;
+    bincode::deserialize(&bytes).map_err(|e| decode_error(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use solana_account_decoder::{UiAccount, UiAccountData};
+    use solana_sdk::{
+        message::Message, system_instruction, transaction::Transaction,
+        transaction::TransactionError,
+    };
+
+    fn executor() -> TradeExecutor {
+        // Never contacted by these tests
+        TradeExecutor::new("http://127.0.0.1:1", Keypair::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_foreign_fee_payer() {
+        let executor = executor();
+        let stranger = Keypair::new();
+        let ix = system_instruction::transfer(&stranger.pubkey(), &executor.wallet.pubkey(), 1);
+        let mut tx: VersionedTransaction =
+            Transaction::new_unsigned(Message::new(&[ix], Some(&stranger.pubkey()))).into();
+
+        let err = executor.sign_transaction(&mut tx).await.unwrap_err();
+        assert!(err.to_string().contains("fee payer"));
+    }
+
+    #[tokio::test]
+    async fn signs_cosigned_transaction_without_refreshing_blockhash() {
+        let executor = executor();
+        let cosigner = Keypair::new();
+        let ix = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new(executor.wallet.pubkey(), true),
+                AccountMeta::new_readonly(cosigner.pubkey(), true),
+            ],
+        );
+        let message = Message::new(&[ix], Some(&executor.wallet.pubkey()));
+        let blockhash = Hash::new_unique();
+        let mut tx = Transaction::new_unsigned(message);
+        tx.partial_sign(&[&cosigner], blockhash);
+        let mut tx = VersionedTransaction::from(tx);
+
+        executor.sign_transaction(&mut tx).await.unwrap();
+        assert_eq!(*tx.message.recent_blockhash(), blockhash);
+        assert!(tx.verify_and_hash_message().is_ok());
+    }
+
+    #[tokio::test]
+    async fn signs_cosigned_v0_transaction() {
+        let executor = executor();
+        let cosigner = Keypair::new();
+        let ix = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new(executor.wallet.pubkey(), true),
+                AccountMeta::new_readonly(cosigner.pubkey(), true),
+            ],
+        );
+        let message = VersionedMessage::V0(
+            v0::Message::try_compile(&executor.wallet.pubkey(), &[ix], &[], Hash::new_unique())
+                .unwrap(),
+        );
+        let cosigner_sig = cosigner.sign_message(&message.serialize());
+        let mut tx = VersionedTransaction {
+            signatures: vec![Signature::default(), cosigner_sig],
+            message,
+        };
+
+        executor.sign_transaction(&mut tx).await.unwrap();
+        assert!(tx.verify_and_hash_message().is_ok());
+        assert_eq!(executor.resolve_account_keys(&tx).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_forged_cosigner_signature() {
+        let executor = executor();
+        let cosigner = Keypair::new();
+        let ix = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new(executor.wallet.pubkey(), true),
+                AccountMeta::new_readonly(cosigner.pubkey(), true),
+            ],
+        );
+        let mut tx: VersionedTransaction =
+            Transaction::new_unsigned(Message::new(&[ix], Some(&executor.wallet.pubkey()))).into();
+        // Pretend the API signed for the cosigner so we skip the blockhash refresh
+        tx.signatures[1] = Signature::new_unique();
+
+        let err = executor.sign_transaction(&mut tx).await.unwrap_err();
+        assert!(err.to_string().contains("signature check failed"));
+    }
+
+    #[test]
+    fn profit_check_uses_the_mints_token_program() {
+        let (mint, account, owner) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        for program in [spl_token::id(), TOKEN_2022_PROGRAM_ID] {
+            let ix = profit_check_instruction(&program, &mint, 6, &account, &owner, 1_500).unwrap();
+            assert_eq!(ix.program_id, program);
+            assert_eq!(
+                ix.accounts,
+                vec![
+                    AccountMeta::new(account, false),
+                    AccountMeta::new_readonly(mint, false),
+                    AccountMeta::new(account, false),
+                    AccountMeta::new_readonly(owner, true),
+                ]
+            );
+            // TransferChecked: tag, amount, decimals
+            let mut data = vec![12];
+            data.extend_from_slice(&1_500u64.to_le_bytes());
+            data.push(6);
+            assert_eq!(ix.data, data);
+        }
+
+        let error = profit_check_instruction(&Pubkey::new_unique(), &mint, 6, &account, &owner, 1)
+            .unwrap_err();
+        assert!(matches!(error, Error::Unsupported { .. }));
+
+        // Token-2022 balances live in a different associated account
+        let executor = executor();
+        assert_ne!(
+            executor.token_account(&mint, &TOKEN_2022_PROGRAM_ID),
+            executor.token_account(&mint, &spl_token::id())
+        );
+    }
+
+    #[test]
+    fn packs_whole_legs_in_order() {
+        let program = Pubkey::new_unique();
+        let leg = |size: usize| vec![Instruction::new_with_bytes(program, &vec![0; size], vec![])];
+        let sizes =
+            |chunk: &[Instruction]| chunk.iter().map(|ix| ix.data.len()).collect::<Vec<_>>();
+        let fits =
+            |chunk: &[Instruction]| chunk.iter().map(|ix| ix.data.len()).sum::<usize>() <= 10;
+
+        let chunks = pack_legs(vec![leg(4), leg(4), leg(4), leg(8), leg(2)], fits).unwrap();
+        let chunks: Vec<_> = chunks.iter().map(|chunk| sizes(chunk)).collect();
+        assert_eq!(chunks, vec![vec![4, 4], vec![4], vec![8, 2]]);
+
+        let error = pack_legs(vec![leg(4), leg(11)], fits).unwrap_err();
+        assert!(matches!(error, Error::Transaction { .. }));
+
+        // Two legs that only fit a packet on their own
+        let executor = executor();
+        let fits = |chunk: &[Instruction]| {
+            executor
+                .compile_transaction(chunk, &[], Hash::new_unique())
+                .is_ok()
+        };
+        assert_eq!(pack_legs(vec![leg(700), leg(700)], fits).unwrap().len(), 2);
+        assert_eq!(pack_legs(vec![leg(300), leg(300)], fits).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn resolves_lookup_table_keys_in_runtime_order() {
+        let executor = executor();
+        let addresses: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: addresses.clone(),
+        };
+        let ix = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new_readonly(addresses[0], false),
+                AccountMeta::new(addresses[2], false),
+                AccountMeta::new_readonly(addresses[3], false),
+                AccountMeta::new(addresses[1], false),
+            ],
+        );
+        let tx = executor
+            .compile_transaction(
+                std::slice::from_ref(&ix),
+                std::slice::from_ref(&table),
+                Hash::new_unique(),
+            )
+            .unwrap();
+        let lookups = tx.message.address_table_lookups().unwrap();
+
+        let mut keys = tx.message.static_account_keys().to_vec();
+        keys.extend(lookup_table_keys(lookups, std::slice::from_ref(&table)).unwrap());
+        assert_eq!(keys.len(), 2 + addresses.len());
+        let compiled = &tx.message.instructions()[0];
+        let accounts: Vec<AccountMeta> = compiled
+            .accounts
+            .iter()
+            .map(|&index| {
+                let index = index as usize;
+                if tx.message.is_maybe_writable(index, None) {
+                    AccountMeta::new(keys[index], false)
+                } else {
+                    AccountMeta::new_readonly(keys[index], false)
+                }
+            })
+            .collect();
+        assert_eq!(accounts, ix.accounts);
+
+        let truncated = AddressLookupTableAccount {
+            addresses: addresses[..1].to_vec(),
+            ..table
+        };
+        let error = lookup_table_keys(lookups, &[truncated]).unwrap_err();
+        assert!(matches!(error, Error::Transaction { .. }));
+    }
+
+    /// A simulation that ends with `amount` in the output token account
+    fn simulation(amount: u64) -> RpcSimulateTransactionResult {
+        let mut data = vec![0; 165];
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        RpcSimulateTransactionResult {
+            err: None,
+            logs: Some(Vec::new()),
+            accounts: Some(vec![Some(UiAccount {
+                lamports: 2_039_280,
+                data: UiAccountData::Binary(BASE64.encode(data), UiAccountEncoding::Base64),
+                owner: spl_token::id().to_string(),
+                executable: false,
+                rent_epoch: 0,
+                space: Some(165),
+            })]),
+            units_consumed: Some(50_000),
+            return_data: None,
+            inner_instructions: None,
+            replacement_blockhash: None,
+        }
+    }
+
+    #[test]
+    fn checks_simulated_output_against_the_minimum() {
+        let account = Pubkey::new_unique();
+        assert_eq!(
+            simulated_output(simulation(1_900), &account, 1_000, 900).unwrap(),
+            900
+        );
+
+        let error = simulated_output(simulation(1_899), &account, 1_000, 900).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::SlippageExceeded {
+                min_out: 900,
+                out: 899
+            }
+        ));
+
+        let failed = RpcSimulateTransactionResult {
+            err: Some(TransactionError::AccountNotFound),
+            ..simulation(1_900)
+        };
+        let error = simulated_output(failed, &account, 1_000, 900).unwrap_err();
+        assert!(matches!(error, Error::Simulation { .. }));
+    }
+
+    /// A venue that can't build swaps on any of its pools
+    struct Unbuildable;
+
+    #[async_trait::async_trait]
+    impl Venue for Unbuildable {
+        fn name(&self) -> &'static str {
+            "Test"
+        }
+
+        async fn discover_pools(&self, _mint: &Pubkey) -> Result<Vec<Pool>> {
+            Ok(Vec::new())
+        }
+
+        async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+            Ok(pool.clone())
+        }
+
+        async fn quote(
+            &self,
+            _pool: &Pool,
+            _input_mint: &Pubkey,
+            _amount_in: u64,
+        ) -> Result<crate::common::SwapQuote> {
+            Err(Error::unsupported("Quoting on the test venue"))
+        }
+
+        async fn swap_instructions(
+            &self,
+            _pool: &Pool,
+            _owner: &Pubkey,
+            _input_mint: &Pubkey,
+            _amount_in: u64,
+            _slippage_bps: u64,
+        ) -> Result<SwapInstructions> {
+            Err(Error::unsupported("Swapping on the test venue"))
+        }
+    }
 
     #[tokio::test]
-    async fn test_trade_executor() {
-        // TODO: Add tests
+    async fn routes_unsupported_swaps_through_jupiter_only_when_enabled() {
+        let venues: Vec<Box<dyn Venue>> = vec![Box::new(Unbuildable)];
+        let mut pool = Pool {
+            address: Pubkey::new_unique(),
+            source: "Test",
+            pool_type: "CPMM".to_string(),
+            program_id: Pubkey::new_unique(),
+            mints: [Pubkey::new_unique(), Pubkey::new_unique()],
+            decimals: [6, 6],
+            reserves: [1_000_000, 1_000_000],
+            price: 1.0,
+            fees: crate::venue::Fees::default(),
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        };
+        let input = pool.mints[0];
+
+        let executor = executor();
+        let error = executor
+            .direct_swap(&venues, &pool, &input, 1_000, 50)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Unsupported { .. }));
+
+        let executor = executor.with_jupiter_routing(JupiterRouting::default());
+        let swap = executor.direct_swap(&venues, &pool, &input, 1_000, 50);
+        assert!(swap.await.unwrap().is_none());
+        // Pools from a venue we weren't given go the same way
+        pool.source = "Elsewhere";
+        let swap = executor.direct_swap(&venues, &pool, &input, 1_000, 50);
+        assert!(swap.await.unwrap().is_none());
+    }
+
+    #[test]
+    fn fee_percentile_picks_rank() {
+        let mut fees = vec![50, 10, 40, 20, 30];
+        assert_eq!(fee_percentile(&mut fees, 0), 10);
+        assert_eq!(fee_percentile(&mut fees, 50), 30);
+        assert_eq!(fee_percentile(&mut fees, 75), 40);
+        assert_eq!(fee_percentile(&mut fees, 100), 50);
+        assert_eq!(fee_percentile(&mut [], 75), 0);
     }
 }
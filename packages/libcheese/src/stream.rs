@@ -0,0 +1,272 @@
+//! Live pool state over account websocket subscriptions.
+//!
+//! `PoolStream` subscribes to every account a swap through one of its pools
+//! writes to, and re-reads a pool through its venue as soon as one of them
+//! changes. The refreshed pools stay cached, so a caller can re-run its
+//! search on every update instead of re-listing pools on a timer.
+//!
+//! Only pools passed to `PoolStream::subscribe` are followed. There is no
+//! `programSubscribe` watching for new ones, so a pool created after
+//! discovery shows up only once the caller lists pools again, as the CLI
+//! does at the end of each `--stream` window.
+
+use crate::error::Result;
+use crate::venue::{Pool, Venue};
+use futures::stream::{self, StreamExt};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// A pool re-read after one of its accounts changed
+#[derive(Debug, Clone)]
+pub struct PoolUpdate {
+    pub pool: Pool,
+    /// Slot of the latest change folded into this update
+    pub slot: u64,
+}
+
+/// Pools kept current from websocket notifications
+pub struct PoolStream<'a> {
+    venues: &'a [Box<dyn Venue + 'a>],
+    pools: Vec<Pool>,
+    /// Pools, by index, that each watched account belongs to
+    watchers: HashMap<Pubkey, Vec<usize>>,
+    changes: mpsc::UnboundedReceiver<(Pubkey, u64)>,
+    subscriptions: Option<JoinHandle<()>>,
+}
+
+impl<'a> PoolStream<'a> {
+    /// Subscribe to the accounts behind `pools` on the websocket endpoint
+    /// `ws_url`. A pool whose venue can't name its accounts is still cached
+    /// but never updated.
+    pub async fn subscribe(
+        ws_url: &str,
+        commitment: CommitmentConfig,
+        venues: &'a [Box<dyn Venue + 'a>],
+        pools: Vec<Pool>,
+    ) -> Result<Self> {
+        let mut watchers: HashMap<Pubkey, Vec<usize>> = HashMap::new();
+        for (index, pool) in pools.iter().enumerate() {
+            let Some(venue) = venues.iter().find(|venue| venue.name() == pool.source) else {
+                continue;
+            };
+            match venue.watch_accounts(pool).await {
+                Ok(accounts) => {
+                    for account in accounts {
+                        watchers.entry(account).or_default().push(index);
+                    }
+                }
+                Err(e) => warn!(
+                    venue = pool.source,
+                    pool = %pool.address,
+                    error = %e,
+                    "Not streaming pool"
+                ),
+            }
+        }
+
+        let client = PubsubClient::new(ws_url).await?;
+        let accounts: Vec<Pubkey> = watchers.keys().copied().collect();
+        let (sender, changes) = mpsc::unbounded_channel();
+        let subscriptions = tokio::spawn(async move {
+            let config = RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(commitment),
+                ..RpcAccountInfoConfig::default()
+            };
+            let mut streams = Vec::new();
+            for account in accounts {
+                match client
+                    .account_subscribe(&account, Some(config.clone()))
+                    .await
+                {
+                    Ok((notifications, _unsubscribe)) => streams
+                        .push(notifications.map(move |response| (account, response.context.slot))),
+                    Err(e) => warn!(%account, error = %e, "Failed to subscribe to account"),
+                }
+            }
+            let mut notifications = stream::select_all(streams);
+            while let Some(change) = notifications.next().await {
+                if sender.send(change).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self::from_changes(
+            venues,
+            pools,
+            watchers,
+            changes,
+            Some(subscriptions),
+        ))
+    }
+
+    fn from_changes(
+        venues: &'a [Box<dyn Venue + 'a>],
+        pools: Vec<Pool>,
+        watchers: HashMap<Pubkey, Vec<usize>>,
+        changes: mpsc::UnboundedReceiver<(Pubkey, u64)>,
+        subscriptions: Option<JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            venues,
+            pools,
+            watchers,
+            changes,
+            subscriptions,
+        }
+    }
+
+    /// Every pool as of its latest update, in the order subscribed
+    pub fn pools(&self) -> &[Pool] {
+        &self.pools
+    }
+
+    /// Wait for the next account change and re-read the pools it touches.
+    /// Changes already queued are folded in, so a burst of swaps costs one
+    /// refresh per pool. `None` once the websocket has closed.
+    pub async fn next(&mut self) -> Option<Vec<PoolUpdate>> {
+        let mut changed: HashMap<usize, u64> = HashMap::new();
+        let mut change = Some(self.changes.recv().await?);
+        while let Some((account, slot)) = change {
+            for &index in self.watchers.get(&account).into_iter().flatten() {
+                let latest = changed.entry(index).or_default();
+                *latest = (*latest).max(slot);
+            }
+            change = self.changes.try_recv().ok();
+        }
+
+        let mut updates = Vec::new();
+        for (index, slot) in changed {
+            let pool = &self.pools[index];
+            let Some(venue) = self.venues.iter().find(|venue| venue.name() == pool.source) else {
+                continue;
+            };
+            match venue.refresh(pool).await {
+                Ok(pool) => {
+                    debug!(venue = pool.source, pool = %pool.address, slot, "Pool refreshed");
+                    self.pools[index] = pool.clone();
+                    updates.push(PoolUpdate { pool, slot });
+                }
+                Err(e) => warn!(
+                    venue = pool.source,
+                    pool = %pool.address,
+                    error = %e,
+                    "Failed to refresh pool"
+                ),
+            }
+        }
+        Some(updates)
+    }
+}
+
+impl Drop for PoolStream<'_> {
+    fn drop(&mut self) {
+        if let Some(subscriptions) = &self.subscriptions {
+            subscriptions.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{SwapInstructions, SwapQuote};
+    use crate::error::Error;
+    use crate::venue::Fees;
+    use async_trait::async_trait;
+
+    /// Refreshes every pool by doubling its first reserve
+    struct Growing;
+
+    #[async_trait]
+    impl Venue for Growing {
+        fn name(&self) -> &'static str {
+            "Test"
+        }
+
+        async fn discover_pools(&self, _mint: &Pubkey) -> Result<Vec<Pool>> {
+            Ok(Vec::new())
+        }
+
+        async fn refresh(&self, pool: &Pool) -> Result<Pool> {
+            let mut refreshed = pool.clone();
+            refreshed.reserves[0] *= 2;
+            Ok(refreshed)
+        }
+
+        async fn quote(
+            &self,
+            _pool: &Pool,
+            _input_mint: &Pubkey,
+            _amount_in: u64,
+        ) -> Result<SwapQuote> {
+            Err(Error::unsupported("Quoting on the test venue"))
+        }
+
+        async fn swap_instructions(
+            &self,
+            _pool: &Pool,
+            _owner: &Pubkey,
+            _input_mint: &Pubkey,
+            _amount_in: u64,
+            _slippage_bps: u64,
+        ) -> Result<SwapInstructions> {
+            Err(Error::unsupported("Swapping on the test venue"))
+        }
+    }
+
+    fn pool() -> Pool {
+        Pool {
+            address: Pubkey::new_unique(),
+            source: "Test",
+            pool_type: "CPMM".to_string(),
+            program_id: Pubkey::new_unique(),
+            mints: [Pubkey::new_unique(), Pubkey::new_unique()],
+            decimals: [6, 6],
+            reserves: [1_000, 1_000],
+            price: 1.0,
+            fees: Fees::default(),
+            symbols: [None, None],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn folds_queued_changes_into_one_refresh_per_pool() {
+        let venues: Vec<Box<dyn Venue>> = vec![Box::new(Growing)];
+        let pools = vec![pool(), pool()];
+        let (vault_a, vault_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let watchers = HashMap::from([
+            (pools[0].address, vec![0]),
+            (vault_a, vec![0]),
+            (vault_b, vec![1]),
+        ]);
+        let (sender, changes) = mpsc::unbounded_channel();
+        let mut stream = PoolStream::from_changes(&venues, pools, watchers, changes, None);
+
+        sender.send((vault_a, 10)).unwrap();
+        sender.send((stream.pools()[0].address, 12)).unwrap();
+        sender.send((Pubkey::new_unique(), 13)).unwrap();
+        let updates = stream.next().await.unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].slot, 12);
+        assert_eq!(stream.pools()[0].reserves, [2_000, 1_000]);
+        assert_eq!(stream.pools()[1].reserves, [1_000, 1_000]);
+
+        sender.send((vault_b, 14)).unwrap();
+        drop(sender);
+        let updates = stream.next().await.unwrap();
+        assert_eq!(updates[0].pool.address, stream.pools()[1].address);
+        assert!(stream.next().await.is_none());
+    }
+}
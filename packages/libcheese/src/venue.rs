@@ -0,0 +1,169 @@
+//! One interface over every DEX we trade on.
+//!
+//! Each venue finds its pools for a mint and normalizes them into `Pool`,
+//! so the scanner can treat pools from every source as one list. Quotes
+//! and swap instructions are built from on-chain state, never a hosted API.
+
+use crate::common::{SwapInstructions, SwapQuote};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+
+#[async_trait]
+pub trait Venue: Send + Sync {
+    /// Name recorded in `Pool::source` and `TradeStep::source`
+    fn name(&self) -> &'static str;
+
+    /// Every pool on this venue that trades `mint`
+    async fn discover_pools(&self, mint: &Pubkey) -> Result<Vec<Pool>>;
+
+    /// Re-read a pool's reserves and price from chain
+    async fn refresh(&self, pool: &Pool) -> Result<Pool>;
+
+    /// Accounts a swap through `pool` writes its new reserves or price to,
+    /// for following the pool live. Most programs keep both in the pool
+    /// account itself.
+    async fn watch_accounts(&self, pool: &Pool) -> Result<Vec<Pubkey>> {
+        Ok(vec![pool.address])
+    }
+
+    /// Exact quote for selling `amount_in` base units of `input_mint`
+    async fn quote(&self, pool: &Pool, input_mint: &Pubkey, amount_in: u64) -> Result<SwapQuote>;
+
+    /// Instructions selling exactly `amount_in` of `input_mint` for the
+    /// pool's other token, accepting `slippage_bps` less than quoted. Any of
+    /// `owner`'s token accounts the swap needs are created first. Pools the
+    /// venue can't build swaps for return `Error::Unsupported`.
+    async fn swap_instructions(
+        &self,
+        pool: &Pool,
+        owner: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        slippage_bps: u64,
+    ) -> Result<SwapInstructions>;
+}
+
+/// The venue `pool` was found on
+pub(crate) fn venue_for<'v>(
+    venues: &'v [Box<dyn Venue + '_>],
+    pool: &Pool,
+) -> Result<&'v dyn Venue> {
+    venues
+        .iter()
+        .find(|venue| venue.name() == pool.source)
+        .map(|venue| venue.as_ref() as &dyn Venue)
+        .ok_or_else(|| Error::unsupported(format!("{} pool {}", pool.source, pool.address)))
+}
+
+// -----------------------------------
+// Normalized Pools
+// -----------------------------------
+
+/// A two-token pool from any venue
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub address: Pubkey,
+    /// Name of the venue the pool was found on
+    pub source: &'static str,
+    /// The venue's own name for the pool type, e.g. "CPMM" or "DLMM"
+    pub pool_type: String,
+    pub program_id: Pubkey,
+    pub mints: [Pubkey; 2],
+    pub decimals: [u8; 2],
+    /// Base units of each token the pool trades against
+    pub reserves: [u64; 2],
+    /// Whole units of `mints[1]` one whole `mints[0]` buys at the current
+    /// price, before fees
+    pub price: f64,
+    pub fees: Fees,
+    /// Token symbols, when the venue reports them
+    pub symbols: [Option<String>; 2],
+    /// Liquidity and 24h volume in USD, when the venue reports them
+    pub tvl_usd: Option<f64>,
+    pub volume_24h_usd: Option<f64>,
+    /// The venue prices this pool off another one rather than its reserves
+    pub derived: bool,
+}
+
+/// What a pool charges per swap
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fees {
+    /// Trade fee as a fraction of the input, at its lowest
+    pub trade_fee: f64,
+    /// Share of the trade fee kept by the protocol rather than LPs
+    pub protocol_share: f64,
+    /// Volatility can push the fee above `trade_fee`
+    pub dynamic: bool,
+}
+
+/// A pool seen from one of its tokens, in UI units
+#[derive(Debug, Clone)]
+pub struct PoolSide {
+    pub other_mint: Pubkey,
+    pub other_symbol: Option<String>,
+    pub qty: f64,
+    pub other_qty: f64,
+    /// Other token one whole token buys at the current price
+    pub other_per_token: f64,
+}
+
+impl Pool {
+    /// Position of `mint` in `mints`
+    pub fn index_of(&self, mint: &Pubkey) -> Option<usize> {
+        self.mints.iter().position(|m| m == mint)
+    }
+
+    /// Reserve of `mints[index]` in whole tokens
+    pub fn ui_reserve(&self, index: usize) -> f64 {
+        self.reserves[index] as f64 / 10f64.powi(self.decimals[index] as i32)
+    }
+
+    /// The pool from `mint`'s side, or `None` if it doesn't trade `mint`
+    pub fn side(&self, mint: &Pubkey) -> Option<PoolSide> {
+        let index = self.index_of(mint)?;
+        let other = 1 - index;
+        Some(PoolSide {
+            other_mint: self.mints[other],
+            other_symbol: self.symbols[other].clone(),
+            qty: self.ui_reserve(index),
+            other_qty: self.ui_reserve(other),
+            other_per_token: if index == 0 {
+                self.price
+            } else {
+                1.0 / self.price
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sides_flip_price_and_reserves() {
+        let pool = Pool {
+            address: Pubkey::new_unique(),
+            source: "Test",
+            pool_type: "CPMM".to_string(),
+            program_id: Pubkey::new_unique(),
+            mints: [Pubkey::new_unique(), Pubkey::new_unique()],
+            decimals: [6, 9],
+            reserves: [2_000_000, 5_000_000_000],
+            price: 2.5,
+            fees: Fees::default(),
+            symbols: [Some("A".to_string()), Some("B".to_string())],
+            tvl_usd: None,
+            volume_24h_usd: None,
+            derived: false,
+        };
+
+        let side = pool.side(&pool.mints[1]).unwrap();
+        assert_eq!(side.other_mint, pool.mints[0]);
+        assert_eq!(side.other_symbol.as_deref(), Some("A"));
+        assert_eq!((side.qty, side.other_qty), (5.0, 2.0));
+        assert_eq!(side.other_per_token, 0.4);
+        assert!(pool.side(&Pubkey::new_unique()).is_none());
+    }
+}